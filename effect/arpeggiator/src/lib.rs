@@ -0,0 +1,557 @@
+// lib.rs
+
+#[macro_use] extern crate vst;
+extern crate vstutils;
+
+mod pattern;
+
+use std::mem;
+
+use vst::api::{Events, Supported};
+use vst::buffer::AudioBuffer;
+use vst::event::{Event, MidiEvent};
+use vst::plugin::{Category, CanDo, HostCallback, Info, Plugin};
+
+use vstutils::division;
+use vstutils::division::Division;
+use vstutils::notetracker::NoteTracker;
+use vstutils::param::{bool_to_name, bool_to_param, choice_to_param, param_to_bool,
+                      param_to_choice};
+use vstutils::random::Random;
+use vstutils::sendbuffer::SendBuffer;
+use vstutils::transport::Transport;
+
+use pattern::Order;
+
+struct Arpeggiator {
+    host:           HostCallback,
+    send_buffer:    SendBuffer,
+    sample_rate:    f32,
+    order:          Order,
+    octaves:        u8,
+    division_param: f32,
+    division:       Division,
+    gate:           f32,
+    swing:          f32,
+    latch:          bool,
+    held:           NoteTracker,
+    pattern:        NoteTracker,
+    // Room to sort the pattern's notes in, so that building the sequence
+    // doesn't allocate.
+    pattern_notes:  Vec<u8>,
+    velocities:     [u8; NUM_NOTES],
+    channel:        u8,
+    sequence:       Vec<u8>,
+    incoming:       Vec<MidiEvent>,
+    outgoing:       Vec<MidiEvent>,
+    synced:         bool,
+    step_length:    f64,
+    position:       f64,
+    step:           Option<i64>,
+    count:          usize,
+    // The sounding note, with the channel it was sent on.
+    playing_note:   Option<(u8, u8)>,
+    note_end:       f64,
+    random:         Random,
+}
+
+const NUM_NOTES: usize = 128;
+const MAX_HELD_NOTES: usize = 32;
+const MAX_OCTAVES: u8 = 4;
+
+// Notes are never shorter than this fraction of a step, so that they're
+// still heard.
+const MIN_GATE: f32 = 0.05;
+
+// The most events which can be sent to the host in one block.
+const MAX_EVENTS: usize = 1024;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+
+fn get_midi_event(data: [u8; 3], delta_frames: i32) -> MidiEvent {
+    MidiEvent {
+        data,
+        delta_frames,
+        live:              false,
+        note_length:       None,
+        note_offset:       None,
+        detune:            0,
+        note_off_velocity: 0,
+    }
+}
+
+impl Arpeggiator {
+    /// Returns the step at a song position, along with its start and length
+    /// in quarter notes. Swing delays every second step, taking the time
+    /// from the step after it.
+    fn get_step(&self, position: f64) -> (i64, f64, f64) {
+        let pair_length = 2.0 * self.step_length;
+        let pair        = (position / pair_length).floor();
+        let pair_start  = pair * pair_length;
+        let delay       = f64::from(self.swing) * self.step_length / 2.0;
+
+        if position - pair_start < self.step_length + delay {
+            (pair as i64 * 2, pair_start, self.step_length + delay)
+        }
+        else {
+            (pair as i64 * 2 + 1, pair_start + self.step_length + delay, self.step_length - delay)
+        }
+    }
+
+    fn send(&mut self, data: [u8; 3], sample: usize) {
+        if self.outgoing.len() < MAX_EVENTS {
+            self.outgoing.push(get_midi_event(data, sample as i32));
+        }
+    }
+
+    fn start_note(&mut self, note: u8, sample: usize) {
+        let velocity = self.velocities[note as usize];
+        self.send([NOTE_ON | self.channel, note, velocity], sample);
+        self.playing_note = Some((note, self.channel));
+    }
+
+    /// Ends the sounding note on the channel it was started on, which a new
+    /// chord may have moved the arpeggio away from since.
+    fn stop_note(&mut self, sample: usize) {
+        if let Some((note, channel)) = self.playing_note.take() {
+            self.send([NOTE_OFF | channel, note, 0], sample);
+        }
+    }
+
+    fn send_outgoing(&mut self) {
+        self.send_buffer.send(&self.outgoing, &mut self.host);
+        self.outgoing.clear();
+    }
+
+    fn next_note(&mut self) -> u8 {
+        let index = match self.order {
+            Order::Random => self.random.next_u32() as usize % self.sequence.len(),
+            _             => self.count % self.sequence.len(),
+        };
+        self.count += 1;
+
+        self.sequence[index]
+    }
+
+    fn update_sequence(&mut self) {
+        self.pattern_notes.clear();
+        self.pattern_notes.extend(self.pattern.iter_playing_notes());
+        pattern::build(self.order, &mut self.pattern_notes, self.octaves, &mut self.sequence);
+    }
+
+    fn note_on(&mut self, note: u8, velocity: u8, channel: u8) {
+        // With latch on, the pattern is kept after the keys are let go,
+        // until a new chord is started.
+        if self.latch && self.held.is_empty() {
+            self.pattern.clear();
+        }
+
+        let starting = self.sequence.is_empty();
+
+        self.held.note_on(note);
+        if !self.pattern.is_playing(note) {
+            self.pattern.note_on(note);
+        }
+        self.velocities[note as usize] = velocity;
+        self.channel = channel;
+        self.update_sequence();
+
+        // A new pattern starts from its first note. In time with the host
+        // it waits for the next step, and otherwise it starts straight away.
+        if starting {
+            self.count = 0;
+
+            if self.synced {
+                self.step = Some(self.get_step(self.position).0);
+            }
+            else {
+                self.position = 0.0;
+                self.step     = None;
+            }
+        }
+    }
+
+    fn note_off(&mut self, note: u8) {
+        self.held.note_off(note);
+
+        if !self.latch {
+            self.pattern.note_off(note);
+            self.update_sequence();
+        }
+    }
+
+    fn process_midi_event(&mut self, event: MidiEvent, sample: usize) {
+        let data = event.data;
+        let note = data[1] & 0x7f;
+
+        match data[0] & 0xf0 {
+            NOTE_ON if data[2] > 0 => self.note_on(note, data[2], data[0] & 0x0f),
+            NOTE_ON | NOTE_OFF     => self.note_off(note),
+            // Everything else passes straight through.
+            _                      => self.send(data, sample),
+        }
+    }
+
+    /// Queues an event for the next block. The queue is kept in time order,
+    /// with events at the same time in the order they arrived, so that it
+    /// never has to be sorted.
+    fn queue_event(&mut self, event: MidiEvent) {
+        if self.incoming.len() < MAX_EVENTS {
+            let position = self.incoming
+                               .partition_point(|queued| queued.delta_frames <= event.delta_frames);
+            self.incoming.insert(position, event);
+        }
+    }
+
+    /// Moves the arpeggio on to the current song position, ending and
+    /// starting notes as needed.
+    fn update(&mut self, sample: usize) {
+        if self.playing_note.is_some()
+           && (self.position >= self.note_end || self.sequence.is_empty()) {
+            self.stop_note(sample);
+        }
+
+        let (step, start, length) = self.get_step(self.position);
+
+        if self.step != Some(step) {
+            self.step = Some(step);
+            self.stop_note(sample);
+
+            if !self.sequence.is_empty() {
+                let note = self.next_note();
+                self.start_note(note, sample);
+                self.note_end = start + length * f64::from(self.gate);
+            }
+        }
+    }
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Arpeggiator {
+        Arpeggiator::new(Default::default())
+    }
+}
+
+impl Plugin for Arpeggiator {
+    fn new(host: HostCallback) -> Arpeggiator {
+        // Step in sixteenth notes by default.
+        let division_param = 0.9;
+
+        Arpeggiator {
+            host,
+            send_buffer:    SendBuffer::new(MAX_EVENTS),
+            sample_rate:    44100.0,
+            order:          Order::Up,
+            octaves:        1,
+            division_param,
+            division:       division::get_division(division_param),
+            gate:           0.5,
+            swing:          0.0,
+            latch:          false,
+            held:           NoteTracker::new(MAX_HELD_NOTES, 0),
+            pattern:        NoteTracker::new(MAX_HELD_NOTES, 0),
+            pattern_notes:  Vec::with_capacity(MAX_HELD_NOTES),
+            velocities:     [0; NUM_NOTES],
+            channel:        0,
+            sequence:       Vec::with_capacity(MAX_HELD_NOTES * MAX_OCTAVES as usize * 2),
+            incoming:       Vec::with_capacity(MAX_EVENTS),
+            outgoing:       Vec::with_capacity(MAX_EVENTS),
+            synced:         false,
+            step_length:    0.25,
+            position:       0.0,
+            step:           None,
+            count:          0,
+            playing_note:   None,
+            note_end:       0.0,
+            random:         Random::default(),
+        }
+    }
+
+    fn get_info(&self) -> Info {
+        Info {
+            name:         "Arpeggiator".to_string(),
+            vendor:       "johnelse".to_string(),
+            unique_id:    21102026,
+
+            inputs:       2,
+            outputs:      2,
+            midi_inputs:  1,
+            midi_outputs: 1,
+            parameters:   6,
+
+            category:     Category::Effect,
+
+            // fill in the rest with the default values
+            ..Info::default()
+        }
+    }
+
+    fn suspend(&mut self) {
+        // Nothing will end the sounding note once processing stops.
+        self.stop_note(0);
+        self.send_outgoing();
+        self.step = None;
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        match can_do {
+            CanDo::ReceiveEvents    => Supported::Yes,
+            CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::ReceiveTimeInfo  => Supported::Yes,
+            CanDo::SendEvents       => Supported::Yes,
+            CanDo::SendMidiEvent    => Supported::Yes,
+            _                       => Supported::Maybe,
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        match index {
+            0 => pattern::get_param(self.order),
+            1 => self.division_param,
+            2 => choice_to_param((self.octaves - 1) as usize, MAX_OCTAVES as usize),
+            3 => (self.gate - MIN_GATE) / (1.0 - MIN_GATE),
+            4 => self.swing,
+            5 => bool_to_param(self.latch),
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: i32, value: f32) {
+        match index {
+            0 => {
+                self.order = pattern::get_order(value);
+                self.update_sequence();
+            },
+            1 => {
+                self.division_param = value;
+                self.division       = division::get_division(self.division_param)
+            },
+            2 => {
+                self.octaves = param_to_choice(value, MAX_OCTAVES as usize) as u8 + 1;
+                self.update_sequence();
+            },
+            3 => self.gate = MIN_GATE + value * (1.0 - MIN_GATE),
+            4 => self.swing = value,
+            5 => {
+                self.latch = param_to_bool(value);
+
+                // Drop any latched notes which aren't still held.
+                if !self.latch {
+                    self.pattern.clear();
+                    for note in self.held.iter_playing_notes() {
+                        self.pattern.note_on(note);
+                    }
+                    self.update_sequence();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "Order".to_string(),
+            1 => "Division".to_string(),
+            2 => "Octaves".to_string(),
+            3 => "Gate".to_string(),
+            4 => "Swing".to_string(),
+            5 => "Latch".to_string(),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            0 => pattern::get_name(self.order),
+            1 => division::get_name(self.division),
+            2 => format!("{}", self.octaves),
+            3 => format!("{:.0}", self.gate * 100.0),
+            // Shown the usual way, as how far through each pair of steps the
+            // second one starts.
+            4 => format!("{:.0}", 50.0 + self.swing * 25.0),
+            5 => bool_to_name(self.latch),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            3 | 4 => "%".to_string(),
+            _     => "".to_string(),
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let samples = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+
+        // Audio passes through untouched.
+        for (input, output) in inputs.into_iter().zip(outputs) {
+            output.copy_from_slice(input);
+        }
+
+        // Follow the host's song position while it's playing, and keep time
+        // on our own otherwise.
+        let transport      = Transport::from_host(&self.host);
+        let ppq_per_sample = transport.get_ppq_per_sample(self.sample_rate);
+        self.step_length   = f64::from(division::get_length(self.division, transport.time_sig));
+        self.synced        = transport.playing && transport.ppq_pos.is_some();
+
+        if let Some(ppq_pos) = transport.ppq_pos.filter(|_| transport.playing) {
+            self.position = ppq_pos;
+        }
+
+        let incoming   = mem::take(&mut self.incoming);
+        let mut events = incoming.iter().peekable();
+
+        for sample in 0..samples {
+            while let Some(event) = events.next_if(|event| event.delta_frames <= sample as i32) {
+                self.process_midi_event(*event, sample);
+            }
+
+            self.update(sample);
+            self.position += ppq_per_sample;
+        }
+
+        // Anything timed past the end of the block happens at its end.
+        for event in events {
+            self.process_midi_event(*event, samples.saturating_sub(1));
+        }
+
+        self.incoming = incoming;
+        self.incoming.clear();
+
+        self.send_outgoing();
+    }
+
+    fn process_events(&mut self, events: &Events) {
+        for event in events.events() {
+            if let Event::Midi(ev) = event {
+                self.queue_event(ev);
+            }
+        }
+    }
+}
+
+plugin_main!(Arpeggiator);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f64, second: f64) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    fn get_sent(arpeggiator: &mut Arpeggiator) -> Vec<[u8; 3]> {
+        arpeggiator.outgoing.drain(..).map(|event| event.data).collect()
+    }
+
+    #[test]
+    fn test_swing()
+    {
+        let mut arpeggiator = Arpeggiator::default();
+
+        let (step, start, length) = arpeggiator.get_step(0.3);
+        assert_eq!(step, 1);
+        assert!(floats_equal(start, 0.25));
+        assert!(floats_equal(length, 0.25));
+
+        // Full swing moves every second step halfway through its length, so
+        // the pair still takes two steps.
+        arpeggiator.swing = 1.0;
+
+        let (step, start, length) = arpeggiator.get_step(0.3);
+        assert_eq!(step, 0);
+        assert!(floats_equal(start, 0.0));
+        assert!(floats_equal(length, 0.375));
+
+        let (step, start, length) = arpeggiator.get_step(0.4);
+        assert_eq!(step, 1);
+        assert!(floats_equal(start, 0.375));
+        assert!(floats_equal(length, 0.125));
+
+        let (step, start, length) = arpeggiator.get_step(0.5);
+        assert_eq!(step, 2);
+        assert!(floats_equal(start, 0.5));
+        assert!(floats_equal(length, 0.375));
+    }
+
+    #[test]
+    fn test_gate()
+    {
+        let mut arpeggiator = Arpeggiator {
+            gate:  0.5,
+            swing: 1.0,
+            ..Arpeggiator::default()
+        };
+
+        arpeggiator.note_on(60, 100, 0);
+        arpeggiator.update(0);
+        assert_eq!(get_sent(&mut arpeggiator), [[NOTE_ON, 60, 100]]);
+
+        // The first step is lengthened by the swing, and so is its gate.
+        arpeggiator.position = 0.18;
+        arpeggiator.update(1);
+        assert!(get_sent(&mut arpeggiator).is_empty());
+
+        arpeggiator.position = 0.19;
+        arpeggiator.update(2);
+        assert_eq!(get_sent(&mut arpeggiator), [[NOTE_OFF, 60, 0]]);
+
+        // The second step is shortened.
+        arpeggiator.position = 0.375;
+        arpeggiator.update(3);
+        assert_eq!(get_sent(&mut arpeggiator), [[NOTE_ON, 60, 100]]);
+
+        arpeggiator.position = 0.44;
+        arpeggiator.update(4);
+        assert_eq!(get_sent(&mut arpeggiator), [[NOTE_OFF, 60, 0]]);
+    }
+
+    #[test]
+    fn test_latch()
+    {
+        let mut arpeggiator = Arpeggiator::default();
+        arpeggiator.set_parameter(5, 1.0);
+
+        arpeggiator.note_on(60, 100, 0);
+        arpeggiator.note_on(64, 100, 0);
+        arpeggiator.note_off(60);
+        arpeggiator.note_off(64);
+        assert_eq!(arpeggiator.sequence, [60, 64]);
+
+        // Playing again once every key is up starts a new pattern, while
+        // notes added to a held chord join it.
+        arpeggiator.note_on(67, 100, 0);
+        assert_eq!(arpeggiator.sequence, [67]);
+        arpeggiator.note_on(72, 100, 0);
+        arpeggiator.note_off(72);
+        assert_eq!(arpeggiator.sequence, [67, 72]);
+
+        // Switching latch off keeps only the keys still held.
+        arpeggiator.set_parameter(5, 0.0);
+        assert_eq!(arpeggiator.sequence, [67]);
+
+        arpeggiator.note_off(67);
+        assert_eq!(arpeggiator.sequence, []);
+    }
+
+    #[test]
+    fn test_event_order()
+    {
+        let mut arpeggiator = Arpeggiator::default();
+
+        for (note, delta_frames) in [(60, 5), (61, 2), (62, 5), (63, 0)].iter() {
+            arpeggiator.queue_event(get_midi_event([NOTE_ON, *note, 100], *delta_frames));
+        }
+
+        let notes: Vec<u8> = arpeggiator.incoming.iter().map(|event| event.data[1]).collect();
+        assert_eq!(notes, [63, 61, 60, 62]);
+    }
+}
@@ -0,0 +1,115 @@
+use vstutils::param::{choice_to_param, param_to_choice};
+
+const MAX_NOTE: u8 = 127;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Order {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+pub const NUM_ORDERS: usize = 5;
+
+pub fn get_order(param: f32) -> Order {
+    match param_to_choice(param, NUM_ORDERS) {
+        0 => Order::Up,
+        1 => Order::Down,
+        2 => Order::UpDown,
+        3 => Order::Random,
+        _ => Order::AsPlayed,
+    }
+}
+
+pub fn get_param(order: Order) -> f32 {
+    choice_to_param(order as usize, NUM_ORDERS)
+}
+
+pub fn get_name(order: Order) -> String {
+    match order {
+        Order::Up       => "Up"       .to_string(),
+        Order::Down     => "Down"     .to_string(),
+        Order::UpDown   => "Up-Down"  .to_string(),
+        Order::Random   => "Random"   .to_string(),
+        Order::AsPlayed => "As Played".to_string(),
+    }
+}
+
+/// Fills `sequence` with the notes to step through, from the held notes in
+/// the order they were played. Each extra octave repeats the notes an octave
+/// higher, leaving out any which would go past the top of the MIDI range.
+/// Unless the order is as played, `notes` is sorted in place.
+///
+/// Random patterns are built in ascending order, and it's up to the player
+/// to pick from them at random.
+pub fn build(order: Order, notes: &mut [u8], octaves: u8, sequence: &mut Vec<u8>) {
+    sequence.clear();
+
+    if order != Order::AsPlayed {
+        notes.sort_unstable();
+    }
+
+    for octave in 0..octaves {
+        let offset = octave.saturating_mul(12);
+
+        sequence.extend(notes.iter()
+                              .filter(|&&note| note <= MAX_NOTE.saturating_sub(offset))
+                              .map(|&note| note + offset));
+    }
+
+    match order {
+        Order::Down   => sequence.reverse(),
+        // Go back down without repeating the top and bottom notes.
+        Order::UpDown => {
+            let length = sequence.len();
+
+            if length > 2 {
+                for index in (1..length - 1).rev() {
+                    let note = sequence[index];
+                    sequence.push(note);
+                }
+            }
+        },
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_sequence(order: Order, notes: &[u8], octaves: u8) -> Vec<u8> {
+        let mut sequence = Vec::new();
+        build(order, &mut notes.to_vec(), octaves, &mut sequence);
+        sequence
+    }
+
+    #[test]
+    fn test_orders()
+    {
+        let notes = [64, 60, 67];
+
+        assert_eq!(get_sequence(Order::Up, &notes, 1), [60, 64, 67]);
+        assert_eq!(get_sequence(Order::Down, &notes, 1), [67, 64, 60]);
+        assert_eq!(get_sequence(Order::UpDown, &notes, 1), [60, 64, 67, 64]);
+        assert_eq!(get_sequence(Order::Random, &notes, 1), [60, 64, 67]);
+        assert_eq!(get_sequence(Order::AsPlayed, &notes, 1), [64, 60, 67]);
+
+        // Up-down patterns of one or two notes just alternate.
+        assert_eq!(get_sequence(Order::UpDown, &[60, 67], 1), [60, 67]);
+        assert_eq!(get_sequence(Order::UpDown, &[], 1), []);
+    }
+
+    #[test]
+    fn test_octaves()
+    {
+        assert_eq!(get_sequence(Order::Up, &[67, 60], 2), [60, 67, 72, 79]);
+        assert_eq!(get_sequence(Order::AsPlayed, &[67, 60], 2), [67, 60, 79, 72]);
+        assert_eq!(get_sequence(Order::UpDown, &[60], 3), [60, 72, 84, 72]);
+
+        // Notes past the top of the range are left out.
+        assert_eq!(get_sequence(Order::Up, &[110, 120], 3), [110, 120, 122]);
+    }
+}
@@ -0,0 +1,186 @@
+use vstutils::delayline::DelayLine;
+use vstutils::filter::{Biquad, BiquadCoefficients};
+use vstutils::oversampler;
+use vstutils::oversampler::{Oversampler, Quality};
+
+use crusher::{Crusher, Dither};
+use shaper;
+use shaper::Mode;
+
+// Auto gain won't try to make up more than 24 dB in either direction.
+const MIN_AUTO_GAIN: f32 = 0.063;
+const MAX_AUTO_GAIN: f32 = 15.85;
+const SILENCE: f32 = 1e-10;
+
+/// Returns the most latency any settings add, with the highest oversampling
+/// factor and quality.
+pub fn get_max_latency() -> usize {
+    oversampler::get_latency(oversampler::MAX_FACTOR, Quality::High)
+}
+
+/// Settings
+///
+/// The values of the parameters which the wet signal path needs for one
+/// frame, after smoothing.
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub filters:    Filters,
+    pub mode:       Mode,
+    pub drive:      f32,
+    pub bits:       f32,
+    pub dither:     Dither,
+    pub downsample: f32,
+    pub jitter:     f32,
+    pub anti_alias: bool,
+}
+
+/// Filters
+///
+/// Coefficients for the tone filters, which are shared between channels.
+#[derive(Clone, Copy)]
+pub struct Filters {
+    pub pre_highpass: BiquadCoefficients,
+    pub tilt_low:     BiquadCoefficients,
+    pub tilt_high:    BiquadCoefficients,
+    pub tone:         BiquadCoefficients,
+}
+
+impl Default for Filters {
+    fn default() -> Filters {
+        Filters {
+            pre_highpass: BiquadCoefficients::identity(),
+            tilt_low:     BiquadCoefficients::identity(),
+            tilt_high:    BiquadCoefficients::identity(),
+            tone:         BiquadCoefficients::identity(),
+        }
+    }
+}
+
+/// Channel
+///
+/// The processing state for one lane of audio. Normally that's one input
+/// channel, but in mid/side mode the first two lanes carry the mid and side
+/// signals.
+pub struct Channel {
+    oversampler:     Oversampler,
+    dry_oversampler: Oversampler,
+    compensation:    DelayLine,
+    padding:         usize,
+    crusher:         Crusher,
+    pre_highpass:    Biquad,
+    tilt_low:        Biquad,
+    tilt_high:       Biquad,
+    tone:            Biquad,
+    dry_power:       f32,
+    wet_power:       f32,
+}
+
+impl Channel {
+    pub fn new(index: usize, oversampling: usize, quality: Quality) -> Channel {
+        Channel {
+            oversampler:     Oversampler::new(1 << oversampling, quality),
+            dry_oversampler: Oversampler::new(1 << oversampling, quality),
+            compensation:    DelayLine::new(get_max_latency()),
+            padding:         0,
+            // Seed each channel differently so that their dither and jitter
+            // aren't correlated.
+            crusher:         Crusher::new(index as u32 + 1),
+            pre_highpass:    Biquad::default(),
+            tilt_low:        Biquad::default(),
+            tilt_high:       Biquad::default(),
+            tone:            Biquad::default(),
+            dry_power:       0.0,
+            wet_power:       0.0,
+        }
+    }
+
+    pub fn set_oversampling(&mut self, oversampling: usize, quality: Quality) {
+        self.oversampler.configure(1 << oversampling, quality);
+        self.dry_oversampler.configure(1 << oversampling, quality);
+    }
+
+    /// Returns the latency the oversampling adds.
+    pub fn get_latency(&self) -> usize {
+        self.oversampler.get_latency()
+    }
+
+    /// Pads the channel's latency out to the latency the host has been told
+    /// about. If the oversampling adds more than that, the host is out until
+    /// it's told again.
+    pub fn set_padding(&mut self, latency: usize) {
+        self.padding = latency.saturating_sub(self.get_latency());
+    }
+
+    /// Delays the output by however much less than the plugin's latency
+    /// the oversampling adds.
+    pub fn compensate(&mut self, output: f32) -> f32 {
+        if self.padding == 0 {
+            return output;
+        }
+
+        let delayed = self.compensation.read(self.padding as f32);
+        self.compensation.write(output);
+        delayed
+    }
+
+    /// Runs a sample through the pre-emphasis filters, the distortion, the
+    /// crusher and finally the tone filter.
+    pub fn process_wet(&mut self, input: f32, threshold: f32, settings: &Settings) -> f32 {
+        let mode    = settings.mode;
+        let filters = &settings.filters;
+
+        let emphasised = self.tilt_high.process(
+                             self.tilt_low.process(
+                                 self.pre_highpass.process(input, &filters.pre_highpass),
+                                 &filters.tilt_low),
+                             &filters.tilt_high);
+
+        // The distortion is what generates the harmonics which alias, so it's
+        // the part which runs at the oversampled rate. The crusher is meant
+        // to alias, so it runs at the base rate.
+        let distorted = self.oversampler.process(emphasised * settings.drive, |sample| {
+            shaper::shape(mode, sample, threshold)
+        });
+        let decimated = self.crusher.decimate(distorted,
+                                              settings.downsample,
+                                              settings.jitter,
+                                              settings.anti_alias);
+
+        let crushed = self.crusher.quantise(decimated, settings.bits, settings.dither);
+
+        self.tone.process(crushed, &filters.tone)
+    }
+
+    /// Passes the dry signal through a matching oversampler so that it lines
+    /// up with the wet signal when they're mixed.
+    pub fn process_dry(&mut self, input: f32) -> f32 {
+        self.dry_oversampler.process(input, |sample| sample)
+    }
+
+    /// Tracks the average power of the dry and wet signals, and returns the
+    /// gain which would bring the wet signal back to the level of the dry.
+    pub fn get_auto_gain(&mut self, dry: f32, wet: f32, coefficient: f32) -> f32 {
+        self.dry_power += coefficient * (dry * dry - self.dry_power);
+        self.wet_power += coefficient * (wet * wet - self.wet_power);
+
+        get_auto_gain(self.dry_power, self.wet_power)
+    }
+}
+
+/// Returns one auto gain for several channels, from their combined power,
+/// so that linked channels are turned up and down together.
+pub fn get_shared_auto_gain(channels: &[Channel]) -> f32 {
+    let dry_power = channels.iter().map(|channel| channel.dry_power).sum();
+    let wet_power = channels.iter().map(|channel| channel.wet_power).sum();
+
+    get_auto_gain(dry_power, wet_power)
+}
+
+fn get_auto_gain(dry_power: f32, wet_power: f32) -> f32 {
+    if wet_power > SILENCE {
+        (dry_power / wet_power).sqrt().clamp(MIN_AUTO_GAIN, MAX_AUTO_GAIN)
+    }
+    else {
+        1.0
+    }
+}
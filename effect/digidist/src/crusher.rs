@@ -0,0 +1,176 @@
+use vstutils::filter::{Biquad, BiquadCoefficients};
+use vstutils::param::{choice_to_param, param_to_choice};
+use vstutils::random::Random;
+
+pub const MIN_BITS: f32 = 1.0;
+pub const MAX_BITS: f32 = 24.0;
+
+pub const MIN_DOWNSAMPLE: f32 = 1.0;
+pub const MAX_DOWNSAMPLE: f32 = 64.0;
+
+// The anti-alias filter is a fourth order Butterworth lowpass, made from two
+// biquads with these Qs.
+const ANTI_ALIAS_QS: [f32; 2] = [0.5412, 1.3066];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    Rectangular,
+    Triangular,
+}
+
+const NUM_DITHERS: usize = 3;
+
+pub fn get_dither(param: f32) -> Dither {
+    match param_to_choice(param, NUM_DITHERS) {
+        0 => Dither::None,
+        1 => Dither::Rectangular,
+        _ => Dither::Triangular,
+    }
+}
+
+pub fn get_dither_param(dither: Dither) -> f32 {
+    choice_to_param(dither as usize, NUM_DITHERS)
+}
+
+pub fn get_dither_name(dither: Dither) -> String {
+    match dither {
+        Dither::None        => "None".to_string(),
+        Dither::Rectangular => "RPDF".to_string(),
+        Dither::Triangular  => "TPDF".to_string(),
+    }
+}
+
+/// Crusher
+///
+/// Reduces the bit depth and sample rate of a single channel.
+pub struct Crusher {
+    random:       Random,
+    filters:      [Biquad; 2],
+    coefficients: [BiquadCoefficients; 2],
+    cutoff:       f32,
+    held:         f32,
+    elapsed:      f32,
+    interval:     f32,
+}
+
+impl Crusher {
+    pub fn new(seed: u32) -> Crusher {
+        Crusher {
+            random:       Random::new(seed),
+            filters:      [Biquad::default(); 2],
+            coefficients: [BiquadCoefficients::identity(); 2],
+            cutoff:       0.0,
+            held:         0.0,
+            elapsed:      0.0,
+            interval:     1.0,
+        }
+    }
+
+    /// Holds each sample for `downsample` samples, with the length of each
+    /// hold varied randomly by up to `jitter` (0-1) of that. If `anti_alias`
+    /// is set, the input is lowpassed to the reduced Nyquist frequency first.
+    pub fn decimate(&mut self,
+                    input:      f32,
+                    downsample: f32,
+                    jitter:     f32,
+                    anti_alias: bool) -> f32 {
+        let filtered = if anti_alias && downsample > MIN_DOWNSAMPLE {
+            // Work in fractions of the sample rate, with the cutoff at the
+            // reduced Nyquist frequency.
+            let cutoff = 0.5 / downsample;
+            if cutoff != self.cutoff {
+                self.cutoff = cutoff;
+                for (coefficients, q) in self.coefficients.iter_mut().zip(ANTI_ALIAS_QS.iter()) {
+                    *coefficients = BiquadCoefficients::lowpass(cutoff, *q, 1.0);
+                }
+            }
+
+            self.filters
+                .iter_mut()
+                .zip(self.coefficients.iter())
+                .fold(input, |sample, (filter, coefficients)| {
+                    filter.process(sample, coefficients)
+                })
+        }
+        else {
+            input
+        };
+
+        self.elapsed += 1.0;
+        if self.elapsed >= self.interval {
+            self.elapsed -= self.interval;
+            self.held     = filtered;
+            self.interval = (downsample * (1.0 + jitter * self.random.next_bipolar() / 2.0))
+                                .max(MIN_DOWNSAMPLE);
+        }
+
+        self.held
+    }
+
+    /// Quantises a sample to `bits` bits, which needn't be a whole number.
+    pub fn quantise(&mut self, input: f32, bits: f32, dither: Dither) -> f32 {
+        if bits >= MAX_BITS {
+            return input;
+        }
+
+        let steps = (bits - 1.0).exp2();
+        let noise = match dither {
+            Dither::None        => 0.0,
+            Dither::Rectangular => self.random.next_bipolar() / 2.0,
+            Dither::Triangular  => (self.random.next_bipolar()
+                                    + self.random.next_bipolar()) / 2.0,
+        };
+
+        (input * steps + noise).round() / steps
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.000001
+    }
+
+    #[test]
+    fn test_quantise()
+    {
+        let mut crusher = Crusher::new(1);
+
+        assert!(floats_equal(crusher.quantise(0.3, 2.0, Dither::None), 0.5));
+        assert!(floats_equal(crusher.quantise(0.2, 2.0, Dither::None), 0.0));
+        assert!(floats_equal(crusher.quantise(0.3, MAX_BITS, Dither::None), 0.3));
+
+        for _ in 0..100 {
+            let value = crusher.quantise(0.3, 2.0, Dither::Triangular);
+            assert!(floats_equal(value, 0.0)
+                    || floats_equal(value, 0.5)
+                    || floats_equal(value, 1.0));
+        }
+    }
+
+    #[test]
+    fn test_decimate()
+    {
+        let mut crusher = Crusher::new(1);
+        let inputs  = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0];
+        let outputs: Vec<f32> = inputs.iter()
+                                      .map(|input| crusher.decimate(*input, 3.0, 0.0, false))
+                                      .collect();
+
+        assert_eq!(outputs, [1.0, 1.0, 1.0, 4.0, 4.0, 4.0, 7.0]);
+    }
+
+    #[test]
+    fn test_no_decimation()
+    {
+        let mut crusher = Crusher::new(1);
+
+        for index in 0..100 {
+            let input = index as f32;
+            assert!(floats_equal(crusher.decimate(input, MIN_DOWNSAMPLE, 0.0, true), input));
+        }
+    }
+}
@@ -1,43 +1,255 @@
 // lib.rs
 
 #[macro_use] extern crate vst;
+extern crate vstutils;
+
+mod channel;
+mod crusher;
+mod shaper;
 
 use vst::buffer::AudioBuffer;
-use vst::plugin::{Info, Plugin};
+use vst::channels::{ChannelInfo, SpeakerArrangementType, StereoChannel, StereoConfig};
+use vst::plugin::{HostCallback, Info, Plugin};
+
+use vstutils::filter::{BiquadCoefficients, BUTTERWORTH_Q};
+use vstutils::latency;
+use vstutils::maths::{db_to_gain, log_range_to_param, param_to_log_range};
+use vstutils::oversampler::Quality;
+use vstutils::param::{bool_to_name, bool_to_param, choice_to_param, param_to_bool,
+                      param_to_choice};
+use vstutils::targetval::{Rate, TargetVal};
+
+use channel::{Channel, Filters, Settings};
+use crusher::Dither;
+use shaper::Mode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum StereoMode {
+    Linked,
+    LeftRight,
+    MidSide,
+}
+
+const NUM_STEREO_MODES: usize = 3;
 
 struct DigiDist {
-    threshold:        f32,
-    active_threshold: f32
+    host:              HostCallback,
+    // The latency the host has been told about.
+    latency:           usize,
+    threshold:         f32,
+    active_threshold:  f32,
+    threshold2:        f32,
+    active_threshold2: f32,
+    stereo_mode:       StereoMode,
+    mode:              Mode,
+    oversampling:      usize,
+    quality:           Quality,
+    bits:              TargetVal<f32>,
+    dither:            Dither,
+    downsample:        TargetVal<f32>,
+    anti_alias:        bool,
+    jitter:            TargetVal<f32>,
+    drive:             TargetVal<f32>,
+    mix:               TargetVal<f32>,
+    output:            TargetVal<f32>,
+    auto_gain:         bool,
+    pre_highpass:      TargetVal<f32>,
+    tilt:              TargetVal<f32>,
+    tone:              TargetVal<f32>,
+    filters:           Filters,
+    filter_values:     [f32; 3],
+    sample_rate:       f32,
+    channels:          Vec<Channel>,
+}
+
+// The plugin is stereo, and has a lane of processing for each side.
+const NUM_CHANNELS: usize = 2;
+
+// Oversampling factors are powers of two from 1 (off) to 8.
+const NUM_OVERSAMPLING_CHOICES: usize = 4;
+const NUM_QUALITIES: usize = 3;
+
+const MAX_DRIVE: f32 = 36.0;
+const MIN_OUTPUT: f32 = -24.0;
+const MAX_OUTPUT: f32 = 12.0;
+
+// At their extremes, the high-pass and tone filters switch off entirely.
+const MIN_HIGHPASS: f32 = 20.0;
+const MAX_HIGHPASS: f32 = 2000.0;
+const MAX_TILT: f32 = 12.0;
+const TILT_FREQUENCY: f32 = 800.0;
+const MIN_TONE: f32 = 1000.0;
+const MAX_TONE: f32 = 20000.0;
+
+// Auto gain measures loudness over roughly this many seconds.
+const AUTO_GAIN_TIME: f32 = 0.3;
+
+impl DigiDist {
+    fn update_oversamplers(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.set_oversampling(self.oversampling, self.quality);
+            channel.set_padding(self.latency);
+        }
+    }
+
+    fn get_auto_gain_coefficient(&self) -> f32 {
+        1.0 - (-1.0 / (AUTO_GAIN_TIME * self.sample_rate)).exp()
+    }
+
+    /// Recalculates the filter coefficients, if any of the filter parameters
+    /// have moved since they were last calculated.
+    fn update_filters(&mut self) {
+        let values = [*self.pre_highpass.get_value(),
+                      *self.tilt.get_value(),
+                      *self.tone.get_value()];
+
+        if values == self.filter_values {
+            return;
+        }
+        self.filter_values = values;
+
+        let [pre_highpass, tilt, tone] = values;
+
+        self.filters = Filters {
+            pre_highpass: if pre_highpass <= MIN_HIGHPASS {
+                BiquadCoefficients::identity()
+            }
+            else {
+                BiquadCoefficients::highpass(pre_highpass, BUTTERWORTH_Q, self.sample_rate)
+            },
+            // Tilting the spectrum around a pivot means cutting one side and
+            // boosting the other by the same amount.
+            tilt_low:     BiquadCoefficients::low_shelf(TILT_FREQUENCY,
+                                                        -tilt / 2.0,
+                                                        self.sample_rate),
+            tilt_high:    BiquadCoefficients::high_shelf(TILT_FREQUENCY,
+                                                         tilt / 2.0,
+                                                         self.sample_rate),
+            tone:         if tone >= MAX_TONE {
+                BiquadCoefficients::identity()
+            }
+            else {
+                BiquadCoefficients::lowpass(tone, BUTTERWORTH_Q, self.sample_rate)
+            },
+        };
+    }
 }
 
 impl Default for DigiDist {
     fn default() -> DigiDist {
         DigiDist {
-            threshold:        1.0,
-            active_threshold: 1.0
+            host:              Default::default(),
+            latency:           0,
+            threshold:         1.0,
+            active_threshold:  1.0,
+            threshold2:        1.0,
+            active_threshold2: 1.0,
+            stereo_mode:       StereoMode::Linked,
+            mode:              Mode::HardClip,
+            oversampling:      0,
+            quality:           Quality::Medium,
+            bits:              TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , crusher::MAX_BITS),
+            dither:            Dither::None,
+            downsample:        TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , crusher::MIN_DOWNSAMPLE),
+            anti_alias:        false,
+            jitter:            TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , 0.0),
+            drive:             TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , 0.0),
+            mix:               TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , 1.0),
+            output:            TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , 0.0),
+            auto_gain:         false,
+            pre_highpass:      TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , MIN_HIGHPASS),
+            tilt:              TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , 0.0),
+            tone:              TargetVal::new(  Rate::Relative(0.001)
+                                              , Rate::Relative(0.001)
+                                              , MAX_TONE),
+            filters:           Filters::default(),
+            filter_values:     [MIN_HIGHPASS, 0.0, MAX_TONE],
+            sample_rate:       44100.0,
+            channels:          (0..NUM_CHANNELS)
+                                   .map(|index| Channel::new(index, 0, Quality::Medium))
+                                   .collect(),
         }
     }
 }
 
 impl Plugin for DigiDist {
+    fn new(host: HostCallback) -> DigiDist {
+        DigiDist {
+            host,
+            ..DigiDist::default()
+        }
+    }
+
     fn get_info(&self) -> Info {
         Info {
             name:       "DigiDist".to_string(),
             vendor:     "johnelse".to_string(),
             unique_id:  29102017,
 
-            inputs:     2,
-            outputs:    2,
-            parameters: 1,
+            inputs:     NUM_CHANNELS as i32,
+            outputs:    NUM_CHANNELS as i32,
+            parameters: 18,
+
+            initial_delay: self.latency as i32,
 
             // fill in the rest with the default values
             ..Info::default()
         }
     }
 
+    fn resume(&mut self) {
+        // Hosts only take in a new latency while the plugin is resumed, so
+        // the oversampling settings' latency is reported here, and any
+        // changes while processing are padded out to it.
+        let latency = self.channels[0].get_latency();
+
+        if latency != self.latency {
+            self.latency = latency;
+            self.update_oversamplers();
+            latency::set_latency(&self.host, latency);
+        }
+    }
+
     fn get_parameter(&self, index: i32) -> f32 {
         match index {
             0 => self.threshold,
+            1 => shaper::get_param(self.mode),
+            2 => choice_to_param(self.oversampling, NUM_OVERSAMPLING_CHOICES),
+            3 => choice_to_param(self.quality as usize, NUM_QUALITIES),
+            4 => (self.bits.get_target() - crusher::MIN_BITS)
+                 / (crusher::MAX_BITS - crusher::MIN_BITS),
+            5 => crusher::get_dither_param(self.dither),
+            6 => log_range_to_param(*self.downsample.get_target(),
+                                    crusher::MIN_DOWNSAMPLE, crusher::MAX_DOWNSAMPLE),
+            7 => bool_to_param(self.anti_alias),
+            8 => *self.jitter.get_target(),
+            9 => self.drive.get_target() / MAX_DRIVE,
+            10 => *self.mix.get_target(),
+            11 => (self.output.get_target() - MIN_OUTPUT) / (MAX_OUTPUT - MIN_OUTPUT),
+            12 => bool_to_param(self.auto_gain),
+            13 => choice_to_param(self.stereo_mode as usize, NUM_STEREO_MODES),
+            14 => self.threshold2,
+            15 => log_range_to_param(*self.pre_highpass.get_target(),
+                                     MIN_HIGHPASS,
+                                     MAX_HIGHPASS),
+            16 => (self.tilt.get_target() + MAX_TILT) / (2.0 * MAX_TILT),
+            17 => log_range_to_param(*self.tone.get_target(), MIN_TONE, MAX_TONE),
             _ => 0.0,
         }
     }
@@ -46,6 +258,42 @@ impl Plugin for DigiDist {
         match index {
             // We don't want to divide by zero, so we'll clamp the value
             0 => self.threshold = value.max(0.01),
+            1 => self.mode      = shaper::get_mode(value),
+            2 => {
+                self.oversampling = param_to_choice(value, NUM_OVERSAMPLING_CHOICES);
+                self.update_oversamplers();
+            },
+            3 => {
+                self.quality = match param_to_choice(value, NUM_QUALITIES) {
+                    0 => Quality::Low,
+                    1 => Quality::Medium,
+                    _ => Quality::High,
+                };
+                self.update_oversamplers();
+            },
+            4 => self.bits.set_target(crusher::MIN_BITS
+                                      + value * (crusher::MAX_BITS - crusher::MIN_BITS)),
+            5 => self.dither = crusher::get_dither(value),
+            6 => self.downsample.set_target(param_to_log_range(value,
+                                                               crusher::MIN_DOWNSAMPLE,
+                                                               crusher::MAX_DOWNSAMPLE)),
+            7 => self.anti_alias = param_to_bool(value),
+            8 => self.jitter.set_target(value),
+            9 => self.drive.set_target(value * MAX_DRIVE),
+            10 => self.mix.set_target(value),
+            11 => self.output.set_target(MIN_OUTPUT + value * (MAX_OUTPUT - MIN_OUTPUT)),
+            12 => self.auto_gain = param_to_bool(value),
+            13 => self.stereo_mode = match param_to_choice(value, NUM_STEREO_MODES) {
+                0 => StereoMode::Linked,
+                1 => StereoMode::LeftRight,
+                _ => StereoMode::MidSide,
+            },
+            14 => self.threshold2 = value.max(0.01),
+            15 => self.pre_highpass.set_target(param_to_log_range(value,
+                                                                  MIN_HIGHPASS,
+                                                                  MAX_HIGHPASS)),
+            16 => self.tilt.set_target(value * 2.0 * MAX_TILT - MAX_TILT),
+            17 => self.tone.set_target(param_to_log_range(value, MIN_TONE, MAX_TONE)),
             _ => (),
         }
     }
@@ -53,6 +301,23 @@ impl Plugin for DigiDist {
     fn get_parameter_name(&self, index: i32) -> String {
         match index {
             0 => "Threshold".to_string(),
+            1 => "Mode".to_string(),
+            2 => "Oversampling".to_string(),
+            3 => "Quality".to_string(),
+            4 => "Bits".to_string(),
+            5 => "Dither".to_string(),
+            6 => "Downsample".to_string(),
+            7 => "Anti-alias".to_string(),
+            8 => "Jitter".to_string(),
+            9 => "Drive".to_string(),
+            10 => "Mix".to_string(),
+            11 => "Output".to_string(),
+            12 => "Auto Gain".to_string(),
+            13 => "Stereo".to_string(),
+            14 => "Threshold 2".to_string(),
+            15 => "Pre HP".to_string(),
+            16 => "Tilt".to_string(),
+            17 => "Tone".to_string(),
             _ => "".to_string(),
         }
     }
@@ -61,37 +326,200 @@ impl Plugin for DigiDist {
         match index {
             // Convert to a percentage
             0 => format!("{}", self.threshold * 100.0),
+            1 => shaper::get_name(self.mode),
+            2 => match self.oversampling {
+                0 => "Off".to_string(),
+                n => format!("{}x", 1 << n),
+            },
+            3 => match self.quality {
+                Quality::Low    => "Low".to_string(),
+                Quality::Medium => "Medium".to_string(),
+                Quality::High   => "High".to_string(),
+            },
+            4 => if *self.bits.get_target() >= crusher::MAX_BITS {
+                "Off".to_string()
+            }
+            else {
+                format!("{:.1}", self.bits.get_target())
+            },
+            5 => crusher::get_dither_name(self.dither),
+            6 => format!("{:.1}", self.downsample.get_target()),
+            7 => bool_to_name(self.anti_alias),
+            8 => format!("{}", self.jitter.get_target() * 100.0),
+            9 => format!("{:.1}", self.drive.get_target()),
+            10 => format!("{}", self.mix.get_target() * 100.0),
+            11 => format!("{:.1}", self.output.get_target()),
+            12 => bool_to_name(self.auto_gain),
+            13 => match self.stereo_mode {
+                StereoMode::Linked    => "Linked".to_string(),
+                StereoMode::LeftRight => "L/R".to_string(),
+                StereoMode::MidSide   => "M/S".to_string(),
+            },
+            14 => format!("{}", self.threshold2 * 100.0),
+            15 => if *self.pre_highpass.get_target() <= MIN_HIGHPASS {
+                "Off".to_string()
+            }
+            else {
+                format!("{:.0}", self.pre_highpass.get_target())
+            },
+            16 => format!("{:.1}", self.tilt.get_target()),
+            17 => if *self.tone.get_target() >= MAX_TONE {
+                "Off".to_string()
+            }
+            else {
+                format!("{:.0}", self.tone.get_target())
+            },
             _ => "".to_string(),
         }
     }
 
     fn get_parameter_label(&self, index: i32) -> String {
         match index {
-            0 => "%".to_string(),
-            _ => "".to_string(),
+            0 | 8 | 10 | 14 => "%".to_string(),
+            6               => "x".to_string(),
+            9 | 11 | 16     => "dB".to_string(),
+            15 | 17         => "Hz".to_string(),
+            _               => "".to_string(),
         }
     }
 
+    fn get_input_info(&self, input: i32) -> ChannelInfo {
+        get_channel_info("Input", "In", input)
+    }
+
+    fn get_output_info(&self, output: i32) -> ChannelInfo {
+        get_channel_info("Output", "Out", output)
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+
+        // Force the filters to be recalculated for the new rate.
+        self.filter_values = [f32::NAN; 3];
+    }
+
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        // For each buffer, transform the samples
-        for (input_buffer, output_buffer) in buffer.zip() {
-            for (input_sample, output_sample) in input_buffer.iter().zip(output_buffer) {
+        let samples = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+        let num_channels = inputs.len().min(outputs.len()).min(self.channels.len());
+
+        // Any channels a host passes beyond the ones the plugin has go
+        // through untouched.
+        for channel_index in num_channels..inputs.len().min(outputs.len()) {
+            outputs.get_mut(channel_index).copy_from_slice(inputs.get(channel_index));
+        }
 
-                self.active_threshold += 0.0001
-                                         * (self.threshold - self.active_threshold);
+        let auto_gain_coefficient = self.get_auto_gain_coefficient();
 
-                if *input_sample >= 0.0 {
-                    *output_sample = input_sample.min(self.active_threshold)
-                                     / self.active_threshold;
-                }
-                else {
-                    *output_sample = input_sample.max(-self.active_threshold)
-                                     / self.active_threshold;
+        // A stereo pair gets the stereo mode. A mono bus is just a single
+        // channel, using the first threshold.
+        let num_paired = if num_channels == 2 {2} else {0};
+
+        for sample_index in 0..samples {
+            self.active_threshold  += 0.0001 * (self.threshold  - self.active_threshold);
+            self.active_threshold2 += 0.0001 * (self.threshold2 - self.active_threshold2);
+            self.bits.advance();
+            self.downsample.advance();
+            self.jitter.advance();
+            self.drive.advance();
+            self.mix.advance();
+            self.output.advance();
+            self.pre_highpass.advance();
+            self.tilt.advance();
+            self.tone.advance();
+            self.update_filters();
+
+            let settings = Settings {
+                filters:    self.filters,
+                mode:       self.mode,
+                drive:      db_to_gain(*self.drive.get_value()),
+                bits:       *self.bits.get_value(),
+                dither:     self.dither,
+                downsample: *self.downsample.get_value(),
+                jitter:     *self.jitter.get_value(),
+                anti_alias: self.anti_alias,
+            };
+            let threshold1 = self.active_threshold;
+            let threshold2 = self.active_threshold2;
+            let mix        = *self.mix.get_value();
+            let output     = db_to_gain(*self.output.get_value());
+
+            let input = |channel_index: usize| inputs.get(channel_index)[sample_index];
+
+            let mut wet_pair = (0.0, 0.0);
+            if num_paired == 2 {
+                let (first, rest) = self.channels.split_at_mut(1);
+                let (left, right) = (&mut first[0], &mut rest[0]);
+
+                wet_pair = match self.stereo_mode {
+                    StereoMode::Linked => {
+                        (left .process_wet(input(0), threshold1, &settings),
+                         right.process_wet(input(1), threshold1, &settings))
+                    },
+                    StereoMode::LeftRight => {
+                        (left .process_wet(input(0), threshold1, &settings),
+                         right.process_wet(input(1), threshold2, &settings))
+                    },
+                    StereoMode::MidSide => {
+                        let mid  = (input(0) + input(1)) / 2.0;
+                        let side = (input(0) - input(1)) / 2.0;
+
+                        let wet_mid  = left .process_wet(mid,  threshold1, &settings);
+                        let wet_side = right.process_wet(side, threshold2, &settings);
+
+                        (wet_mid + wet_side, wet_mid - wet_side)
+                    },
+                };
+            }
+
+            // Linked channels share their auto gain, so that it doesn't pull
+            // the stereo image towards whichever side is distorting less.
+            // It's taken from the levels up to the last sample.
+            let shared_gain = if num_paired == 2 && self.stereo_mode == StereoMode::Linked {
+                Some(channel::get_shared_auto_gain(&self.channels[..num_paired]))
+            }
+            else {
+                None
+            };
+
+            for (channel_index, channel) in self.channels[..num_channels].iter_mut()
+                                                                         .enumerate() {
+                let mut wet = match channel_index {
+                    0 if num_paired == 2 => wet_pair.0,
+                    1 if num_paired == 2 => wet_pair.1,
+                    _ => channel.process_wet(input(channel_index), threshold1, &settings),
+                };
+                let dry = channel.process_dry(input(channel_index));
+
+                if self.auto_gain {
+                    let gain = channel.get_auto_gain(dry, wet, auto_gain_coefficient);
+
+                    wet *= match shared_gain {
+                        Some(shared_gain) if channel_index < num_paired => shared_gain,
+                        _                                              => gain,
+                    };
                 }
 
+                outputs.get_mut(channel_index)[sample_index] =
+                    channel.compensate((dry * (1.0 - mix) + wet * mix) * output);
             }
         }
     }
 }
 
+fn get_channel_info(direction: &str, short_direction: &str, index: i32) -> ChannelInfo {
+    let (name, arrangement) = match index {
+        0 => ("Left".to_string(),  Some(SpeakerArrangementType::Stereo(StereoConfig::L_R,
+                                                                        StereoChannel::Left))),
+        1 => ("Right".to_string(), Some(SpeakerArrangementType::Stereo(StereoConfig::L_R,
+                                                                        StereoChannel::Right))),
+        _ => (format!("{}", index + 1), None),
+    };
+
+    ChannelInfo::new(format!("{} {}", direction, name),
+                     Some(format!("{} {}", short_direction, name)),
+                     true,
+                     arrangement)
+}
+
 plugin_main!(DigiDist);
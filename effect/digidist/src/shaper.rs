@@ -0,0 +1,131 @@
+use std::f32::consts::FRAC_PI_2;
+
+use vstutils::param::{choice_to_param, param_to_choice};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    HardClip,
+    SoftClip,
+    Tube,
+    Foldback,
+    SineFold,
+    Rectify,
+}
+
+const NUM_MODES: usize = 6;
+
+// The bias which makes the tube mode asymmetric.
+const TUBE_BIAS: f32 = 0.25;
+
+pub fn get_mode(param: f32) -> Mode {
+    match param_to_choice(param, NUM_MODES) {
+        0 => Mode::HardClip,
+        1 => Mode::SoftClip,
+        2 => Mode::Tube,
+        3 => Mode::Foldback,
+        4 => Mode::SineFold,
+        _ => Mode::Rectify,
+    }
+}
+
+pub fn get_param(mode: Mode) -> f32 {
+    choice_to_param(mode as usize, NUM_MODES)
+}
+
+pub fn get_name(mode: Mode) -> String {
+    match mode {
+        Mode::HardClip => "Hard Clip".to_string(),
+        Mode::SoftClip => "Soft Clip".to_string(),
+        Mode::Tube     => "Tube"     .to_string(),
+        Mode::Foldback => "Foldback" .to_string(),
+        Mode::SineFold => "Sine Fold".to_string(),
+        Mode::Rectify  => "Rectify"  .to_string(),
+    }
+}
+
+/// Distorts a sample. Every mode treats `threshold` as the level at which the
+/// distortion starts to bite, and scales its output back up so that the
+/// result peaks at around 1.
+pub fn shape(mode: Mode, input: f32, threshold: f32) -> f32 {
+    let scaled = input / threshold;
+
+    match mode {
+        Mode::HardClip => scaled.clamp(-1.0, 1.0),
+        Mode::SoftClip => scaled.tanh(),
+        Mode::Tube     => {
+            // Pushing the curve off-centre clips the two halves of the wave
+            // differently, which adds even harmonics. Subtracting the bias
+            // again keeps silence silent.
+            ((scaled + TUBE_BIAS).tanh() - TUBE_BIAS.tanh()) / (1.0 + TUBE_BIAS.tanh())
+        },
+        Mode::Foldback => {
+            // A triangle wave of the input: anything beyond the threshold is
+            // reflected back towards zero.
+            1.0 - ((scaled + 1.0).rem_euclid(4.0) - 2.0).abs()
+        },
+        Mode::SineFold => (scaled * FRAC_PI_2).sin(),
+        Mode::Rectify  => scaled.abs().min(1.0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALL_MODES: [Mode; NUM_MODES] = [
+        Mode::HardClip,
+        Mode::SoftClip,
+        Mode::Tube,
+        Mode::Foldback,
+        Mode::SineFold,
+        Mode::Rectify,
+    ];
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.000001
+    }
+
+    #[test]
+    fn test_get_mode()
+    {
+        for &mode in ALL_MODES.iter() {
+            assert!(get_mode(get_param(mode)) == mode);
+        }
+    }
+
+    #[test]
+    fn test_bounded()
+    {
+        for &mode in ALL_MODES.iter() {
+            assert!(floats_equal(shape(mode, 0.0, 0.5), 0.0));
+
+            for step in -100..=100 {
+                let input = step as f32 / 10.0;
+                assert!(shape(mode, input, 0.5).abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hard_clip()
+    {
+        assert!(floats_equal(shape(Mode::HardClip,  0.25, 0.5),  0.5));
+        assert!(floats_equal(shape(Mode::HardClip,  2.0,  0.5),  1.0));
+        assert!(floats_equal(shape(Mode::HardClip, -2.0,  0.5), -1.0));
+    }
+
+    #[test]
+    fn test_foldback()
+    {
+        assert!(floats_equal(shape(Mode::Foldback,  0.5, 1.0),  0.5));
+        assert!(floats_equal(shape(Mode::Foldback,  1.5, 1.0),  0.5));
+        assert!(floats_equal(shape(Mode::Foldback, -1.5, 1.0), -0.5));
+        assert!(floats_equal(shape(Mode::Foldback,  3.0, 1.0), -1.0));
+    }
+
+    #[test]
+    fn test_tube_is_asymmetric()
+    {
+        assert!(shape(Mode::Tube, 1.0, 1.0) < -shape(Mode::Tube, -1.0, 1.0));
+    }
+}
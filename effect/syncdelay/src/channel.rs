@@ -0,0 +1,56 @@
+use vstutils::delayline::DelayLine;
+use vstutils::filter::{Biquad, BiquadCoefficients};
+
+/// Filters
+///
+/// Coefficients for the filters in the feedback loop, which are shared
+/// between channels.
+#[derive(Clone, Copy)]
+pub struct Filters {
+    pub low_cut:  BiquadCoefficients,
+    pub high_cut: BiquadCoefficients,
+}
+
+impl Default for Filters {
+    fn default() -> Filters {
+        Filters {
+            low_cut:  BiquadCoefficients::identity(),
+            high_cut: BiquadCoefficients::identity(),
+        }
+    }
+}
+
+/// Channel
+///
+/// One side of the delay: a delay line, and the filters which colour each
+/// repeat as it goes back round the feedback loop.
+pub struct Channel {
+    delay_line: DelayLine,
+    low_cut:    Biquad,
+    high_cut:   Biquad,
+}
+
+impl Channel {
+    pub fn new(max_delay: usize) -> Channel {
+        Channel {
+            delay_line: DelayLine::new(max_delay),
+            low_cut:    Biquad::default(),
+            high_cut:   Biquad::default(),
+        }
+    }
+
+    /// Returns the delayed signal, in samples.
+    pub fn read(&self, delay: f32) -> f32 {
+        self.delay_line.read(delay)
+    }
+
+    pub fn write(&mut self, input: f32) {
+        self.delay_line.write(input);
+    }
+
+    /// Filters the delayed signal on its way back into the delay line.
+    pub fn filter(&mut self, input: f32, filters: &Filters) -> f32 {
+        let low_cut = self.low_cut.process(input, &filters.low_cut);
+        self.high_cut.process(low_cut, &filters.high_cut)
+    }
+}
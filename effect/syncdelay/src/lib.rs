@@ -0,0 +1,338 @@
+// lib.rs
+
+#[macro_use] extern crate vst;
+extern crate vstutils;
+
+mod channel;
+
+use std::f32::consts::PI;
+
+use vst::buffer::AudioBuffer;
+use vst::plugin::{Category, HostCallback, Info, Plugin};
+
+use vstutils::division;
+use vstutils::division::Division;
+use vstutils::filter::{BiquadCoefficients, BUTTERWORTH_Q};
+use vstutils::maths::{log_range_to_param, param_to_log_range};
+use vstutils::param::{bool_to_name, bool_to_param, param_to_bool};
+use vstutils::targetval::{Rate, TargetVal};
+use vstutils::transport::Transport;
+
+use channel::{Channel, Filters};
+
+#[derive(Clone, Copy, PartialEq)]
+enum TimeMode {
+    Sync,
+    Free,
+}
+
+struct SyncDelay {
+    host:           HostCallback,
+    time_mode:      TimeMode,
+    division_param: f32,
+    division:       Division,
+    time:           f32,
+    delay:          TargetVal<f32>,
+    feedback:       TargetVal<f32>,
+    ping_pong:      bool,
+    low_cut:        TargetVal<f32>,
+    high_cut:       TargetVal<f32>,
+    filters:        Filters,
+    filter_values:  [f32; 2],
+    mod_rate:       f32,
+    mod_depth:      TargetVal<f32>,
+    mod_phase:      f32,
+    mix:            TargetVal<f32>,
+    sample_rate:    f32,
+    channels:       [Channel; 2],
+}
+
+// Times are in seconds. Synced delays are limited to the same maximum as
+// free ones, which only matters for long divisions at very slow tempos.
+const MIN_TIME: f32 = 0.001;
+const MAX_TIME: f32 = 4.0;
+
+const MAX_FEEDBACK: f32 = 0.95;
+
+// At their extremes, the filters in the feedback loop switch off entirely.
+const MIN_LOW_CUT: f32 = 20.0;
+const MAX_LOW_CUT: f32 = 2000.0;
+const MIN_HIGH_CUT: f32 = 1000.0;
+const MAX_HIGH_CUT: f32 = 20000.0;
+
+const MIN_MOD_RATE: f32 = 0.05;
+const MAX_MOD_RATE: f32 = 10.0;
+const MAX_MOD_DEPTH: f32 = 0.01;
+
+// Changes to the delay time glide over roughly a tenth of a second, which
+// gives a tape-like pitch bend instead of zipper noise.
+const DELAY_GLIDE: f32 = 0.0002;
+
+fn get_max_delay(sample_rate: f32) -> usize {
+    ((MAX_TIME + 2.0 * MAX_MOD_DEPTH) * sample_rate).ceil() as usize
+}
+
+impl SyncDelay {
+    /// Recalculates the filter coefficients, if either of the filter
+    /// parameters have moved since they were last calculated.
+    fn update_filters(&mut self) {
+        let values = [*self.low_cut.get_value(), *self.high_cut.get_value()];
+
+        if values == self.filter_values {
+            return;
+        }
+        self.filter_values = values;
+
+        let [low_cut, high_cut] = values;
+
+        self.filters = Filters {
+            low_cut:  if low_cut <= MIN_LOW_CUT {
+                BiquadCoefficients::identity()
+            }
+            else {
+                BiquadCoefficients::highpass(low_cut, BUTTERWORTH_Q, self.sample_rate)
+            },
+            high_cut: if high_cut >= MAX_HIGH_CUT {
+                BiquadCoefficients::identity()
+            }
+            else {
+                BiquadCoefficients::lowpass(high_cut, BUTTERWORTH_Q, self.sample_rate)
+            },
+        };
+    }
+
+    /// Returns the delay time the parameters are asking for, in seconds.
+    fn get_time(&self) -> f32 {
+        let time = match self.time_mode {
+            TimeMode::Sync => Transport::from_host(&self.host).get_seconds(self.division),
+            TimeMode::Free => self.time,
+        };
+
+        time.clamp(MIN_TIME, MAX_TIME)
+    }
+}
+
+impl Default for SyncDelay {
+    fn default() -> SyncDelay {
+        SyncDelay::new(Default::default())
+    }
+}
+
+impl Plugin for SyncDelay {
+    fn new(host: HostCallback) -> SyncDelay {
+        // Sync to eighth notes by default.
+        let division_param = 0.7;
+
+        SyncDelay {
+            host,
+            time_mode:      TimeMode::Sync,
+            division_param,
+            division:       division::get_division(division_param),
+            time:           0.25,
+            delay:          TargetVal::new(  Rate::Relative(DELAY_GLIDE)
+                                           , Rate::Relative(DELAY_GLIDE)
+                                           , 0.25),
+            feedback:       TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
+                                           , 0.4),
+            ping_pong:      false,
+            low_cut:        TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
+                                           , MIN_LOW_CUT),
+            high_cut:       TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
+                                           , MAX_HIGH_CUT),
+            filters:        Filters::default(),
+            filter_values:  [MIN_LOW_CUT, MAX_HIGH_CUT],
+            mod_rate:       0.5,
+            mod_depth:      TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
+                                           , 0.0),
+            mod_phase:      0.0,
+            mix:            TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
+                                           , 0.3),
+            sample_rate:    44100.0,
+            channels:       [Channel::new(get_max_delay(44100.0)),
+                             Channel::new(get_max_delay(44100.0))],
+        }
+    }
+
+    fn get_info(&self) -> Info {
+        Info {
+            name:       "SyncDelay".to_string(),
+            vendor:     "johnelse".to_string(),
+            unique_id:  19102026,
+
+            inputs:     2,
+            outputs:    2,
+            parameters: 10,
+
+            category:   Category::Effect,
+
+            // fill in the rest with the default values
+            ..Info::default()
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        match index {
+            0 => bool_to_param(self.time_mode == TimeMode::Free),
+            1 => self.division_param,
+            2 => log_range_to_param(self.time, MIN_TIME, MAX_TIME),
+            3 => self.feedback.get_target() / MAX_FEEDBACK,
+            4 => bool_to_param(self.ping_pong),
+            5 => log_range_to_param(*self.low_cut.get_target(), MIN_LOW_CUT, MAX_LOW_CUT),
+            6 => log_range_to_param(*self.high_cut.get_target(), MIN_HIGH_CUT, MAX_HIGH_CUT),
+            7 => log_range_to_param(self.mod_rate, MIN_MOD_RATE, MAX_MOD_RATE),
+            8 => self.mod_depth.get_target() / MAX_MOD_DEPTH,
+            9 => *self.mix.get_target(),
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: i32, value: f32) {
+        match index {
+            0 => self.time_mode = if param_to_bool(value) {TimeMode::Free}
+                                  else                    {TimeMode::Sync},
+            1 => {
+                self.division_param = value;
+                self.division       = division::get_division(self.division_param)
+            },
+            2 => self.time = param_to_log_range(value, MIN_TIME, MAX_TIME),
+            3 => self.feedback.set_target(value * MAX_FEEDBACK),
+            4 => self.ping_pong = param_to_bool(value),
+            5 => self.low_cut.set_target(param_to_log_range(value, MIN_LOW_CUT, MAX_LOW_CUT)),
+            6 => self.high_cut.set_target(param_to_log_range(value, MIN_HIGH_CUT, MAX_HIGH_CUT)),
+            7 => self.mod_rate = param_to_log_range(value, MIN_MOD_RATE, MAX_MOD_RATE),
+            8 => self.mod_depth.set_target(value * MAX_MOD_DEPTH),
+            9 => self.mix.set_target(value),
+            _ => (),
+        }
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "Mode".to_string(),
+            1 => "Division".to_string(),
+            2 => "Time".to_string(),
+            3 => "Feedback".to_string(),
+            4 => "Ping-Pong".to_string(),
+            5 => "Low Cut".to_string(),
+            6 => "High Cut".to_string(),
+            7 => "Mod Rate".to_string(),
+            8 => "Mod Depth".to_string(),
+            9 => "Mix".to_string(),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            0 => match self.time_mode {
+                TimeMode::Sync => "Sync".to_string(),
+                TimeMode::Free => "Free".to_string(),
+            },
+            1 => division::get_name(self.division),
+            // Convert to milliseconds
+            2 => format!("{:.1}", self.time * 1000.0),
+            3 => format!("{}", self.feedback.get_target() * 100.0),
+            4 => bool_to_name(self.ping_pong),
+            5 => if *self.low_cut.get_target() <= MIN_LOW_CUT {
+                "Off".to_string()
+            }
+            else {
+                format!("{:.0}", self.low_cut.get_target())
+            },
+            6 => if *self.high_cut.get_target() >= MAX_HIGH_CUT {
+                "Off".to_string()
+            }
+            else {
+                format!("{:.0}", self.high_cut.get_target())
+            },
+            7 => format!("{:.2}", self.mod_rate),
+            8 => format!("{:.2}", self.mod_depth.get_target() * 1000.0),
+            9 => format!("{}", self.mix.get_target() * 100.0),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            2 | 8     => "ms".to_string(),
+            3 | 9     => "%".to_string(),
+            5..=7     => "Hz".to_string(),
+            _         => "".to_string(),
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+        self.channels    = [Channel::new(get_max_delay(rate)),
+                            Channel::new(get_max_delay(rate))];
+
+        // Force the filters to be recalculated for the new rate.
+        self.filter_values = [f32::NAN; 2];
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let samples = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+
+        if inputs.len() < 2 || outputs.len() < 2 {
+            return;
+        }
+
+        self.delay.set_target(self.get_time());
+
+        for sample_index in 0..samples {
+            self.delay.advance();
+            self.feedback.advance();
+            self.low_cut.advance();
+            self.high_cut.advance();
+            self.mod_depth.advance();
+            self.mix.advance();
+            self.update_filters();
+
+            self.mod_phase = (self.mod_phase + self.mod_rate / self.sample_rate).fract();
+
+            // The channels are modulated a quarter of a cycle apart, and the
+            // modulation only ever lengthens the delay so that it can't go
+            // below the time that's been set.
+            let depth       = *self.mod_depth.get_value();
+            let mod_left    = depth * (1.0 + (2.0 * PI * self.mod_phase).sin());
+            let mod_right   = depth * (1.0 + (2.0 * PI * (self.mod_phase + 0.25)).sin());
+            let delay       = *self.delay.get_value();
+            let delay_left  = (delay + mod_left) * self.sample_rate;
+            let delay_right = (delay + mod_right) * self.sample_rate;
+
+            let wet_left  = self.channels[0].read(delay_left);
+            let wet_right = self.channels[1].read(delay_right);
+
+            let feedback       = *self.feedback.get_value();
+            let feedback_left  = self.channels[0].filter(wet_left, &self.filters) * feedback;
+            let feedback_right = self.channels[1].filter(wet_right, &self.filters) * feedback;
+
+            let dry_left  = inputs.get(0)[sample_index];
+            let dry_right = inputs.get(1)[sample_index];
+
+            // In ping-pong mode the input goes into the left side only, and
+            // each side feeds the other.
+            if self.ping_pong {
+                self.channels[0].write((dry_left + dry_right) / 2.0 + feedback_right);
+                self.channels[1].write(feedback_left);
+            }
+            else {
+                self.channels[0].write(dry_left + feedback_left);
+                self.channels[1].write(dry_right + feedback_right);
+            }
+
+            let mix = *self.mix.get_value();
+
+            outputs.get_mut(0)[sample_index] = dry_left  * (1.0 - mix) + wet_left  * mix;
+            outputs.get_mut(1)[sample_index] = dry_right * (1.0 - mix) + wet_right * mix;
+        }
+    }
+}
+
+plugin_main!(SyncDelay);
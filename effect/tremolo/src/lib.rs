@@ -0,0 +1,204 @@
+// lib.rs
+
+#[macro_use] extern crate vst;
+extern crate vstutils;
+
+use vst::buffer::AudioBuffer;
+use vst::plugin::{Category, HostCallback, Info, Plugin};
+
+use vstutils::division;
+use vstutils::division::Division;
+use vstutils::lfo;
+use vstutils::lfo::Lfo;
+use vstutils::pan;
+use vstutils::param::{bool_to_param, param_to_bool};
+use vstutils::targetval::{Rate, TargetVal};
+use vstutils::transport::Transport;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Tremolo,
+    AutoPan,
+}
+
+struct Tremolo {
+    host:           HostCallback,
+    mode:           Mode,
+    division_param: f32,
+    division:       Division,
+    depth:          TargetVal<f32>,
+    phase_offset:   f32,
+    lfo:            Lfo,
+    gains:          (f32, f32),
+}
+
+// Gains are smoothed over a few milliseconds, so that the square wave and
+// the saws' resets don't click.
+const GAIN_SMOOTHING: f32 = 0.005;
+
+impl Tremolo {
+    /// Returns the target gains for the left and right channels, with the
+    /// right channel's LFO offset by the stereo phase.
+    fn get_gains(&self) -> (f32, f32) {
+        let depth = *self.depth.get_value();
+        let left  = self.lfo.get_value(0.0);
+        let right = self.lfo.get_value(self.phase_offset);
+
+        match self.mode {
+            // The LFO's peak is full volume, and its trough is cut by the
+            // depth.
+            Mode::Tremolo => (1.0 - depth * (1.0 - left) / 2.0,
+                              1.0 - depth * (1.0 - right) / 2.0),
+            // Each channel takes its side of an equal-power pan, scaled so
+            // that the furthest the depth lets it swing is unity gain. With
+            // no phase offset this moves the whole signal from side to side.
+            Mode::AutoPan => {
+                let (peak, _)       = pan::equal_power(0.5 - depth / 2.0);
+                let (left_gain, _)  = pan::equal_power(0.5 - depth * left / 2.0);
+                let (_, right_gain) = pan::equal_power(0.5 - depth * right / 2.0);
+
+                (left_gain / peak, right_gain / peak)
+            },
+        }
+    }
+}
+
+impl Default for Tremolo {
+    fn default() -> Tremolo {
+        Tremolo::new(Default::default())
+    }
+}
+
+impl Plugin for Tremolo {
+    fn new(host: HostCallback) -> Tremolo {
+        // Sync to quarter notes by default.
+        let division_param = 0.5;
+
+        Tremolo {
+            host,
+            mode:           Mode::Tremolo,
+            division_param,
+            division:       division::get_division(division_param),
+            depth:          TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
+                                           , 0.5),
+            phase_offset:   0.0,
+            lfo:            Lfo::new(44100.0),
+            gains:          (1.0, 1.0),
+        }
+    }
+
+    fn get_info(&self) -> Info {
+        Info {
+            name:       "Tremolo".to_string(),
+            vendor:     "johnelse".to_string(),
+            unique_id:  20102026,
+
+            inputs:     2,
+            outputs:    2,
+            parameters: 5,
+
+            category:   Category::Effect,
+
+            // fill in the rest with the default values
+            ..Info::default()
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        match index {
+            0 => bool_to_param(self.mode == Mode::AutoPan),
+            1 => self.division_param,
+            2 => lfo::get_param(self.lfo.get_shape()),
+            3 => *self.depth.get_target(),
+            4 => self.phase_offset,
+            _ => 0.0,
+        }
+    }
+
+    fn set_parameter(&mut self, index: i32, value: f32) {
+        match index {
+            0 => self.mode = if param_to_bool(value) {Mode::AutoPan} else {Mode::Tremolo},
+            1 => {
+                self.division_param = value;
+                self.division       = division::get_division(self.division_param)
+            },
+            2 => self.lfo.set_shape(lfo::get_shape(value)),
+            3 => self.depth.set_target(value),
+            4 => self.phase_offset = value,
+            _ => (),
+        }
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "Mode".to_string(),
+            1 => "Division".to_string(),
+            2 => "Shape".to_string(),
+            3 => "Depth".to_string(),
+            4 => "Phase".to_string(),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            0 => match self.mode {
+                Mode::Tremolo => "Tremolo".to_string(),
+                Mode::AutoPan => "Auto-Pan".to_string(),
+            },
+            1 => division::get_name(self.division),
+            2 => lfo::get_name(self.lfo.get_shape()),
+            3 => format!("{}", self.depth.get_target() * 100.0),
+            // Convert to degrees
+            4 => format!("{}", self.phase_offset * 360.0),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            3 => "%".to_string(),
+            4 => "deg".to_string(),
+            _ => "".to_string(),
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.lfo.set_sample_rate(rate);
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let samples = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+
+        if inputs.len() < 2 || outputs.len() < 2 {
+            return;
+        }
+
+        let transport = Transport::from_host(&self.host);
+        self.lfo.set_frequency(transport.get_frequency(self.division));
+
+        // While the host is playing, the LFO follows the song position, so
+        // that it lands in the same place every time the song is played.
+        if let Some(ppq_pos) = transport.ppq_pos.filter(|_| transport.playing) {
+            let length = f64::from(division::get_length(self.division, transport.time_sig));
+            self.lfo.set_phase((ppq_pos / length).rem_euclid(1.0) as f32);
+        }
+
+        for sample_index in 0..samples {
+            self.depth.advance();
+
+            let (left_target, right_target) = self.get_gains();
+            self.gains.0 += (left_target  - self.gains.0) * GAIN_SMOOTHING;
+            self.gains.1 += (right_target - self.gains.1) * GAIN_SMOOTHING;
+
+            self.lfo.advance();
+
+            outputs.get_mut(0)[sample_index] = inputs.get(0)[sample_index] * self.gains.0;
+            outputs.get_mut(1)[sample_index] = inputs.get(1)[sample_index] * self.gains.1;
+        }
+    }
+}
+
+plugin_main!(Tremolo);
@@ -3,69 +3,332 @@
 #[macro_use] extern crate vst;
 extern crate vstutils;
 
-use vst::api::{Events, Supported, TimeInfoFlags};
+mod voice;
+
+use vst::api::{Events, Supported};
 use vst::buffer::AudioBuffer;
-use vst::event::{Event, MidiEvent};
-use vst::host::Host;
+use vst::event::{Event, MidiEvent, SysExEvent};
 use vst::plugin::{Category, CanDo, HostCallback, Info, Plugin};
 
+use vstutils::ccmap;
+use vstutils::chunk::Chunk;
 use vstutils::division;
 use vstutils::division::Division;
-use vstutils::generator::{Generator, Oscillator};
-use vstutils::maths::{get_beats_frequencies, midi_pitch_to_freq};
-use vstutils::notetracker::NoteTracker;
+use vstutils::maths::{get_beats_frequencies, get_beats_frequencies_cents,
+                      log_range_to_param, param_to_log_range, PitchConverter};
+use vstutils::modmatrix;
+use vstutils::modulation::{Modulated, Modulation};
+use vstutils::mpe::Mpe;
+use vstutils::mts;
+use vstutils::pan;
+use vstutils::pan::Law;
+use vstutils::param::{bool_to_name, bool_to_param, choice_to_param, param_to_bool,
+                      param_to_choice};
+use vstutils::sequencer;
+use vstutils::sequencer::Sequencer;
 use vstutils::targetval::{Rate, TargetVal};
+use vstutils::transport::Transport;
+use vstutils::tuning;
+use vstutils::velocity;
+use vstutils::velocity::VelocityCurve;
+use vstutils::voiceallocator::{Release, VoiceAllocator};
+
+use voice::Voice;
+
+#[derive(Clone, Copy, PartialEq)]
+enum BeatMode {
+    Sync,
+    Free,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SeqTarget {
+    Off,
+    Level,
+    Pan,
+}
+
+const NUM_SEQ_TARGETS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum BeatUnits {
+    Hz,
+    Cents,
+}
 
 struct Colliculus {
     host:           HostCallback,
     level:          TargetVal<f32>,
     pan:            TargetVal<f32>,
-    velocity:       TargetVal<f32>,
+    width:          TargetVal<f32>,
+    pan_law:        Law,
+    legacy_pan:     bool,
+    velocity_curve: VelocityCurve,
     division_param: f32,
     division:       Division,
-    tracker:        NoteTracker,
-    osc1:           Oscillator,
-    osc2:           Oscillator,
+    beat_mode:      BeatMode,
+    beat_rate:      f32,
+    beat_units:     BeatUnits,
+    retrigger:      bool,
+    phase_offset:   f32,
+    allocator:      VoiceAllocator,
+    voices:         Vec<Voice>,
+    tuning:         tuning::Selector,
+    pitch:          PitchConverter,
+    octave:         i32,
+    semitone:       i32,
+    mpe:            Mpe,
+    pressure_depth: f32,
+    seq_target:     SeqTarget,
+    seq_div_param:  f32,
+    seq_depth:      f32,
+    sequencer:      Sequencer,
+    modulation:     Modulation,
 }
 
-const ATTACK: f32 = 0.1;
-const DECAY: f32 = 0.1;
+const NUM_PARAMETERS: i32 = 87;
+
+// The parameters which control CC learning, which can't be mapped
+// themselves.
+const CC_PARAMS: [i32; 5] = [15, 16, 17, 18, CC_CLEAR_PARAM];
+
+// Clears every CC mapping when it's pushed.
+const CC_CLEAR_PARAM: i32 = 85;
+
+// How far MPE pressure sets the level. At 0 it's ignored, so controllers
+// which send no pressure are still heard.
+const PRESSURE_PARAM: i32 = 86;
+
+// Learning and clearing act on the map rather than setting anything, so a
+// stray automation write mustn't reach them.
+const CC_ACTIONS: [i32; 2] = [15, CC_CLEAR_PARAM];
+
+const NUM_VOICES: usize = 8;
+
+// Notes held beyond the voices wait for one to come free.
+const EXTRA_NOTES: usize = 9;
+
+const MIN_BEAT_RATE: f32 = 0.01;
+const MAX_BEAT_RATE: f32 = 40.0;
+
+// Master tune covers the reference pitches in common use, from baroque to
+// modern orchestral.
+const MIN_REFERENCE: f32 = 400.0;
+const MAX_REFERENCE: f32 = 480.0;
+const MAX_OCTAVES: i32 = 3;
+const MAX_SEMITONES: i32 = 12;
+const MAX_FINE: f32 = 100.0;
+
+// The value of each sequencer step has a parameter of its own.
+const FIRST_STEP_PARAM: i32 = 24;
+const LAST_STEP_PARAM: i32 = FIRST_STEP_PARAM + sequencer::MAX_STEPS as i32 - 1;
+
+// The modulation matrix's parameters.
+const FIRST_MOD_PARAM: i32 = LAST_STEP_PARAM + 1;
+const LAST_MOD_PARAM: i32 = FIRST_MOD_PARAM + modmatrix::NUM_PARAMETERS - 1;
+
+// The velocity curve's parameters.
+const FIRST_VEL_PARAM: i32 = LAST_MOD_PARAM + 1;
+const LAST_VEL_PARAM: i32 = FIRST_VEL_PARAM + velocity::NUM_PARAMETERS - 1;
+
+// The version of the parameter layout saved in chunks.
+const CHUNK_VERSION: u32 = 1;
+
+// Saves whether Pan still spreads the oscillators the old way. Sessions
+// saved before there was a Width don't have it.
+const LEGACY_PAN_KEY: &str = "legacy_pan";
 
 impl Colliculus {
     fn process_midi_event(&mut self, data: [u8; 3]) {
-        match data[0] {
-            128 => self.note_off(data[1]),
-            144 => self.note_on(data[1], data[2]),
-            _ => (),
+        match data[0] & 0xf0 {
+            // A note on with no velocity is a note off, and never reaches
+            // the velocity curve.
+            144 if data[2] > 0 => self.note_on(data[1], data[2], data[0] & 0x0f),
+            128 | 144          => self.note_off(data[1]),
+            _ => {
+                self.mpe.process_midi_event(data);
+                self.velocity_curve.process_midi_event(data);
+
+                let mut host = self.host;
+                self.process_modulation_event(data, &mut host);
+            },
         }
     }
 
-    fn note_on(&mut self, note: u8, velocity: u8) {
-        self.tracker.note_on(note);
+    fn note_on(&mut self, note: u8, velocity: u8, channel: u8) {
+        self.mpe.note_on(note, channel);
 
-        let target = velocity as f32 / 127.0;
-        self.velocity.set_target(target);
+        // Take up any high resolution prefix even if the note doesn't play.
+        let level = self.velocity_curve.note_on(channel, velocity);
 
-        let time_per_sample = 1.0 / self.osc1.get_sample_rate();
-        self.velocity.set_inc_rate(Rate::Absolute(target
-                                                  * time_per_sample / ATTACK));
-        self.velocity.set_dec_rate(Rate::Absolute(target
-                                                  * time_per_sample / DECAY));
+        // Notes which the tuning leaves out don't play.
+        if self.get_note_frequency(note).is_none() {
+            return;
+        }
+
+        // Without the host's transport to follow, the sequencer starts
+        // again with each new phrase.
+        if self.allocator.is_empty() {
+            self.sequencer.reset();
+        }
+
+        if let Some(index) = self.allocator.note_on(note) {
+            self.modulation.get_matrix_mut().note_on(note, level);
+
+            let gain = self.velocity_curve.get_gain(level);
+            self.voices[index].note_on(note, gain, self.retrigger, self.phase_offset);
+        }
     }
 
     fn note_off(&mut self, note: u8) {
-        self.tracker.note_off(note);
+        match self.allocator.note_off(note) {
+            Some(Release::Free(index))         => self.voices[index].release(),
+            Some(Release::Resume(index, note)) => self.voices[index].set_note(note),
+            None                               => (),
+        }
 
-        if self.get_current_note() == None {
-            self.velocity.set_target(0.0);
+        if self.allocator.is_empty() {
+            self.modulation.get_matrix_mut().note_off();
         }
     }
 
-    fn get_current_note(&self) -> Option<u8> {
-        self.tracker
-            .get_playing_notes()
-            .get(0)
-            .map(|note| note.to_owned())
+    fn get_synced_beats_frequency(&self) -> f32 {
+        Transport::from_host(&self.host).get_frequency(self.division)
+    }
+
+    /// Returns the frequencies of the two oscillators for a note, lower
+    /// first, which beat at the rate the beat settings ask for.
+    fn get_beats_frequencies(&self, f_target: f32) -> (f32, f32) {
+        match (self.beat_mode, self.beat_units) {
+            (BeatMode::Sync, _) =>
+                get_beats_frequencies(f_target, self.get_synced_beats_frequency()),
+            (BeatMode::Free, BeatUnits::Hz) =>
+                get_beats_frequencies(f_target, self.beat_rate),
+            (BeatMode::Free, BeatUnits::Cents) =>
+                get_beats_frequencies_cents(f_target, self.beat_rate),
+        }
+    }
+
+    /// Follows each voice's note with its frequencies and pressure, so that
+    /// every note is bent and pressed on its own.
+    fn update_voices(&mut self) {
+        for index in 0..self.voices.len() {
+            if !self.voices[index].is_active() {
+                continue;
+            }
+
+            let note       = self.voices[index].get_note();
+            let expression = self.mpe.get_note_expression(note);
+            let pressure   = 1.0 - self.pressure_depth * (1.0 - expression.pressure);
+            let beats      = self.get_note_frequency(note)
+                                 .map(|f_target| self.get_beats_frequencies(f_target));
+
+            let voice = &mut self.voices[index];
+            voice.set_pressure(pressure);
+
+            if let Some((f_lower, f_upper)) = beats {
+                voice.set_frequencies(f_lower, f_upper);
+            }
+        }
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.voices[0].get_sample_rate()
+    }
+
+    fn update_transpose(&mut self) {
+        self.pitch.set_transpose((self.octave * 12 + self.semitone) as f32);
+    }
+
+    /// Returns the frequency of a note in the current tuning, after it's
+    /// been bent, transposed and tuned to the reference pitch.
+    fn get_note_frequency(&self, note: u8) -> Option<f32> {
+        let pitch = f32::from(note) + self.mpe.get_note_expression(note).bend;
+
+        self.tuning
+            .get_tuning()
+            .get_pitch_frequency(self.pitch.transpose(pitch))
+            .map(|frequency| self.pitch.retune(frequency))
+    }
+
+    /// Returns the sequencer's modulation, as a gain and an offset to the
+    /// pan position.
+    fn get_sequencer_modulation(&self) -> (f32, f32) {
+        let value = self.sequencer.get_value();
+
+        match self.seq_target {
+            SeqTarget::Off   => (1.0, 0.0),
+            SeqTarget::Level => (1.0 - self.seq_depth * (1.0 - value), 0.0),
+            SeqTarget::Pan   => (1.0, self.seq_depth * (value - 0.5)),
+        }
+    }
+}
+
+impl Modulated for Colliculus {
+    fn get_modulation(&self) -> &Modulation {
+        &self.modulation
+    }
+
+    fn get_modulation_mut(&mut self) -> &mut Modulation {
+        &mut self.modulation
+    }
+
+    /// Changes a parameter without touching its modulation base.
+    fn set_parameter_value(&mut self, index: i32, value: f32) {
+        match index {
+            0 => self.level.set_target(value),
+            1 => self.pan.set_target(value),
+            2 => {
+                self.division_param = value;
+                self.division       = division::get_division(self.division_param)
+            },
+            3 => self.beat_mode  = if value < 0.5 {BeatMode::Sync} else {BeatMode::Free},
+            4 => self.beat_rate  = param_to_log_range(value, MIN_BEAT_RATE, MAX_BEAT_RATE),
+            5 => self.beat_units = if value < 0.5 {BeatUnits::Hz} else {BeatUnits::Cents},
+            6 => self.retrigger  = param_to_bool(value),
+            7 => self.phase_offset = value,
+            8 => self.width.set_target(value),
+            9 => self.pan_law = pan::get_law(value),
+            10 => self.tuning.set_param(value),
+            11 => self.pitch.set_reference(MIN_REFERENCE
+                                           + value * (MAX_REFERENCE - MIN_REFERENCE)),
+            12 => {
+                self.octave = param_to_choice(value, (2 * MAX_OCTAVES + 1) as usize) as i32
+                              - MAX_OCTAVES;
+                self.update_transpose();
+            },
+            13 => {
+                self.semitone = param_to_choice(value, (2 * MAX_SEMITONES + 1) as usize) as i32
+                                - MAX_SEMITONES;
+                self.update_transpose();
+            },
+            14 => self.pitch.set_fine(value * 2.0 * MAX_FINE - MAX_FINE),
+            15 => self.modulation.get_cc_map_mut().set_learn_param(value),
+            16 => self.modulation.get_cc_map_mut().set_learn_min(value),
+            17 => self.modulation.get_cc_map_mut().set_learn_max(value),
+            18 => self.modulation.get_cc_map_mut().set_learn_curve(ccmap::get_curve(value)),
+            19 => self.seq_target = match param_to_choice(value, NUM_SEQ_TARGETS) {
+                0 => SeqTarget::Off,
+                1 => SeqTarget::Level,
+                _ => SeqTarget::Pan,
+            },
+            20 => {
+                self.seq_div_param = value;
+                self.sequencer.set_division(division::get_division(value));
+            },
+            21 => self.sequencer.set_length(sequencer::get_length(value)),
+            22 => self.sequencer.set_glide(value),
+            23 => self.seq_depth = value,
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM =>
+                self.sequencer.set_step_value((index - FIRST_STEP_PARAM) as usize, value),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix_mut().set_parameter(index - FIRST_MOD_PARAM, value),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.set_parameter(index - FIRST_VEL_PARAM, value),
+            CC_CLEAR_PARAM if value >= 0.5 => self.modulation.get_cc_map_mut().clear(),
+            PRESSURE_PARAM => self.pressure_depth = value,
+            _ => (),
+        }
     }
 }
 
@@ -78,21 +341,39 @@ impl Default for Colliculus {
 impl Plugin for Colliculus {
     fn new(host: HostCallback) -> Colliculus {
         Colliculus {
-            host:           host,
+            host,
             level:          TargetVal::new(  Rate::Relative(0.001)
                                            , Rate::Relative(0.001)
                                            , 1.0),
             pan:            TargetVal::new(  Rate::Relative(0.001)
                                            , Rate::Relative(0.001)
                                            , 0.5),
-            velocity:       TargetVal::new(  Rate::Absolute(0.0)
-                                           , Rate::Absolute(0.0)
+            width:          TargetVal::new(  Rate::Relative(0.001)
+                                           , Rate::Relative(0.001)
                                            , 0.0),
+            pan_law:        Law::EqualPower,
+            legacy_pan:     false,
+            velocity_curve: VelocityCurve::new(),
             division_param: 0.0,
             division:       division::get_division(0.0),
-            tracker:        NoteTracker::new(1, 9),
-            osc1:           Oscillator::sine(44100.0),
-            osc2:           Oscillator::sine(44100.0),
+            beat_mode:      BeatMode::Sync,
+            beat_rate:      7.83,
+            beat_units:     BeatUnits::Hz,
+            retrigger:      false,
+            phase_offset:   0.0,
+            allocator:      VoiceAllocator::new(NUM_VOICES, EXTRA_NOTES),
+            voices:         (0..NUM_VOICES).map(|_| Voice::new(44100.0)).collect(),
+            tuning:         tuning::Selector::new(),
+            pitch:          PitchConverter::default(),
+            octave:         0,
+            semitone:       0,
+            mpe:            Mpe::new(),
+            seq_target:     SeqTarget::Off,
+            seq_div_param:  0.9,
+            seq_depth:      1.0,
+            sequencer:      Sequencer::new(),
+            modulation:     Modulation::new(NUM_PARAMETERS, &CC_PARAMS, FIRST_MOD_PARAM, 44100.0),
+            pressure_depth: 0.0,
         }
     }
 
@@ -104,40 +385,75 @@ impl Plugin for Colliculus {
 
             inputs:     0,
             outputs:    2,
-            parameters: 3,
+            parameters: NUM_PARAMETERS,
 
             category:   Category::Synth,
 
+            preset_chunks: true,
+
             // fill in the rest with the default values
             ..Info::default()
         }
     }
 
+    fn can_be_automated(&self, index: i32) -> bool {
+        (0..NUM_PARAMETERS).contains(&index) && !CC_ACTIONS.contains(&index)
+    }
+
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
             CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::ReceiveSysExEvent => Supported::Yes,
             _ => Supported::Maybe,
         }
     }
 
     fn get_parameter(&self, index: i32) -> f32 {
+        // The host sees modulated parameters where it left them.
+        if let Some(base) = self.modulation.get_modulated_base(index) {
+            return base;
+        }
+
         match index {
-            0 => *self.level.get_target() as f32,
-            1 => *self.pan.get_target() as f32,
+            0 => *self.level.get_target(),
+            1 => *self.pan.get_target(),
+            2 => self.division_param,
+            3 => if self.beat_mode == BeatMode::Sync {0.0} else {1.0},
+            4 => log_range_to_param(self.beat_rate, MIN_BEAT_RATE, MAX_BEAT_RATE),
+            5 => if self.beat_units == BeatUnits::Hz {0.0} else {1.0},
+            6 => bool_to_param(self.retrigger),
+            7 => self.phase_offset,
+            8 => *self.width.get_target(),
+            9 => pan::get_param(self.pan_law),
+            10 => self.tuning.get_param(),
+            11 => (self.pitch.get_reference() - MIN_REFERENCE) / (MAX_REFERENCE - MIN_REFERENCE),
+            12 => choice_to_param((self.octave + MAX_OCTAVES) as usize,
+                                  (2 * MAX_OCTAVES + 1) as usize),
+            13 => choice_to_param((self.semitone + MAX_SEMITONES) as usize,
+                                  (2 * MAX_SEMITONES + 1) as usize),
+            14 => (self.pitch.get_fine() + MAX_FINE) / (2.0 * MAX_FINE),
+            15 => self.modulation.get_cc_map().get_learn_param(),
+            16 => self.modulation.get_cc_map().get_learn_min(),
+            17 => self.modulation.get_cc_map().get_learn_max(),
+            18 => ccmap::get_param(self.modulation.get_cc_map().get_learn_curve()),
+            19 => choice_to_param(self.seq_target as usize, NUM_SEQ_TARGETS),
+            20 => self.seq_div_param,
+            21 => sequencer::get_length_param(self.sequencer.get_length()),
+            22 => self.sequencer.get_glide(),
+            23 => self.seq_depth,
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM =>
+                self.sequencer.get_step_value((index - FIRST_STEP_PARAM) as usize),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix().get_parameter(index - FIRST_MOD_PARAM),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter(index - FIRST_VEL_PARAM),
+            PRESSURE_PARAM => self.pressure_depth,
             _ => 0.0,
         }
     }
 
     fn set_parameter(&mut self, index: i32, value: f32) {
-        match index {
-            0 => self.level.set_target(value),
-            1 => self.pan.set_target(value),
-            2 => {
-                self.division_param = value;
-                self.division       = division::get_division(self.division_param)
-            },
-            _ => (),
-        }
+        self.set_modulated_parameter(index, value);
     }
 
     fn get_parameter_name(&self, index: i32) -> String {
@@ -145,6 +461,35 @@ impl Plugin for Colliculus {
             0 => "Level".to_string(),
             1 => "Pan".to_string(),
             2 => "Division".to_string(),
+            3 => "Mode".to_string(),
+            4 => "Rate".to_string(),
+            5 => "Units".to_string(),
+            6 => "Retrigger".to_string(),
+            7 => "Phase".to_string(),
+            8 => "Width".to_string(),
+            9 => "Pan Law".to_string(),
+            10 => "Tuning".to_string(),
+            11 => "Master Tune".to_string(),
+            12 => "Octave".to_string(),
+            13 => "Semitone".to_string(),
+            14 => "Fine".to_string(),
+            15 => "CC Learn".to_string(),
+            16 => "CC Min".to_string(),
+            17 => "CC Max".to_string(),
+            18 => "CC Curve".to_string(),
+            19 => "Seq Target".to_string(),
+            20 => "Seq Division".to_string(),
+            21 => "Seq Steps".to_string(),
+            22 => "Seq Glide".to_string(),
+            23 => "Seq Depth".to_string(),
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM =>
+                format!("Step {}", index - FIRST_STEP_PARAM + 1),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix().get_parameter_name(index - FIRST_MOD_PARAM),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter_name(index - FIRST_VEL_PARAM),
+            CC_CLEAR_PARAM => "CC Clear".to_string(),
+            PRESSURE_PARAM => "Pressure".to_string(),
             _ => "".to_string(),
         }
     }
@@ -155,69 +500,178 @@ impl Plugin for Colliculus {
             0 => format!("{}", self.level.get_target() * 100.0),
             1 => format!("{}", (self.pan.get_target() - 0.5) * 100.0),
             2 => division::get_name(self.division),
+            3 => match self.beat_mode {
+                BeatMode::Sync => "Sync".to_string(),
+                BeatMode::Free => "Free".to_string(),
+            },
+            4 => format!("{:.2}", self.beat_rate),
+            5 => match self.beat_units {
+                BeatUnits::Hz    => "Hz".to_string(),
+                BeatUnits::Cents => "Cents".to_string(),
+            },
+            6 => bool_to_name(self.retrigger),
+            // Convert to degrees
+            7 => format!("{}", self.phase_offset * 360.0),
+            8 => format!("{}", self.width.get_target() * 100.0),
+            9 => pan::get_name(self.pan_law),
+            10 => self.tuning.get_name(),
+            11 => format!("{:.1}", self.pitch.get_reference()),
+            12 => format!("{}", self.octave),
+            13 => format!("{}", self.semitone),
+            14 => format!("{:.1}", self.pitch.get_fine()),
+            15 => match self.modulation.get_cc_map().get_learning() {
+                Some(parameter) => self.get_parameter_name(parameter),
+                None            => "Off".to_string(),
+            },
+            16 => format!("{}", self.modulation.get_cc_map().get_learn_min() * 100.0),
+            17 => format!("{}", self.modulation.get_cc_map().get_learn_max() * 100.0),
+            18 => ccmap::get_name(self.modulation.get_cc_map().get_learn_curve()),
+            19 => match self.seq_target {
+                SeqTarget::Off   => "Off".to_string(),
+                SeqTarget::Level => "Level".to_string(),
+                SeqTarget::Pan   => "Pan".to_string(),
+            },
+            20 => division::get_name(self.sequencer.get_division()),
+            21 => format!("{}", self.sequencer.get_length()),
+            22 => format!("{}", self.sequencer.get_glide() * 100.0),
+            23 => format!("{}", self.seq_depth * 100.0),
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM => format!(
+                "{}", self.sequencer.get_step_value((index - FIRST_STEP_PARAM) as usize) * 100.0),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM => self.modulation.get_matrix().get_parameter_text(
+                index - FIRST_MOD_PARAM, |parameter| self.get_parameter_name(parameter)),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter_text(index - FIRST_VEL_PARAM),
+            CC_CLEAR_PARAM => format!("{}", self.modulation.get_cc_map().get_mappings().len()),
+            PRESSURE_PARAM => format!("{}", self.pressure_depth * 100.0),
+            _ => "".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            0 | 1 | 8 | 16 | 17 | 22 | 23 => "%".to_string(),
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM => "%".to_string(),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix().get_parameter_label(index - FIRST_MOD_PARAM),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter_label(index - FIRST_VEL_PARAM),
+            4 => match self.beat_units {
+                BeatUnits::Hz    => "Hz".to_string(),
+                BeatUnits::Cents => "cents".to_string(),
+            },
+            7 => "deg".to_string(),
+            11 => "Hz".to_string(),
+            14 => "cents".to_string(),
+            CC_CLEAR_PARAM => "mappings".to_string(),
+            PRESSURE_PARAM => "%".to_string(),
             _ => "".to_string(),
         }
     }
 
     fn set_sample_rate(&mut self, rate: f32) {
-        self.osc1.set_sample_rate(rate);
-        self.osc2.set_sample_rate(rate);
+        for voice in self.voices.iter_mut() {
+            voice.set_sample_rate(rate);
+        }
+        self.modulation.get_matrix_mut().set_sample_rate(rate);
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        if *self.velocity.get_value() > 0.0 || self.get_current_note() != None {
-            if let Some(note) = self.get_current_note() {
-                let f_beats = match self.host.get_time_info(TimeInfoFlags::TEMPO_VALID.bits()) {
-                    None            => 120.,
-                    Some(time_info) => {
-                         time_info.tempo as f32
-                    },
-                } * division::get_tempo_multiplier(self.division);
-
-                let f_target           = midi_pitch_to_freq(note);
-                let (f_lower, f_upper) = get_beats_frequencies(f_target, f_beats);
-
-                self.osc1.set_frequency(f_upper);
-                self.osc2.set_frequency(f_lower);
-            }
+        self.sequencer.sync(&Transport::from_host(&self.host), self.get_sample_rate());
+        self.apply_modulation(buffer.samples());
+
+        if self.voices.iter().any(Voice::is_active) {
+            self.update_voices();
 
             let samples = buffer.samples();
             let (_, outputs) = buffer.split();
 
+            // Older sessions keep the linear crossfade they were made with.
+            // Whatever the law, the centre is as loud as that crossfade.
+            let law         = if self.legacy_pan {Law::Linear} else {self.pan_law};
+            let centre_trim = 0.5 / pan::get_gains(law, 0.5).0;
+
             if outputs.len() == 2 {
                 for sample_index in 0..samples {
                     self.level.advance();
                     self.pan.advance();
-                    self.velocity.advance();
+                    self.width.advance();
+                    self.sequencer.advance();
+
+                    let (seq_gain, seq_pan) = self.get_sequencer_modulation();
+                    let gain = self.level.get_value() * seq_gain * centre_trim;
 
-                    let osc1_value = self.osc1.next_sample();
-                    let osc2_value = self.osc2.next_sample();
+                    let mut osc1_value = 0.0;
+                    let mut osc2_value = 0.0;
 
-                    let pan        = self.pan.get_value();
-                    let osc1_left  = 1.0 - pan;
-                    let osc2_left  = pan;
-                    let osc1_right = pan;
-                    let osc2_right = 1.0 - pan;
+                    for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+                        let (osc1_sample, osc2_sample) = voice.next_samples();
+                        osc1_value += osc1_sample;
+                        osc2_value += osc2_sample;
+                    }
+
+                    // The oscillators sit either side of the pan position,
+                    // with osc1 on the left. At full width and centred, each
+                    // ear hears only one of them. In older sessions, Pan
+                    // spreads them from the centre the way it did before
+                    // there was a Width, with osc1 at the pan position and
+                    // osc2 mirroring it.
+                    let (pan, offset) = if self.legacy_pan {
+                        (0.5 + seq_pan, 0.5 - self.pan.get_value())
+                    }
+                    else {
+                        (self.pan.get_value() + seq_pan, self.width.get_value() / 2.0)
+                    };
+                    let (osc1_left, osc1_right) = pan::get_gains(law, pan - offset);
+                    let (osc2_left, osc2_right) = pan::get_gains(law, pan + offset);
 
                     if let Some (left_sample) = outputs.get_mut(0).get_mut(sample_index) {
-                        *left_sample =   (osc1_value * osc1_left + osc2_value * osc2_left)
-                                       * self.level.get_value()
-                                       * self.velocity.get_value();
+                        *left_sample = (osc1_value * osc1_left + osc2_value * osc2_left) * gain;
                     }
                     if let Some (right_sample) = outputs.get_mut(1).get_mut(sample_index) {
-                        *right_sample =   (osc1_value * osc1_right + osc2_value * osc2_right)
-                                        * self.level.get_value()
-                                        * self.velocity.get_value();
+                        *right_sample = (osc1_value * osc1_right + osc2_value * osc2_right) * gain;
                     }
                 }
             }
         }
     }
 
+    fn get_preset_data(&mut self) -> Vec<u8> {
+        let mut chunk = Chunk::new();
+        chunk.set_version(CHUNK_VERSION);
+        chunk.save_parameters(self);
+        chunk.set(LEGACY_PAN_KEY, &self.legacy_pan.to_string());
+        self.tuning.save(&mut chunk);
+        self.modulation.save(&mut chunk);
+        chunk.to_bytes()
+    }
+
+    fn get_bank_data(&mut self) -> Vec<u8> {
+        self.get_preset_data()
+    }
+
+    fn load_preset_data(&mut self, data: &[u8]) {
+        // Load the tuning files first, so that the saved tuning parameter
+        // can choose between them and the presets.
+        let chunk = Chunk::parse(data);
+        self.tuning.load(&chunk);
+        chunk.load_parameters(self);
+        self.modulation.load(&chunk);
+        self.legacy_pan = chunk.get(LEGACY_PAN_KEY) != Some("false");
+    }
+
+    fn load_bank_data(&mut self, data: &[u8]) {
+        self.load_preset_data(data);
+    }
+
     fn process_events(&mut self, events: &Events) {
         for event in events.events() {
             match event {
                 Event::Midi(MidiEvent {data, ..}) => self.process_midi_event(data),
+                Event::SysEx(SysExEvent {payload, ..}) => {
+                    if let Some(message) = mts::parse(payload) {
+                        self.tuning.retune(message);
+                    }
+                },
                 _ => (),
             }
         }
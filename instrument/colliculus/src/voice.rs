@@ -0,0 +1,104 @@
+use vstutils::generator::{Generator, Oscillator};
+use vstutils::targetval::{Rate, TargetVal};
+
+const ATTACK: f32 = 0.1;
+const DECAY: f32 = 0.1;
+
+/// Voice
+///
+/// One note's pair of beating oscillators, with a gain which fades in and
+/// out with the note and follows the note's MPE pressure. A voice keeps its
+/// note once it's released, so that it stays in tune while it fades out.
+pub struct Voice {
+    note:     u8,
+    osc1:     Oscillator,
+    osc2:     Oscillator,
+    velocity: TargetVal<f32>,
+    pressure: TargetVal<f32>,
+}
+
+impl Voice {
+    pub fn new(sample_rate: f32) -> Voice {
+        Voice {
+            note:     0,
+            osc1:     Oscillator::sine(sample_rate),
+            osc2:     Oscillator::sine(sample_rate),
+            velocity: TargetVal::new(  Rate::Absolute(0.0)
+                                     , Rate::Absolute(0.0)
+                                     , 0.0),
+            pressure: TargetVal::new(  Rate::Relative(0.001)
+                                     , Rate::Relative(0.001)
+                                     , 1.0),
+        }
+    }
+
+    pub fn get_note(&self) -> u8 {
+        self.note
+    }
+
+    /// Moves the voice on to another note without starting it again.
+    pub fn set_note(&mut self, note: u8) {
+        self.note = note;
+    }
+
+    pub fn get_sample_rate(&self) -> f32 {
+        self.osc1.get_sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.osc1.set_sample_rate(sample_rate);
+        self.osc2.set_sample_rate(sample_rate);
+    }
+
+    /// Returns whether the voice can be heard.
+    pub fn is_active(&self) -> bool {
+        *self.velocity.get_value() > 0.0 || *self.velocity.get_target() > 0.0
+    }
+
+    /// Starts a note, fading in to `gain`. The oscillators start from a
+    /// known phase relationship so that the beat pattern always begins at
+    /// the same point, unless the voice was still sounding and isn't
+    /// retriggered.
+    pub fn note_on(&mut self, note: u8, gain: f32, retrigger: bool, phase_offset: f32) {
+        if retrigger || *self.velocity.get_value() <= 0.0 {
+            self.osc1.reset();
+            self.osc2.set_phase(phase_offset);
+        }
+
+        self.note = note;
+        self.velocity.set_target(gain);
+
+        let time_per_sample = 1.0 / self.get_sample_rate();
+        self.velocity.set_inc_rate(Rate::Absolute(gain
+                                                  * time_per_sample / ATTACK));
+        self.velocity.set_dec_rate(Rate::Absolute(gain
+                                                  * time_per_sample / DECAY));
+    }
+
+    pub fn release(&mut self) {
+        self.velocity.set_target(0.0);
+    }
+
+    /// Sets the gain which the note's pressure gives the voice.
+    pub fn set_pressure(&mut self, pressure: f32) {
+        self.pressure.set_target(pressure);
+    }
+
+    /// Sets the frequencies of the two oscillators, with osc1 at the upper
+    /// one.
+    pub fn set_frequencies(&mut self, lower: f32, upper: f32) {
+        self.osc1.set_frequency(upper);
+        self.osc2.set_frequency(lower);
+    }
+
+    /// Returns the next sample of each oscillator, with the voice's gain
+    /// applied.
+    pub fn next_samples(&mut self) -> (f32, f32) {
+        self.velocity.advance();
+        self.pressure.advance();
+
+        let gain = self.velocity.get_value() * self.pressure.get_value();
+
+        (self.osc1.next_sample() * gain, self.osc2.next_sample() * gain)
+    }
+}
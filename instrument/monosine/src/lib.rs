@@ -3,80 +3,434 @@
 #[macro_use] extern crate vst;
 extern crate vstutils;
 
+mod voice;
+
 use vst::api::{Events, Supported};
 use vst::buffer::AudioBuffer;
 use vst::event::Event;
-use vst::plugin::{Category, CanDo, Info, Plugin};
+use vst::plugin::{Category, CanDo, HostCallback, Info, Plugin};
 
-use vstutils::generator::{Generator, Oscillator};
-use vstutils::maths::{midi_pitch_to_freq};
-use vstutils::notetracker::NoteTracker;
+use vstutils::ccmap;
+use vstutils::chunk::Chunk;
+use vstutils::division;
+use vstutils::maths::{log_range_to_param, param_to_log_range, PitchConverter};
+use vstutils::modmatrix;
+use vstutils::modulation::{Modulated, Modulation};
+use vstutils::mpe::Mpe;
+use vstutils::mts;
+use vstutils::param::{bool_to_name, bool_to_param, choice_to_param, param_to_bool,
+                      param_to_choice};
+use vstutils::sequencer;
+use vstutils::sequencer::Sequencer;
 use vstutils::targetval::{Rate, TargetVal};
+use vstutils::transport::Transport;
+use vstutils::tuning;
+use vstutils::velocity;
+use vstutils::velocity::VelocityCurve;
+use vstutils::unison;
+use vstutils::voiceallocator::{Release, VoiceAllocator};
+
+use voice::{Settings, Voice};
+
+#[derive(Clone, Copy, PartialEq)]
+enum VoiceMode {
+    Sine,
+    Sync,
+    Fm,
+}
+
+const NUM_VOICE_MODES: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FilterType {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    Ladder,
+}
+
+const NUM_FILTER_TYPES: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum SeqTarget {
+    Off,
+    Pitch,
+    Filter,
+}
+
+const NUM_SEQ_TARGETS: usize = 3;
 
 struct MonoSine {
-    level:      TargetVal<f32>,
-    velocity:   TargetVal<f32>,
-    tracker:    NoteTracker,
-    oscillator: Oscillator,
+    host:            HostCallback,
+    level:           TargetVal<f32>,
+    velocity_curve:  VelocityCurve,
+    retrigger:       bool,
+    voice_mode:      VoiceMode,
+    allocator:       VoiceAllocator,
+    voices:          Vec<Voice>,
+    sync_ratio:      f32,
+    filter_type:     FilterType,
+    cutoff:          TargetVal<f32>,
+    resonance:       TargetVal<f32>,
+    env_amount:      f32,
+    key_track:       f32,
+    filter_velocity: f32,
+    tuning:          tuning::Selector,
+    pitch:           PitchConverter,
+    octave:          i32,
+    semitone:        i32,
+    mpe:             Mpe,
+    pressure_depth:  f32,
+    seq_target:      SeqTarget,
+    seq_div_param:   f32,
+    seq_depth:       f32,
+    sequencer:       Sequencer,
+    modulation:      Modulation,
 }
 
-const ATTACK: f32 = 0.1;
-const DECAY: f32 = 0.1;
+const NUM_PARAMETERS: i32 = 97;
+
+// The parameters which control CC learning, which can't be mapped
+// themselves.
+const CC_PARAMS: [i32; 5] = [24, 25, 26, 27, CC_CLEAR_PARAM];
+
+// Clears every CC mapping when it's pushed.
+const CC_CLEAR_PARAM: i32 = 94;
+
+// How far MPE pressure sets the level. At 0 it's ignored, so controllers
+// which send no pressure are still heard.
+const PRESSURE_PARAM: i32 = 95;
+
+// The pitch of the synced oscillator, relative to the note.
+const SYNC_PARAM: i32 = 96;
+
+// Learning and clearing act on the map rather than setting anything, so a
+// stray automation write mustn't reach them.
+const CC_ACTIONS: [i32; 2] = [24, CC_CLEAR_PARAM];
+
+const NUM_VOICES: usize = 8;
+
+// Notes held beyond the voices wait for one to come free.
+const EXTRA_NOTES: usize = 9;
+
+const MIN_FM_RATIO: f32 = 0.25;
+const MAX_FM_RATIO: f32 = 16.0;
+const MAX_FM_INDEX: f32 = 10.0;
+const MAX_FM_FEEDBACK: f32 = 1.5;
+
+const MAX_DETUNE: f32 = 100.0;
+
+const MIN_SYNC_RATIO: f32 = 1.0;
+const MAX_SYNC_RATIO: f32 = 8.0;
+
+const MIN_CUTOFF: f32 = 20.0;
+const MAX_CUTOFF: f32 = 20000.0;
+
+const MIN_ENV_TIME: f32 = 0.001;
+const MAX_ENV_TIME: f32 = 10.0;
+
+// Master tune covers the reference pitches in common use, from baroque to
+// modern orchestral.
+const MIN_REFERENCE: f32 = 400.0;
+const MAX_REFERENCE: f32 = 480.0;
+const MAX_OCTAVES: i32 = 3;
+const MAX_SEMITONES: i32 = 12;
+const MAX_FINE: f32 = 100.0;
+
+// At full depth, the sequencer moves the pitch and cutoff this far either
+// way.
+const MAX_SEQ_SEMITONES: f32 = 12.0;
+const MAX_SEQ_OCTAVES: f32 = 4.0;
+
+// The value of each sequencer step has a parameter of its own.
+const FIRST_STEP_PARAM: i32 = 33;
+const LAST_STEP_PARAM: i32 = FIRST_STEP_PARAM + sequencer::MAX_STEPS as i32 - 1;
+
+// The modulation matrix's parameters.
+const FIRST_MOD_PARAM: i32 = LAST_STEP_PARAM + 1;
+const LAST_MOD_PARAM: i32 = FIRST_MOD_PARAM + modmatrix::NUM_PARAMETERS - 1;
+
+// The velocity curve's parameters.
+const FIRST_VEL_PARAM: i32 = LAST_MOD_PARAM + 1;
+const LAST_VEL_PARAM: i32 = FIRST_VEL_PARAM + velocity::NUM_PARAMETERS - 1;
+
+// The version of the parameter layout saved in chunks.
+const CHUNK_VERSION: u32 = 1;
 
 impl MonoSine {
     fn process_midi_event(&mut self, data: [u8; 3]) {
-        match data[0] {
-            128 => self.note_off(data[1]),
-            144 => self.note_on(data[1], data[2]),
-            _ => (),
+        match data[0] & 0xf0 {
+            // A note on with no velocity is a note off, and never reaches
+            // the velocity curve.
+            144 if data[2] > 0 => self.note_on(data[1], data[2], data[0] & 0x0f),
+            128 | 144          => self.note_off(data[1]),
+            _ => {
+                self.mpe.process_midi_event(data);
+                self.velocity_curve.process_midi_event(data);
+
+                let mut host = self.host;
+                self.process_modulation_event(data, &mut host);
+            },
         }
     }
 
-    fn note_on(&mut self, note: u8, velocity: u8) {
-        self.tracker.note_on(note);
+    fn note_on(&mut self, note: u8, velocity: u8, channel: u8) {
+        self.mpe.note_on(note, channel);
+
+        // Take up any high resolution prefix even if the note doesn't play.
+        let level = self.velocity_curve.note_on(channel, velocity);
 
-        let target = velocity as f32 / 127.0;
-        self.velocity.set_target(target);
+        // Notes which the tuning leaves out don't play.
+        if self.get_note_frequency(note).is_none() {
+            return;
+        }
 
-        let time_per_sample = 1.0 / self.oscillator.get_sample_rate();
-        self.velocity.set_inc_rate(Rate::Absolute(target
-                                                  * time_per_sample / ATTACK));
-        self.velocity.set_dec_rate(Rate::Absolute(target
-                                                  * time_per_sample / DECAY));
+        // Without the host's transport to follow, the sequencer starts
+        // again with each new phrase.
+        if self.allocator.is_empty() {
+            self.sequencer.reset();
+        }
+
+        if let Some(index) = self.allocator.note_on(note) {
+            self.modulation.get_matrix_mut().note_on(note, level);
+
+            let gain = self.velocity_curve.get_gain(level);
+            self.voices[index].note_on(note, level, gain, self.retrigger);
+        }
     }
 
     fn note_off(&mut self, note: u8) {
-        self.tracker.note_off(note);
+        match self.allocator.note_off(note) {
+            Some(Release::Free(index))         => self.voices[index].release(),
+            Some(Release::Resume(index, note)) => self.voices[index].set_note(note),
+            None                               => (),
+        }
+
+        if self.allocator.is_empty() {
+            self.modulation.get_matrix_mut().note_off();
+        }
+    }
+
+    /// Returns the sequencer's modulation of a target, between -1 and 1.
+    fn get_sequencer_offset(&self, target: SeqTarget) -> f32 {
+        if self.seq_target == target {
+            (self.sequencer.get_value() - 0.5) * 2.0 * self.seq_depth
+        }
+        else {
+            0.0
+        }
+    }
+
+    // Every voice has the same settings, so any of them can report them.
+    fn get_voice(&self) -> &Voice {
+        &self.voices[0]
+    }
+
+    /// Changes a setting on every voice.
+    fn for_each_voice<F: FnMut(&mut Voice)>(&mut self, mut update: F) {
+        for voice in self.voices.iter_mut() {
+            update(voice);
+        }
+    }
+
+    /// Follows each voice's note with its frequency, pressure and timbre,
+    /// so that every note is bent and pressed on its own.
+    fn update_voices(&mut self) {
+        for index in 0..self.voices.len() {
+            if !self.voices[index].is_active() {
+                continue;
+            }
+
+            let note       = self.voices[index].get_note();
+            let expression = self.mpe.get_note_expression(note);
+            let pressure   = 1.0 - self.pressure_depth * (1.0 - expression.pressure);
+            let frequency  = self.get_note_frequency(note);
+            let sync_ratio = self.sync_ratio;
 
-        if self.get_current_note() == None {
-            self.velocity.set_target(0.0);
+            let voice = &mut self.voices[index];
+            voice.set_expression(pressure, expression.timbre);
+
+            if let Some(frequency) = frequency {
+                voice.set_frequency(frequency, sync_ratio);
+            }
         }
     }
 
-    fn get_current_note(&self) -> Option<u8> {
-        self.tracker
-            .get_playing_notes()
-            .get(0)
-            .map(|note| note.to_owned())
+    fn get_sample_rate(&self) -> f32 {
+        self.get_voice().get_sample_rate()
+    }
+
+    fn update_transpose(&mut self) {
+        self.pitch.set_transpose((self.octave * 12 + self.semitone) as f32);
+    }
+
+    /// Returns the frequency of a note in the current tuning, after it's
+    /// been bent, transposed and tuned to the reference pitch.
+    fn get_note_frequency(&self, note: u8) -> Option<f32> {
+        let pitch = f32::from(note)
+                  + self.mpe.get_note_expression(note).bend
+                  + self.get_sequencer_offset(SeqTarget::Pitch) * MAX_SEQ_SEMITONES;
+
+        self.tuning
+            .get_tuning()
+            .get_pitch_frequency(self.pitch.transpose(pitch))
+            .map(|frequency| self.pitch.retune(frequency))
+    }
+}
+
+impl Modulated for MonoSine {
+    fn get_modulation(&self) -> &Modulation {
+        &self.modulation
+    }
+
+    fn get_modulation_mut(&mut self) -> &mut Modulation {
+        &mut self.modulation
+    }
+
+    /// Changes a parameter without touching its modulation base.
+    fn set_parameter_value(&mut self, index: i32, value: f32) {
+        match index {
+            0 => self.level.set_target(value),
+            1 => self.retrigger = param_to_bool(value),
+            2 => self.voice_mode = match param_to_choice(value, NUM_VOICE_MODES) {
+                0 => VoiceMode::Sine,
+                1 => VoiceMode::Sync,
+                _ => VoiceMode::Fm,
+            },
+            3 => {
+                let ratio = param_to_log_range(value, MIN_FM_RATIO, MAX_FM_RATIO);
+                self.for_each_voice(|voice| voice.get_modulator_mut().set_ratio(ratio));
+            },
+            4 => self.for_each_voice(|voice| voice.get_modulator_mut()
+                                                  .set_index(value * MAX_FM_INDEX)),
+            5 => self.for_each_voice(|voice| voice.get_modulator_mut()
+                                                  .set_feedback(value * MAX_FM_FEEDBACK)),
+            6 => {
+                let num_voices = 1 + (value * (unison::MAX_VOICES - 1) as f32).round() as usize;
+                self.for_each_voice(|voice| voice.get_unison_mut().set_num_voices(num_voices));
+            },
+            7 => self.for_each_voice(|voice| voice.get_unison_mut().set_detune(value * MAX_DETUNE)),
+            8 => self.for_each_voice(|voice| voice.get_unison_mut().set_spread(value)),
+            9 => self.cutoff.set_target(param_to_log_range(value, MIN_CUTOFF, MAX_CUTOFF)),
+            10 => self.resonance.set_target(value),
+            11 => self.filter_type = match param_to_choice(value, NUM_FILTER_TYPES) {
+                0 => FilterType::Lowpass,
+                1 => FilterType::Highpass,
+                2 => FilterType::Bandpass,
+                3 => FilterType::Notch,
+                _ => FilterType::Ladder,
+            },
+            12 => self.env_amount = value * 2.0 - 1.0,
+            13 => self.key_track = value,
+            14 => {
+                let attack = param_to_log_range(value, MIN_ENV_TIME, MAX_ENV_TIME);
+                self.for_each_voice(|voice| voice.get_filter_env_mut().set_attack(attack));
+            },
+            15 => {
+                let decay = param_to_log_range(value, MIN_ENV_TIME, MAX_ENV_TIME);
+                self.for_each_voice(|voice| voice.get_filter_env_mut().set_decay(decay));
+            },
+            16 => self.for_each_voice(|voice| voice.get_filter_env_mut().set_sustain(value)),
+            17 => {
+                let release = param_to_log_range(value, MIN_ENV_TIME, MAX_ENV_TIME);
+                self.for_each_voice(|voice| voice.get_filter_env_mut().set_release(release));
+            },
+            18 => self.filter_velocity = value,
+            19 => self.tuning.set_param(value),
+            20 => self.pitch.set_reference(MIN_REFERENCE
+                                           + value * (MAX_REFERENCE - MIN_REFERENCE)),
+            21 => {
+                self.octave = param_to_choice(value, (2 * MAX_OCTAVES + 1) as usize) as i32
+                              - MAX_OCTAVES;
+                self.update_transpose();
+            },
+            22 => {
+                self.semitone = param_to_choice(value, (2 * MAX_SEMITONES + 1) as usize) as i32
+                                - MAX_SEMITONES;
+                self.update_transpose();
+            },
+            23 => self.pitch.set_fine(value * 2.0 * MAX_FINE - MAX_FINE),
+            24 => self.modulation.get_cc_map_mut().set_learn_param(value),
+            25 => self.modulation.get_cc_map_mut().set_learn_min(value),
+            26 => self.modulation.get_cc_map_mut().set_learn_max(value),
+            27 => self.modulation.get_cc_map_mut().set_learn_curve(ccmap::get_curve(value)),
+            28 => self.seq_target = match param_to_choice(value, NUM_SEQ_TARGETS) {
+                0 => SeqTarget::Off,
+                1 => SeqTarget::Pitch,
+                _ => SeqTarget::Filter,
+            },
+            29 => {
+                self.seq_div_param = value;
+                self.sequencer.set_division(division::get_division(value));
+            },
+            30 => self.sequencer.set_length(sequencer::get_length(value)),
+            31 => self.sequencer.set_glide(value),
+            32 => self.seq_depth = value,
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM =>
+                self.sequencer.set_step_value((index - FIRST_STEP_PARAM) as usize, value),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix_mut().set_parameter(index - FIRST_MOD_PARAM, value),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.set_parameter(index - FIRST_VEL_PARAM, value),
+            CC_CLEAR_PARAM if value >= 0.5 => self.modulation.get_cc_map_mut().clear(),
+            PRESSURE_PARAM => self.pressure_depth = value,
+            SYNC_PARAM => {
+                let sync_ratio = param_to_log_range(value, MIN_SYNC_RATIO, MAX_SYNC_RATIO);
+                self.sync_ratio = sync_ratio;
+                self.for_each_voice(|voice| voice.set_sync_ratio(sync_ratio));
+            },
+            _ => (),
+        }
     }
 }
 
 impl Default for MonoSine {
     fn default() -> MonoSine {
         MonoSine {
-            level:      TargetVal::new(  Rate::Relative(0.001)
-                                       , Rate::Relative(0.001)
-                                       , 1.0),
-            velocity:   TargetVal::new(  Rate::Absolute(0.0)
-                                       , Rate::Absolute(0.0)
-                                       , 0.0),
-            tracker:    NoteTracker::new(1, 9),
-            oscillator: Oscillator::sine(44100.0),
+            host:            Default::default(),
+            level:           TargetVal::new(  Rate::Relative(0.001)
+                                            , Rate::Relative(0.001)
+                                            , 1.0),
+            velocity_curve:  VelocityCurve::new(),
+            retrigger:       false,
+            voice_mode:      VoiceMode::Sine,
+            allocator:       VoiceAllocator::new(NUM_VOICES, EXTRA_NOTES),
+            voices:          (0..NUM_VOICES).map(|_| Voice::new(44100.0)).collect(),
+            sync_ratio:      MIN_SYNC_RATIO,
+            filter_type:     FilterType::Lowpass,
+            cutoff:          TargetVal::new(  Rate::Relative(0.001)
+                                            , Rate::Relative(0.001)
+                                            , MAX_CUTOFF),
+            resonance:       TargetVal::new(  Rate::Relative(0.001)
+                                            , Rate::Relative(0.001)
+                                            , 0.0),
+            env_amount:      0.0,
+            key_track:       0.0,
+            filter_velocity: 0.0,
+            tuning:          tuning::Selector::new(),
+            pitch:           PitchConverter::default(),
+            octave:          0,
+            semitone:        0,
+            mpe:             Mpe::new(),
+            seq_target:      SeqTarget::Off,
+            seq_div_param:   0.9,
+            seq_depth:       1.0,
+            sequencer:       Sequencer::new(),
+            modulation:      Modulation::new(NUM_PARAMETERS, &CC_PARAMS, FIRST_MOD_PARAM, 44100.0),
+            pressure_depth:  0.0,
         }
     }
 }
 
 impl Plugin for MonoSine {
+    fn new(host: HostCallback) -> MonoSine {
+        MonoSine {
+            host,
+            ..MonoSine::default()
+        }
+    }
+
     fn get_info(&self) -> Info {
         Info {
             name:       "MonoSine".to_string(),
@@ -85,39 +439,136 @@ impl Plugin for MonoSine {
 
             inputs:     0,
             outputs:    2,
-            parameters: 1,
+            parameters: NUM_PARAMETERS,
 
             category:   Category::Synth,
 
+            preset_chunks: true,
+
             // fill in the rest with the default values
             ..Info::default()
         }
     }
 
+    fn can_be_automated(&self, index: i32) -> bool {
+        (0..NUM_PARAMETERS).contains(&index) && !CC_ACTIONS.contains(&index)
+    }
+
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
             CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::ReceiveSysExEvent => Supported::Yes,
             _ => Supported::Maybe,
         }
     }
 
     fn get_parameter(&self, index: i32) -> f32 {
+        // The host sees modulated parameters where it left them.
+        if let Some(base) = self.modulation.get_modulated_base(index) {
+            return base;
+        }
+
         match index {
-            0 => *self.level.get_target() as f32,
+            0 => *self.level.get_target(),
+            1 => bool_to_param(self.retrigger),
+            2 => choice_to_param(self.voice_mode as usize, NUM_VOICE_MODES),
+            3 => log_range_to_param(self.get_voice().get_modulator().get_ratio(),
+                                    MIN_FM_RATIO, MAX_FM_RATIO),
+            4 => self.get_voice().get_modulator().get_index() / MAX_FM_INDEX,
+            5 => self.get_voice().get_modulator().get_feedback() / MAX_FM_FEEDBACK,
+            6 => (self.get_voice().get_unison().get_num_voices() - 1) as f32
+                 / (unison::MAX_VOICES - 1) as f32,
+            7 => self.get_voice().get_unison().get_detune() / MAX_DETUNE,
+            8 => self.get_voice().get_unison().get_spread(),
+            9 => log_range_to_param(*self.cutoff.get_target(), MIN_CUTOFF, MAX_CUTOFF),
+            10 => *self.resonance.get_target(),
+            11 => choice_to_param(self.filter_type as usize, NUM_FILTER_TYPES),
+            12 => (self.env_amount + 1.0) / 2.0,
+            13 => self.key_track,
+            14 => log_range_to_param(self.get_voice().get_filter_env().get_attack(),
+                                     MIN_ENV_TIME, MAX_ENV_TIME),
+            15 => log_range_to_param(self.get_voice().get_filter_env().get_decay(),
+                                     MIN_ENV_TIME, MAX_ENV_TIME),
+            16 => self.get_voice().get_filter_env().get_sustain(),
+            17 => log_range_to_param(self.get_voice().get_filter_env().get_release(),
+                                     MIN_ENV_TIME, MAX_ENV_TIME),
+            18 => self.filter_velocity,
+            19 => self.tuning.get_param(),
+            20 => (self.pitch.get_reference() - MIN_REFERENCE) / (MAX_REFERENCE - MIN_REFERENCE),
+            21 => choice_to_param((self.octave + MAX_OCTAVES) as usize,
+                                  (2 * MAX_OCTAVES + 1) as usize),
+            22 => choice_to_param((self.semitone + MAX_SEMITONES) as usize,
+                                  (2 * MAX_SEMITONES + 1) as usize),
+            23 => (self.pitch.get_fine() + MAX_FINE) / (2.0 * MAX_FINE),
+            24 => self.modulation.get_cc_map().get_learn_param(),
+            25 => self.modulation.get_cc_map().get_learn_min(),
+            26 => self.modulation.get_cc_map().get_learn_max(),
+            27 => ccmap::get_param(self.modulation.get_cc_map().get_learn_curve()),
+            28 => choice_to_param(self.seq_target as usize, NUM_SEQ_TARGETS),
+            29 => self.seq_div_param,
+            30 => sequencer::get_length_param(self.sequencer.get_length()),
+            31 => self.sequencer.get_glide(),
+            32 => self.seq_depth,
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM =>
+                self.sequencer.get_step_value((index - FIRST_STEP_PARAM) as usize),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix().get_parameter(index - FIRST_MOD_PARAM),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter(index - FIRST_VEL_PARAM),
+            PRESSURE_PARAM => self.pressure_depth,
+            SYNC_PARAM => log_range_to_param(self.sync_ratio, MIN_SYNC_RATIO, MAX_SYNC_RATIO),
             _ => 0.0,
         }
     }
 
     fn set_parameter(&mut self, index: i32, value: f32) {
-        match index {
-            0 => self.level.set_target(value),
-            _ => (),
-        }
+        self.set_modulated_parameter(index, value);
     }
 
     fn get_parameter_name(&self, index: i32) -> String {
         match index {
             0 => "Level".to_string(),
+            1 => "Retrigger".to_string(),
+            2 => "Voice".to_string(),
+            3 => "FM Ratio".to_string(),
+            4 => "FM Index".to_string(),
+            5 => "FM Feedback".to_string(),
+            6 => "Voices".to_string(),
+            7 => "Detune".to_string(),
+            8 => "Spread".to_string(),
+            9 => "Cutoff".to_string(),
+            10 => "Resonance".to_string(),
+            11 => "Filter Type".to_string(),
+            12 => "Env Amount".to_string(),
+            13 => "Key Track".to_string(),
+            14 => "Filter Attack".to_string(),
+            15 => "Filter Decay".to_string(),
+            16 => "Filter Sustain".to_string(),
+            17 => "Filter Release".to_string(),
+            18 => "Filter Velocity".to_string(),
+            19 => "Tuning".to_string(),
+            20 => "Master Tune".to_string(),
+            21 => "Octave".to_string(),
+            22 => "Semitone".to_string(),
+            23 => "Fine".to_string(),
+            24 => "CC Learn".to_string(),
+            25 => "CC Min".to_string(),
+            26 => "CC Max".to_string(),
+            27 => "CC Curve".to_string(),
+            28 => "Seq Target".to_string(),
+            29 => "Seq Division".to_string(),
+            30 => "Seq Steps".to_string(),
+            31 => "Seq Glide".to_string(),
+            32 => "Seq Depth".to_string(),
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM =>
+                format!("Step {}", index - FIRST_STEP_PARAM + 1),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM =>
+                self.modulation.get_matrix().get_parameter_name(index - FIRST_MOD_PARAM),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter_name(index - FIRST_VEL_PARAM),
+            CC_CLEAR_PARAM => "CC Clear".to_string(),
+            PRESSURE_PARAM => "Pressure".to_string(),
+            SYNC_PARAM => "Sync Ratio".to_string(),
             _ => "".to_string(),
         }
     }
@@ -126,46 +577,176 @@ impl Plugin for MonoSine {
         match index {
             // Convert to a percentage
             0 => format!("{}", self.level.get_target() * 100.0),
+            1 => bool_to_name(self.retrigger),
+            2 => match self.voice_mode {
+                VoiceMode::Sine => "Sine".to_string(),
+                VoiceMode::Sync => "Sync".to_string(),
+                VoiceMode::Fm   => "FM".to_string(),
+            },
+            3 => format!("{:.2}", self.get_voice().get_modulator().get_ratio()),
+            4 => format!("{:.2}", self.get_voice().get_modulator().get_index()),
+            5 => format!("{:.2}", self.get_voice().get_modulator().get_feedback()),
+            6 => format!("{}", self.get_voice().get_unison().get_num_voices()),
+            7 => format!("{:.1}", self.get_voice().get_unison().get_detune()),
+            8 => format!("{}", self.get_voice().get_unison().get_spread() * 100.0),
+            9 => format!("{:.0}", self.cutoff.get_target()),
+            10 => format!("{}", self.resonance.get_target() * 100.0),
+            11 => match self.filter_type {
+                FilterType::Lowpass  => "Low-pass".to_string(),
+                FilterType::Highpass => "High-pass".to_string(),
+                FilterType::Bandpass => "Band-pass".to_string(),
+                FilterType::Notch    => "Notch".to_string(),
+                FilterType::Ladder   => "Ladder".to_string(),
+            },
+            12 => format!("{}", self.env_amount * 100.0),
+            13 => format!("{}", self.key_track * 100.0),
+            14 => format!("{:.3}", self.get_voice().get_filter_env().get_attack()),
+            15 => format!("{:.3}", self.get_voice().get_filter_env().get_decay()),
+            16 => format!("{}", self.get_voice().get_filter_env().get_sustain() * 100.0),
+            17 => format!("{:.3}", self.get_voice().get_filter_env().get_release()),
+            18 => format!("{}", self.filter_velocity * 100.0),
+            19 => self.tuning.get_name(),
+            20 => format!("{:.1}", self.pitch.get_reference()),
+            21 => format!("{}", self.octave),
+            22 => format!("{}", self.semitone),
+            23 => format!("{:.1}", self.pitch.get_fine()),
+            24 => match self.modulation.get_cc_map().get_learning() {
+                Some(parameter) => self.get_parameter_name(parameter),
+                None            => "Off".to_string(),
+            },
+            25 => format!("{}", self.modulation.get_cc_map().get_learn_min() * 100.0),
+            26 => format!("{}", self.modulation.get_cc_map().get_learn_max() * 100.0),
+            27 => ccmap::get_name(self.modulation.get_cc_map().get_learn_curve()),
+            28 => match self.seq_target {
+                SeqTarget::Off    => "Off".to_string(),
+                SeqTarget::Pitch  => "Pitch".to_string(),
+                SeqTarget::Filter => "Filter".to_string(),
+            },
+            29 => division::get_name(self.sequencer.get_division()),
+            30 => format!("{}", self.sequencer.get_length()),
+            31 => format!("{}", self.sequencer.get_glide() * 100.0),
+            32 => format!("{}", self.seq_depth * 100.0),
+            // Steps are shown as offsets either side of the centre.
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM => format!(
+                "{:.0}",
+                (self.sequencer.get_step_value((index - FIRST_STEP_PARAM) as usize) - 0.5) * 200.0),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM => self.modulation.get_matrix().get_parameter_text(
+                index - FIRST_MOD_PARAM, |parameter| self.get_parameter_name(parameter)),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM =>
+                self.velocity_curve.get_parameter_text(index - FIRST_VEL_PARAM),
+            CC_CLEAR_PARAM => format!("{}", self.modulation.get_cc_map().get_mappings().len()),
+            PRESSURE_PARAM => format!("{}", self.pressure_depth * 100.0),
+            SYNC_PARAM => format!("{:.2}", self.sync_ratio),
             _ => "".to_string(),
         }
     }
 
-    fn get_parameter_label(&self, _: i32) -> String {
-        "".to_string()
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            7 | 23                                         => "cents".to_string(),
+            8 | 10 | 12 | 13 | 16 | 18 | 25 | 26 | 31 | 32 => "%".to_string(),
+            FIRST_STEP_PARAM..=LAST_STEP_PARAM             => "%".to_string(),
+            FIRST_MOD_PARAM..=LAST_MOD_PARAM               =>
+                self.modulation.get_matrix().get_parameter_label(index - FIRST_MOD_PARAM),
+            FIRST_VEL_PARAM..=LAST_VEL_PARAM               =>
+                self.velocity_curve.get_parameter_label(index - FIRST_VEL_PARAM),
+            9 | 20                                         => "Hz".to_string(),
+            14 | 15 | 17                                   => "s".to_string(),
+            CC_CLEAR_PARAM                                 => "mappings".to_string(),
+            PRESSURE_PARAM                                 => "%".to_string(),
+            _                                              => "".to_string(),
+        }
     }
 
     fn set_sample_rate(&mut self, rate: f32) {
-        self.oscillator.set_sample_rate(rate);
+        self.for_each_voice(|voice| voice.set_sample_rate(rate));
+        self.modulation.get_matrix_mut().set_sample_rate(rate);
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
-        if *self.velocity.get_value() > 0.0 || self.get_current_note() != None {
-            if let Some(note) = self.get_current_note() {
-                self.oscillator.set_frequency(midi_pitch_to_freq(note));
-            }
+        self.sequencer.sync(&Transport::from_host(&self.host), self.get_sample_rate());
+        self.apply_modulation(buffer.samples());
+
+        if self.voices.iter().any(Voice::is_active) {
+            self.update_voices();
 
             let samples = buffer.samples();
             let (_, outputs) = buffer.split();
 
             for sample_index in 0..samples {
                 self.level.advance();
-                self.velocity.advance();
+                self.cutoff.advance();
+                self.resonance.advance();
+                self.sequencer.advance();
+
+                let settings = Settings {
+                    voice_mode:      self.voice_mode,
+                    filter_type:     self.filter_type,
+                    cutoff:          *self.cutoff.get_value(),
+                    resonance:       *self.resonance.get_value(),
+                    env_amount:      self.env_amount,
+                    key_track:       self.key_track,
+                    filter_velocity: self.filter_velocity,
+                    seq_octaves:     self.get_sequencer_offset(SeqTarget::Filter)
+                                     * MAX_SEQ_OCTAVES,
+                };
+
+                let mut left  = 0.0;
+                let mut right = 0.0;
 
-                for output_buffer in outputs {
+                for voice in self.voices.iter_mut().filter(|voice| voice.is_active()) {
+                    let (voice_left, voice_right) = voice.process(&settings);
+                    left  += voice_left;
+                    right += voice_right;
+                }
+
+                let gain = *self.level.get_value();
+
+                for (output_index, output_buffer) in outputs.into_iter().enumerate() {
                     if let Some(output_sample) = output_buffer.get_mut(sample_index) {
-                        *output_sample = (  self.oscillator.next_sample()
-                                          * self.level.get_value()
-                                          * self.velocity.get_value()) as f32;
+                        let value = if output_index % 2 == 0 {left} else {right};
+                        *output_sample = value * gain;
                     }
                 }
             }
         }
     }
 
+    fn get_preset_data(&mut self) -> Vec<u8> {
+        let mut chunk = Chunk::new();
+        chunk.set_version(CHUNK_VERSION);
+        chunk.save_parameters(self);
+        self.tuning.save(&mut chunk);
+        self.modulation.save(&mut chunk);
+        chunk.to_bytes()
+    }
+
+    fn get_bank_data(&mut self) -> Vec<u8> {
+        self.get_preset_data()
+    }
+
+    fn load_preset_data(&mut self, data: &[u8]) {
+        // Load the tuning files first, so that the saved tuning parameter
+        // can choose between them and the presets.
+        let chunk = Chunk::parse(data);
+        self.tuning.load(&chunk);
+        chunk.load_parameters(self);
+        self.modulation.load(&chunk);
+    }
+
+    fn load_bank_data(&mut self, data: &[u8]) {
+        self.load_preset_data(data);
+    }
+
     fn process_events(&mut self, events: &Events) {
         for event in events.events() {
             match event {
                 Event::Midi(ev) => self.process_midi_event(ev.data),
+                Event::SysEx(ev) => {
+                    if let Some(message) = mts::parse(ev.payload) {
+                        self.tuning.retune(message);
+                    }
+                },
                 _ => (),
             }
         }
@@ -0,0 +1,261 @@
+use vstutils::envelope::Envelope;
+use vstutils::filter::{Ladder, LadderCoefficients, StateVariable, SvfCoefficients, SvfMode,
+                       BUTTERWORTH_Q};
+use vstutils::fm::{Algorithm, OperatorGraph, PmOperator};
+use vstutils::generator::{Generator, HardSync, Oscillator, StereoGenerator};
+use vstutils::maths::midi_pitch_to_freq;
+use vstutils::targetval::{Rate, TargetVal};
+use vstutils::unison::Unison;
+
+use {FilterType, VoiceMode, MAX_CUTOFF, MIN_CUTOFF};
+
+const ATTACK: f32 = 0.1;
+const DECAY: f32 = 0.1;
+
+const MAX_SVF_Q: f32 = 20.0;
+
+// At full amount, the filter envelope sweeps the cutoff this far.
+const MAX_ENV_OCTAVES: f32 = 8.0;
+
+// MPE timbre (CC 74) moves the cutoff this far either way.
+const MAX_TIMBRE_OCTAVES: f32 = 4.0;
+
+// With full key tracking, the cutoff is where it's set for this note, and
+// follows the pitch of any other note.
+const KEY_TRACK_NOTE: u8 = 60;
+
+// The FM voice is a two-operator stack, with operator 1 as the modulator.
+const FM_MODULATOR: usize = 1;
+
+/// Settings
+///
+/// The values of the parameters which every voice shares for one sample,
+/// after smoothing.
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub voice_mode:      VoiceMode,
+    pub filter_type:     FilterType,
+    pub cutoff:          f32,
+    pub resonance:       f32,
+    pub env_amount:      f32,
+    pub key_track:       f32,
+    pub filter_velocity: f32,
+    // How far the sequencer moves the cutoff, in octaves.
+    pub seq_octaves:     f32,
+}
+
+/// Voice
+///
+/// One note's oscillators, filters and filter envelope, with a gain which
+/// fades in and out with the note and follows the note's MPE pressure. A
+/// voice keeps its note once it's released, so that it stays in tune while
+/// it fades out.
+pub struct Voice {
+    note:          u8,
+    unison:        Unison,
+    fm:            OperatorGraph,
+    sync:          HardSync,
+    filter_env:    Envelope,
+    filters:       [StateVariable; 2],
+    ladders:       [Ladder; 2],
+    velocity:      TargetVal<f32>,
+    pressure:      TargetVal<f32>,
+    note_velocity: f32,
+    timbre:        f32,
+}
+
+impl Voice {
+    pub fn new(sample_rate: f32) -> Voice {
+        Voice {
+            note:          0,
+            unison:        Unison::sine(sample_rate),
+            fm:            OperatorGraph::new(2, Algorithm::Stack, sample_rate),
+            sync:          HardSync::new(Oscillator::sine(sample_rate),
+                                         Oscillator::sine(sample_rate)),
+            filter_env:    Envelope::new(sample_rate),
+            filters:       [StateVariable::default(); 2],
+            ladders:       [Ladder::default(); 2],
+            velocity:      TargetVal::new(  Rate::Absolute(0.0)
+                                          , Rate::Absolute(0.0)
+                                          , 0.0),
+            pressure:      TargetVal::new(  Rate::Relative(0.001)
+                                          , Rate::Relative(0.001)
+                                          , 1.0),
+            note_velocity: 0.0,
+            timbre:        0.5,
+        }
+    }
+
+    pub fn get_note(&self) -> u8 {
+        self.note
+    }
+
+    /// Moves the voice on to another note without starting it again.
+    pub fn set_note(&mut self, note: u8) {
+        self.note = note;
+    }
+
+    pub fn get_sample_rate(&self) -> f32 {
+        self.unison.get_sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.unison.set_sample_rate(sample_rate);
+        self.fm.set_sample_rate(sample_rate);
+        self.sync.set_sample_rate(sample_rate);
+        self.filter_env.set_sample_rate(sample_rate);
+    }
+
+    pub fn get_unison(&self) -> &Unison {
+        &self.unison
+    }
+
+    pub fn get_unison_mut(&mut self) -> &mut Unison {
+        &mut self.unison
+    }
+
+    pub fn get_modulator(&self) -> &PmOperator {
+        self.fm.get_operator(FM_MODULATOR).unwrap()
+    }
+
+    pub fn get_modulator_mut(&mut self) -> &mut PmOperator {
+        self.fm.get_operator_mut(FM_MODULATOR).unwrap()
+    }
+
+    pub fn get_filter_env(&self) -> &Envelope {
+        &self.filter_env
+    }
+
+    pub fn get_filter_env_mut(&mut self) -> &mut Envelope {
+        &mut self.filter_env
+    }
+
+    /// Returns whether the voice can be heard.
+    pub fn is_active(&self) -> bool {
+        *self.velocity.get_value() > 0.0 || *self.velocity.get_target() > 0.0
+    }
+
+    fn reset_filters(&mut self) {
+        for filter in self.filters.iter_mut() {
+            filter.reset();
+        }
+        for ladder in self.ladders.iter_mut() {
+            ladder.reset();
+        }
+    }
+
+    /// Starts a note with its velocity in the range 0-1, fading in to
+    /// `gain`. A voice taken from a note which is still held carries on
+    /// with the envelope that's running unless it's retriggered.
+    pub fn note_on(&mut self, note: u8, velocity: f32, gain: f32, retrigger: bool) {
+        if retrigger || *self.velocity.get_value() <= 0.0 {
+            self.unison.reset();
+            self.fm.reset();
+            self.sync.reset();
+            self.reset_filters();
+        }
+
+        if retrigger || *self.velocity.get_target() <= 0.0 {
+            self.filter_env.note_on();
+        }
+
+        self.note          = note;
+        self.note_velocity = velocity;
+        self.velocity.set_target(gain);
+
+        let time_per_sample = 1.0 / self.get_sample_rate();
+        self.velocity.set_inc_rate(Rate::Absolute(gain
+                                                  * time_per_sample / ATTACK));
+        self.velocity.set_dec_rate(Rate::Absolute(gain
+                                                  * time_per_sample / DECAY));
+    }
+
+    pub fn release(&mut self) {
+        self.velocity.set_target(0.0);
+        self.filter_env.note_off();
+    }
+
+    /// Sets the gain which the note's pressure gives the voice, and the
+    /// note's timbre, which moves the cutoff.
+    pub fn set_expression(&mut self, pressure: f32, timbre: f32) {
+        self.pressure.set_target(pressure);
+        self.timbre = timbre;
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32, sync_ratio: f32) {
+        self.unison.set_frequency(frequency);
+        self.fm.set_frequency(frequency);
+        self.sync.set_frequency(frequency);
+        self.sync.set_slave_frequency(frequency * sync_ratio);
+    }
+
+    /// Sets the pitch of the synced oscillator, relative to the note.
+    pub fn set_sync_ratio(&mut self, sync_ratio: f32) {
+        let frequency = self.sync.get_frequency();
+        self.sync.set_slave_frequency(frequency * sync_ratio);
+    }
+
+    /// Returns the cutoff after it's been moved by the filter envelope, key
+    /// tracking and modulation.
+    fn get_cutoff(&self, envelope: f32, settings: &Settings) -> f32 {
+        let velocity_scale = 1.0 - settings.filter_velocity
+                           + settings.filter_velocity * self.note_velocity;
+        let octaves        = settings.env_amount * MAX_ENV_OCTAVES * envelope * velocity_scale
+                           + (self.timbre - 0.5) * 2.0 * MAX_TIMBRE_OCTAVES
+                           + settings.seq_octaves;
+        let key_ratio      = self.unison.get_frequency() / midi_pitch_to_freq(KEY_TRACK_NOTE);
+
+        (settings.cutoff * 2f32.powf(octaves) * key_ratio.powf(settings.key_track))
+            .clamp(MIN_CUTOFF, MAX_CUTOFF)
+    }
+
+    fn filter(&mut self, left: f32, right: f32, cutoff: f32, settings: &Settings) -> (f32, f32) {
+        let sample_rate = self.get_sample_rate();
+        let resonance   = settings.resonance;
+
+        let mode = match settings.filter_type {
+            FilterType::Lowpass  => SvfMode::Lowpass,
+            FilterType::Highpass => SvfMode::Highpass,
+            FilterType::Bandpass => SvfMode::Bandpass,
+            FilterType::Notch    => SvfMode::Notch,
+            FilterType::Ladder   => {
+                let coefficients = LadderCoefficients::new(cutoff, resonance, sample_rate);
+
+                return (self.ladders[0].process(left, &coefficients),
+                        self.ladders[1].process(right, &coefficients));
+            },
+        };
+
+        let q            = BUTTERWORTH_Q * (MAX_SVF_Q / BUTTERWORTH_Q).powf(resonance);
+        let coefficients = SvfCoefficients::new(cutoff, q, sample_rate);
+
+        (self.filters[0].process(left, &coefficients).get(mode),
+         self.filters[1].process(right, &coefficients).get(mode))
+    }
+
+    /// Returns the voice's next left and right samples, filtered and with
+    /// its gain applied.
+    pub fn process(&mut self, settings: &Settings) -> (f32, f32) {
+        self.velocity.advance();
+        self.pressure.advance();
+
+        let (left, right) = match settings.voice_mode {
+            VoiceMode::Sine => self.unison.next_stereo_sample(),
+            VoiceMode::Sync => {
+                let value = self.sync.next_sample();
+                (value, value)
+            },
+            VoiceMode::Fm   => {
+                let value = self.fm.next_sample();
+                (value, value)
+            },
+        };
+
+        let envelope      = self.filter_env.next_value();
+        let cutoff        = self.get_cutoff(envelope, settings);
+        let (left, right) = self.filter(left, right, cutoff, settings);
+        let gain          = self.velocity.get_value() * self.pressure.get_value();
+
+        (left * gain, right * gain)
+    }
+}
@@ -0,0 +1,445 @@
+extern crate vst;
+
+use self::vst::host::Host;
+use self::vst::plugin::Plugin;
+
+use chunk::Chunk;
+use param::{choice_to_param, param_to_choice};
+
+const CONTROL_CHANGE: u8 = 0xb0;
+const MAX_CC_VALUE: f32 = 127.0;
+
+const ANY_CHANNEL: &str = "*";
+
+/// Curve
+///
+/// How a mapping spreads the controller's travel over its range. The
+/// exponential curve gives finer control at the start of the range, and the
+/// logarithmic one at the end.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Curve {
+    Linear,
+    Exponential,
+    Logarithmic,
+}
+
+pub const NUM_CURVES: usize = 3;
+
+pub fn get_curve(param: f32) -> Curve {
+    match param_to_choice(param, NUM_CURVES) {
+        0 => Curve::Linear,
+        1 => Curve::Exponential,
+        _ => Curve::Logarithmic,
+    }
+}
+
+pub fn get_param(curve: Curve) -> f32 {
+    choice_to_param(curve as usize, NUM_CURVES)
+}
+
+pub fn get_name(curve: Curve) -> String {
+    match curve {
+        Curve::Linear      => "Linear"     .to_string(),
+        Curve::Exponential => "Exponential".to_string(),
+        Curve::Logarithmic => "Logarithmic".to_string(),
+    }
+}
+
+fn get_key(curve: Curve) -> &'static str {
+    match curve {
+        Curve::Linear      => "linear",
+        Curve::Exponential => "exponential",
+        Curve::Logarithmic => "logarithmic",
+    }
+}
+
+fn from_key(key: &str) -> Option<Curve> {
+    match key {
+        "linear"      => Some(Curve::Linear),
+        "exponential" => Some(Curve::Exponential),
+        "logarithmic" => Some(Curve::Logarithmic),
+        _             => None,
+    }
+}
+
+/// Applies a curve to a value in the range 0-1.
+pub fn apply(curve: Curve, value: f32) -> f32 {
+    match curve {
+        Curve::Linear      => value,
+        Curve::Exponential => value * value,
+        Curve::Logarithmic => value.sqrt(),
+    }
+}
+
+/// Mapping
+///
+/// Drives a parameter from a MIDI controller. The range is in parameter
+/// values, and a minimum above the maximum turns the controller around. A
+/// mapping with no channel responds to the controller on every channel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Mapping {
+    pub channel:    Option<u8>,
+    pub controller: u8,
+    pub parameter:  i32,
+    pub min:        f32,
+    pub max:        f32,
+    pub curve:      Curve,
+}
+
+impl Mapping {
+    pub fn new(channel: Option<u8>, controller: u8, parameter: i32) -> Mapping {
+        Mapping {
+            channel,
+            controller,
+            parameter,
+            min:        0.0,
+            max:        1.0,
+            curve:      Curve::Linear,
+        }
+    }
+
+    pub fn matches(&self, channel: u8, controller: u8) -> bool {
+        self.controller == controller && self.channel.is_none_or(|own| own == channel)
+    }
+
+    /// Returns the parameter value for a controller value.
+    pub fn get_value(&self, cc_value: u8) -> f32 {
+        let position = apply(self.curve, (f32::from(cc_value) / MAX_CC_VALUE).min(1.0));
+
+        self.min + (self.max - self.min) * position
+    }
+
+    fn get_chunk_value(&self) -> String {
+        let channel = self.channel.map_or(ANY_CHANNEL.to_string(), |channel| channel.to_string());
+
+        format!("{} {} {} {} {} {}",
+                channel, self.controller, self.parameter, self.min, self.max,
+                get_key(self.curve))
+    }
+
+    fn from_chunk_value(value: &str) -> Option<Mapping> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+
+        if fields.len() != 6 {
+            return None;
+        }
+
+        let channel = if fields[0] == ANY_CHANNEL {
+            None
+        }
+        else {
+            Some(fields[0].parse().ok()?)
+        };
+
+        Some(Mapping {
+            channel,
+            controller: fields[1].parse().ok()?,
+            parameter:  fields[2].parse().ok()?,
+            min:        fields[3].parse().ok()?,
+            max:        fields[4].parse().ok()?,
+            curve:      from_key(fields[5])?,
+        })
+    }
+}
+
+fn get_mapping_key(index: usize) -> String {
+    format!("cc.{}", index)
+}
+
+/// CcMap
+///
+/// Maps MIDI controllers to a plugin's parameters, so that hardware can
+/// reach them without going through host automation. Mappings are learned
+/// by choosing a parameter and then moving a control, and take the range and
+/// curve which are set for learning at the time.
+///
+/// The learn choices are exposed as a single parameter: off, then each of
+/// the parameters which can be mapped. Clearing the map is left to the
+/// plugin, so that it can keep it away from host automation.
+pub struct CcMap {
+    targets:     Vec<i32>,
+    mappings:    Vec<Mapping>,
+    learning:    Option<i32>,
+    learn_min:   f32,
+    learn_max:   f32,
+    learn_curve: Curve,
+}
+
+impl CcMap {
+    /// Creates a map for a plugin's parameters, apart from the ones listed
+    /// in `unmappable`, such as the ones which control learning.
+    pub fn new(num_parameters: i32, unmappable: &[i32]) -> CcMap {
+        CcMap {
+            targets:     (0..num_parameters).filter(|index| !unmappable.contains(index))
+                                            .collect(),
+            mappings:    Vec::new(),
+            learning:    None,
+            learn_min:   0.0,
+            learn_max:   1.0,
+            learn_curve: Curve::Linear,
+        }
+    }
+
+    pub fn is_target(&self, parameter: i32) -> bool {
+        self.targets.contains(&parameter)
+    }
+
+    fn get_target_position(&self, parameter: i32) -> Option<usize> {
+        self.targets.iter().position(|&target| target == parameter)
+    }
+
+    pub fn get_mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// Adds a mapping, replacing any which already drove the same parameter
+    /// from the same controller.
+    pub fn add(&mut self, mapping: Mapping) {
+        if !self.is_target(mapping.parameter) {
+            return;
+        }
+
+        self.mappings.retain(|existing| {
+            existing.parameter != mapping.parameter
+            || existing.controller != mapping.controller
+            || existing.channel != mapping.channel
+        });
+        self.mappings.push(mapping);
+    }
+
+    /// Removes every mapping for a parameter.
+    pub fn forget(&mut self, parameter: i32) {
+        self.mappings.retain(|existing| existing.parameter != parameter);
+    }
+
+    pub fn clear(&mut self) {
+        self.mappings.clear();
+    }
+
+    /// Returns the parameter waiting for a controller to be moved, if any.
+    pub fn get_learning(&self) -> Option<i32> {
+        self.learning
+    }
+
+    pub fn learn(&mut self, parameter: Option<i32>) {
+        self.learning = parameter.filter(|&parameter| self.is_target(parameter));
+    }
+
+    fn get_num_learn_choices(&self) -> usize {
+        self.targets.len() + 1
+    }
+
+    pub fn get_learn_param(&self) -> f32 {
+        let choice = self.learning
+                         .and_then(|parameter| self.get_target_position(parameter))
+                         .map_or(0, |position| position + 1);
+        choice_to_param(choice, self.get_num_learn_choices())
+    }
+
+    pub fn set_learn_param(&mut self, param: f32) {
+        let choice = param_to_choice(param, self.get_num_learn_choices());
+
+        if choice == 0 {
+            self.learn(None);
+        }
+        else {
+            let parameter = self.targets.get(choice - 1).cloned();
+            self.learn(parameter);
+        }
+    }
+
+    pub fn get_learn_min(&self) -> f32 {
+        self.learn_min
+    }
+
+    pub fn set_learn_min(&mut self, min: f32) {
+        self.learn_min = min.clamp(0.0, 1.0);
+    }
+
+    pub fn get_learn_max(&self) -> f32 {
+        self.learn_max
+    }
+
+    pub fn set_learn_max(&mut self, max: f32) {
+        self.learn_max = max.clamp(0.0, 1.0);
+    }
+
+    pub fn get_learn_curve(&self) -> Curve {
+        self.learn_curve
+    }
+
+    pub fn set_learn_curve(&mut self, curve: Curve) {
+        self.learn_curve = curve;
+    }
+
+    /// Handles a MIDI message, learning a mapping from it if a parameter is
+    /// waiting for one, and setting every parameter mapped to it. The host
+    /// is told about each change, so that it can follow the controller.
+    /// Returns whether the message was a control change.
+    ///
+    /// The map is usually part of the plugin it drives, so the plugin has to
+    /// take it out of itself while this is called.
+    pub fn process_midi_event<P: Plugin, H: Host>(&mut self, data: [u8; 3], plugin: &mut P,
+                                                  host: &mut H) -> bool {
+        if data[0] & 0xf0 != CONTROL_CHANGE {
+            return false;
+        }
+
+        let channel    = data[0] & 0x0f;
+        let controller = data[1];
+
+        if let Some(parameter) = self.learning.take() {
+            self.forget(parameter);
+            self.add(Mapping {
+                min:   self.learn_min,
+                max:   self.learn_max,
+                curve: self.learn_curve,
+                ..Mapping::new(Some(channel), controller, parameter)
+            });
+        }
+
+        for mapping in self.mappings.iter().filter(|mapping| mapping.matches(channel, controller)) {
+            let value = mapping.get_value(data[2]);
+
+            plugin.set_parameter(mapping.parameter, value);
+            host.automate(mapping.parameter, value);
+        }
+
+        true
+    }
+
+    pub fn save(&self, chunk: &mut Chunk) {
+        for (index, mapping) in self.mappings.iter().enumerate() {
+            chunk.set(&get_mapping_key(index), &mapping.get_chunk_value());
+        }
+    }
+
+    /// Replaces the mappings with the ones saved in a chunk.
+    pub fn load(&mut self, chunk: &Chunk) {
+        self.learning = None;
+        self.mappings.clear();
+
+        let mut index = 0;
+
+        while let Some(value) = chunk.get(&get_mapping_key(index)) {
+            if let Some(mapping) = Mapping::from_chunk_value(value) {
+                self.add(mapping);
+            }
+            index += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use self::vst::plugin::Info;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[derive(Default)]
+    struct TestPlugin {
+        parameters: [f32; 4],
+    }
+
+    impl Plugin for TestPlugin {
+        fn get_info(&self) -> Info {
+            Info {
+                parameters: 4,
+                ..Info::default()
+            }
+        }
+
+        fn set_parameter(&mut self, index: i32, value: f32) {
+            self.parameters[index as usize] = value;
+        }
+    }
+
+    #[derive(Default)]
+    struct TestHost {
+        automated: Vec<(i32, f32)>,
+    }
+
+    impl Host for TestHost {
+        fn automate(&mut self, index: i32, value: f32) {
+            self.automated.push((index, value));
+        }
+    }
+
+    #[test]
+    fn test_curves()
+    {
+        let mut mapping = Mapping::new(None, 1, 0);
+        assert!(floats_equal(mapping.get_value(0), 0.0));
+        assert!(floats_equal(mapping.get_value(127), 1.0));
+
+        mapping.min   = 0.8;
+        mapping.max   = 0.2;
+        mapping.curve = Curve::Exponential;
+        assert!(floats_equal(mapping.get_value(0), 0.8));
+        assert!(floats_equal(mapping.get_value(127), 0.2));
+        assert!(mapping.get_value(64) > 0.5);
+
+        mapping.curve = Curve::Logarithmic;
+        assert!(mapping.get_value(64) < 0.5);
+    }
+
+    #[test]
+    fn test_learn()
+    {
+        let mut map    = CcMap::new(4, &[2]);
+        let mut plugin = TestPlugin::default();
+        let mut host   = TestHost::default();
+
+        map.set_learn_param(choice_to_param(2, 4));
+        assert_eq!(map.get_learning(), Some(1));
+        map.set_learn_param(choice_to_param(3, 4));
+        assert_eq!(map.get_learning(), Some(3));
+        map.set_learn_param(choice_to_param(2, 4));
+        assert_eq!(map.get_learning(), Some(1));
+        map.set_learn_max(0.5);
+
+        assert!(map.process_midi_event([0xb2, 7, 127], &mut plugin, &mut host));
+        assert_eq!(map.get_learning(), None);
+        assert!(floats_equal(plugin.parameters[1], 0.5));
+        assert_eq!(host.automated, vec![(1, 0.5)]);
+
+        // Learned mappings only respond on the channel they were learned on.
+        assert!(map.process_midi_event([0xb3, 7, 0], &mut plugin, &mut host));
+        assert!(floats_equal(plugin.parameters[1], 0.5));
+        assert!(!map.process_midi_event([0x92, 7, 0], &mut plugin, &mut host));
+        assert!(floats_equal(plugin.parameters[1], 0.5));
+        assert_eq!(host.automated.len(), 1);
+
+        // Unmappable parameters and ones past the end can't be learned, and
+        // no learn choice touches the existing mappings.
+        map.learn(Some(2));
+        assert_eq!(map.get_learning(), None);
+        map.learn(Some(4));
+        assert_eq!(map.get_learning(), None);
+        map.set_learn_param(1.0);
+        assert_eq!(map.get_learning(), Some(3));
+        assert_eq!(map.get_mappings().len(), 1);
+    }
+
+    #[test]
+    fn test_chunk()
+    {
+        let mut map = CcMap::new(4, &[]);
+        map.add(Mapping::new(None, 74, 2));
+        map.add(Mapping {
+            min:   0.25,
+            curve: Curve::Logarithmic,
+            ..Mapping::new(Some(15), 1, 3)
+        });
+
+        let mut chunk = Chunk::new();
+        map.save(&mut chunk);
+
+        let mut loaded = CcMap::new(4, &[]);
+        loaded.load(&Chunk::parse(&chunk.to_bytes()));
+        assert_eq!(loaded.get_mappings(), map.get_mappings());
+    }
+}
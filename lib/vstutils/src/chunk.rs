@@ -0,0 +1,205 @@
+extern crate vst;
+
+use self::vst::plugin::Plugin;
+
+/// Chunk
+///
+/// Plugin state for hosts to save with a project, as UTF-8 text with one
+/// `key=value` pair per line. Values are escaped so that they can hold any
+/// text, including file paths with newlines in them. Lines which can't be
+/// understood are skipped when loading, so that state saved by a newer
+/// version of a plugin still loads as far as it can.
+///
+/// Parameters are saved by index, along with the version of the plugin's
+/// layout, so that a plugin which moves its parameters can find where the
+/// saved ones belong.
+#[derive(Default)]
+pub struct Chunk {
+    entries: Vec<(String, String)>,
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _    => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut characters = value.chars();
+
+    while let Some(character) = characters.next() {
+        if character == '\\' {
+            match characters.next() {
+                Some('n')   => unescaped.push('\n'),
+                Some('r')   => unescaped.push('\r'),
+                Some(other) => unescaped.push(other),
+                None        => (),
+            }
+        }
+        else {
+            unescaped.push(character);
+        }
+    }
+
+    unescaped
+}
+
+const VERSION_KEY: &str = "version";
+
+fn get_parameter_key(index: i32) -> String {
+    format!("param.{}", index)
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk::default()
+    }
+
+    pub fn parse(data: &[u8]) -> Chunk {
+        let text = String::from_utf8_lossy(data);
+        let mut chunk = Chunk::new();
+
+        for line in text.lines() {
+            if let Some(separator) = line.find('=') {
+                let (key, value) = line.split_at(separator);
+                chunk.set(key.trim(), &unescape(&value[1..]));
+            }
+        }
+
+        chunk
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.entries
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, escape(value)))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Sets the value for a key, replacing any value it already had. Keys
+    /// can't contain `=` or newlines.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().find(|(existing, _)| existing == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None        => self.entries.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Returns the version of the parameter layout the chunk was saved with.
+    /// Chunks from before versions were saved are version 0.
+    pub fn get_version(&self) -> u32 {
+        self.get(VERSION_KEY).and_then(|value| value.parse().ok()).unwrap_or(0)
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.set(VERSION_KEY, &version.to_string());
+    }
+
+    /// Saves the value of every parameter a plugin has. Hosts don't save
+    /// parameters themselves for plugins which use chunks.
+    pub fn save_parameters<P: Plugin>(&mut self, plugin: &P) {
+        for index in 0..plugin.get_info().parameters {
+            self.set(&get_parameter_key(index), &plugin.get_parameter(index).to_string());
+        }
+    }
+
+    /// Restores any parameters which were saved by `save_parameters`.
+    pub fn load_parameters<P: Plugin>(&self, plugin: &mut P) {
+        for index in 0..plugin.get_info().parameters {
+            if let Some(value) = self.get_f32(&get_parameter_key(index)) {
+                plugin.set_parameter(index, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use self::vst::plugin::Info;
+
+    #[derive(Default)]
+    struct TestPlugin {
+        parameters: [f32; 3],
+    }
+
+    impl Plugin for TestPlugin {
+        fn get_info(&self) -> Info {
+            Info {
+                parameters: 3,
+                ..Info::default()
+            }
+        }
+
+        fn get_parameter(&self, index: i32) -> f32 {
+            self.parameters[index as usize]
+        }
+
+        fn set_parameter(&mut self, index: i32, value: f32) {
+            self.parameters[index as usize] = value;
+        }
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        let mut chunk = Chunk::new();
+        chunk.set("path", "/tunings/odd\\name\nwith=newline.scl");
+        chunk.set("level", "0.5");
+        chunk.set("level", "0.25");
+
+        let loaded = Chunk::parse(&chunk.to_bytes());
+        assert_eq!(loaded.get("path"), Some("/tunings/odd\\name\nwith=newline.scl"));
+        assert_eq!(loaded.get_f32("level"), Some(0.25));
+        assert_eq!(loaded.get("missing"), None);
+    }
+
+    #[test]
+    fn test_bad_lines_are_skipped()
+    {
+        let chunk = Chunk::parse(b"nonsense\nlevel=0.5\n\xff\xfe\ncount=x\n");
+
+        assert_eq!(chunk.get_f32("level"), Some(0.5));
+        assert_eq!(chunk.get("count"), Some("x"));
+        assert_eq!(chunk.get_f32("count"), None);
+    }
+
+    #[test]
+    fn test_parameters()
+    {
+        let mut plugin = TestPlugin { parameters: [0.1, 0.2, 0.3] };
+        let mut chunk  = Chunk::new();
+        chunk.save_parameters(&plugin);
+        chunk.set_version(2);
+
+        let loaded = Chunk::parse(&chunk.to_bytes());
+        assert_eq!(loaded.get_version(), 2);
+        assert_eq!(Chunk::parse(b"param.0=1\n").get_version(), 0);
+
+        plugin.parameters = [0.0; 3];
+        loaded.load_parameters(&mut plugin);
+        assert_eq!(plugin.parameters, [0.1, 0.2, 0.3]);
+    }
+}
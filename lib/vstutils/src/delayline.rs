@@ -0,0 +1,125 @@
+// Cubic interpolation needs a sample either side of the two it falls between.
+const INTERPOLATION_SAMPLES: usize = 3;
+
+// Reading is done before writing, so the shortest delay is one sample.
+const MIN_DELAY: f32 = 1.0;
+
+/// DelayLine
+///
+/// A circular buffer which can be read at fractional delays. Reads use
+/// cubic Hermite interpolation, so a delay time which is gliding doesn't add
+/// the noise that linear interpolation would.
+pub struct DelayLine {
+    buffer:      Vec<f32>,
+    write_index: usize,
+}
+
+impl DelayLine {
+    /// Creates a delay line which can delay by up to `max_delay` samples.
+    pub fn new(max_delay: usize) -> DelayLine {
+        DelayLine {
+            buffer:      vec![0.0; max_delay + INTERPOLATION_SAMPLES],
+            write_index: 0,
+        }
+    }
+
+    pub fn get_max_delay(&self) -> usize {
+        self.buffer.len() - INTERPOLATION_SAMPLES
+    }
+
+    // Returns the sample which was written `age` writes ago.
+    fn get_sample(&self, age: usize) -> f32 {
+        let length = self.buffer.len();
+        self.buffer[(self.write_index + length - age) % length]
+    }
+
+    /// Returns the input from `delay` samples ago. This should be called
+    /// before the current sample is written.
+    pub fn read(&self, delay: f32) -> f32 {
+        let delay    = delay.clamp(MIN_DELAY, self.get_max_delay() as f32);
+        let age      = delay as usize;
+        let fraction = delay - age as f32;
+
+        // The sample written most recently has no newer neighbour, so repeat
+        // it rather than reading the slot which is about to be overwritten.
+        let newer = self.get_sample(if age > 1 {age - 1} else {age});
+        let x0    = self.get_sample(age);
+        let x1    = self.get_sample(age + 1);
+        let x2    = self.get_sample(age + 2);
+
+        let c1 = 0.5 * (x1 - newer);
+        let c2 = newer - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+        let c3 = 0.5 * (x2 - newer) + 1.5 * (x0 - x1);
+
+        ((c3 * fraction + c2) * fraction + c1) * fraction + x0
+    }
+
+    pub fn write(&mut self, input: f32) {
+        self.buffer[self.write_index] = input;
+        self.write_index = (self.write_index + 1) % self.buffer.len();
+    }
+
+    pub fn reset(&mut self) {
+        for sample in self.buffer.iter_mut() {
+            *sample = 0.0;
+        }
+        self.write_index = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_whole_sample_delay()
+    {
+        let mut delay_line = DelayLine::new(100);
+
+        for i in 0..1000 {
+            let output = delay_line.read(10.0);
+            delay_line.write(i as f32);
+
+            if i >= 10 {
+                assert!(floats_equal(output, (i - 10) as f32));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fractional_delay()
+    {
+        let mut delay_line = DelayLine::new(100);
+
+        // Interpolating a ramp should give points on the same ramp.
+        for i in 0..1000 {
+            let output = delay_line.read(10.25);
+            delay_line.write(i as f32);
+
+            if i >= 20 {
+                assert!(floats_equal(output, i as f32 - 10.25));
+            }
+        }
+    }
+
+    #[test]
+    fn test_delay_is_clamped()
+    {
+        let mut delay_line = DelayLine::new(100);
+
+        for i in 0..1000 {
+            let short = delay_line.read(0.0);
+            let long  = delay_line.read(1000.0);
+            delay_line.write(i as f32);
+
+            if i >= 200 {
+                assert!(floats_equal(short, (i - 1) as f32));
+                assert!(floats_equal(long, (i - 100) as f32));
+            }
+        }
+    }
+}
@@ -20,10 +20,7 @@ pub enum Division {
 const NUM_DIVISIONS: u8 = 15;
 
 pub fn get_division(param: f32) -> Division {
-    let clamped_param =
-        if      param < 0.0 {0.0}
-        else if param > 1.0 {1.0}
-        else                {param};
+    let clamped_param = param.clamp(0.0, 1.0);
 
     match (clamped_param * NUM_DIVISIONS as f32) as u8 {
         0  => Division::WholeDot,
@@ -64,24 +61,167 @@ pub fn get_name(division: Division) -> String {
     }
 }
 
-pub fn get_tempo_multiplier(division: Division) -> f32 {
-    let beat_multiplier : f32 = match division {
-        Division::WholeDot         => 0.5 / 3.0,
-        Division::Whole            => 0.25,
-        Division::WholeTriplet     => 0.375,
-        Division::HalfDot          => 1.0 / 3.0,
-        Division::Half             => 0.5,
-        Division::HalfTriplet      => 0.75,
-        Division::QuarterDot       => 2.0 / 3.0,
+/// TimeSignature
+
+#[derive(Clone, Copy)]
+pub struct TimeSignature {
+    pub numerator:   u32,
+    pub denominator: u32,
+}
+
+impl TimeSignature {
+    pub fn new(numerator: i32, denominator: i32) -> TimeSignature {
+        // Hosts occasionally report zeroes before the transport has started,
+        // so fall back to common time rather than dividing by zero.
+        if numerator <= 0 || denominator <= 0 {
+            TimeSignature::default()
+        }
+        else {
+            TimeSignature {
+                numerator:   numerator as u32,
+                denominator: denominator as u32,
+            }
+        }
+    }
+
+    /// The length of one bar, in quarter notes.
+    pub fn get_bar_length(&self) -> f32 {
+        self.numerator as f32 * 4.0 / self.denominator as f32
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> TimeSignature {
+        TimeSignature {
+            numerator:   4,
+            denominator: 4,
+        }
+    }
+}
+
+/// Returns the length of a division in quarter notes.
+///
+/// The whole divisions are treated as bar lengths, so that in 6/8 a "1" lasts
+/// for three quarter notes rather than four. All other divisions are note
+/// values, which don't depend on the time signature.
+pub fn get_length(division: Division, time_sig: TimeSignature) -> f32 {
+    let bar = time_sig.get_bar_length();
+
+    match division {
+        Division::WholeDot         => bar * 1.5,
+        Division::Whole            => bar,
+        Division::WholeTriplet     => bar * 2.0 / 3.0,
+        Division::HalfDot          => 3.0,
+        Division::Half             => 2.0,
+        Division::HalfTriplet      => 4.0 / 3.0,
+        Division::QuarterDot       => 1.5,
         Division::Quarter          => 1.0,
-        Division::QuarterTriplet   => 1.5,
-        Division::EighthDot        => 4.0 / 3.0,
-        Division::Eighth           => 2.0,
-        Division::EighthTriplet    => 3.0,
-        Division::SixteenthDot     => 8.0 / 3.0,
-        Division::Sixteenth        => 4.0,
-        Division::SixteenthTriplet => 6.0,
-    };
-
-    beat_multiplier / 60.0
+        Division::QuarterTriplet   => 2.0 / 3.0,
+        Division::EighthDot        => 0.75,
+        Division::Eighth           => 0.5,
+        Division::EighthTriplet    => 1.0 / 3.0,
+        Division::SixteenthDot     => 0.375,
+        Division::Sixteenth        => 0.25,
+        Division::SixteenthTriplet => 1.0 / 6.0,
+    }
+}
+
+/// Returns the value to multiply a tempo (in quarter notes per minute) by to
+/// get the frequency of a division in Hz.
+pub fn get_tempo_multiplier(division: Division, time_sig: TimeSignature) -> f32 {
+    1.0 / (get_length(division, time_sig) * 60.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() / ((first + second) / 2.) < 0.000001
+    }
+
+    const ALL_DIVISIONS: [(Division, f32); 15] = [
+        (Division::WholeDot,         0.5 / 3.0),
+        (Division::Whole,            0.25),
+        (Division::WholeTriplet,     0.375),
+        (Division::HalfDot,          1.0 / 3.0),
+        (Division::Half,             0.5),
+        (Division::HalfTriplet,      0.75),
+        (Division::QuarterDot,       2.0 / 3.0),
+        (Division::Quarter,          1.0),
+        (Division::QuarterTriplet,   1.5),
+        (Division::EighthDot,        4.0 / 3.0),
+        (Division::Eighth,           2.0),
+        (Division::EighthTriplet,    3.0),
+        (Division::SixteenthDot,     8.0 / 3.0),
+        (Division::Sixteenth,        4.0),
+        (Division::SixteenthTriplet, 6.0),
+    ];
+
+    #[test]
+    fn test_get_division()
+    {
+        for (index, &(division, _)) in ALL_DIVISIONS.iter().enumerate() {
+            let param = (index as f32 + 0.5) / NUM_DIVISIONS as f32;
+            assert_eq!(get_name(get_division(param)), get_name(division));
+        }
+
+        assert_eq!(get_name(get_division(-1.0)), "1.");
+        assert_eq!(get_name(get_division(2.0)),  "1/16T");
+    }
+
+    #[test]
+    fn test_tempo_multiplier_common_time()
+    {
+        let time_sig = TimeSignature::new(4, 4);
+
+        for &(division, beats_per_cycle) in ALL_DIVISIONS.iter() {
+            assert!(floats_equal(get_tempo_multiplier(division, time_sig),
+                                 beats_per_cycle / 60.0));
+        }
+    }
+
+    #[test]
+    fn test_tempo_multiplier_compound_time()
+    {
+        // A bar of 6/8 lasts for three quarter notes.
+        let time_sig = TimeSignature::new(6, 8);
+
+        for &(division, beats_per_cycle) in ALL_DIVISIONS.iter() {
+            let expected = match division {
+                Division::WholeDot     => 1.0 / 4.5,
+                Division::Whole        => 1.0 / 3.0,
+                Division::WholeTriplet => 0.5,
+                _                      => beats_per_cycle,
+            };
+            assert!(floats_equal(get_tempo_multiplier(division, time_sig),
+                                 expected / 60.0));
+        }
+    }
+
+    #[test]
+    fn test_tempo_multiplier_odd_time()
+    {
+        // A bar of 7/8 lasts for three and a half quarter notes.
+        let time_sig = TimeSignature::new(7, 8);
+
+        for &(division, beats_per_cycle) in ALL_DIVISIONS.iter() {
+            let expected = match division {
+                Division::WholeDot     => 1.0 / 5.25,
+                Division::Whole        => 1.0 / 3.5,
+                Division::WholeTriplet => 3.0 / 7.0,
+                _                      => beats_per_cycle,
+            };
+            assert!(floats_equal(get_tempo_multiplier(division, time_sig),
+                                 expected / 60.0));
+        }
+    }
+
+    #[test]
+    fn test_time_signature_fallback()
+    {
+        let time_sig = TimeSignature::new(0, 0);
+        assert_eq!(time_sig.numerator,   4);
+        assert_eq!(time_sig.denominator, 4);
+    }
 }
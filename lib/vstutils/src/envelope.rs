@@ -0,0 +1,212 @@
+// The decay and release curves are exponential, and are considered finished
+// once they're this close to their target.
+const TIME_CONSTANTS: f32 = 5.0;
+const THRESHOLD: f32 = 0.0001;
+
+// Keep the stages from becoming instantaneous, which would click.
+const MIN_TIME: f32 = 0.001;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// Envelope
+///
+/// An ADSR envelope with a linear attack and exponential decay and release.
+/// Times are in seconds, and the sustain level is in the range 0-1. A new
+/// note starts the attack from wherever the envelope currently is, so
+/// retriggering a sounding envelope doesn't click.
+pub struct Envelope {
+    attack:      f32,
+    decay:       f32,
+    sustain:     f32,
+    release:     f32,
+    sample_rate: f32,
+    stage:       Stage,
+    value:       f32,
+}
+
+impl Envelope {
+    pub fn new(sample_rate: f32) -> Envelope {
+        Envelope {
+            attack:      0.01,
+            decay:       0.1,
+            sustain:     1.0,
+            release:     0.1,
+            sample_rate,
+            stage:       Stage::Idle,
+            value:       0.0,
+        }
+    }
+
+    pub fn get_attack(&self) -> f32 {
+        self.attack
+    }
+
+    pub fn set_attack(&mut self, attack: f32) {
+        self.attack = attack.max(MIN_TIME);
+    }
+
+    pub fn get_decay(&self) -> f32 {
+        self.decay
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.max(MIN_TIME);
+    }
+
+    pub fn get_sustain(&self) -> f32 {
+        self.sustain
+    }
+
+    pub fn set_sustain(&mut self, sustain: f32) {
+        self.sustain = sustain.clamp(0.0, 1.0);
+    }
+
+    pub fn get_release(&self) -> f32 {
+        self.release
+    }
+
+    pub fn set_release(&mut self, release: f32) {
+        self.release = release.max(MIN_TIME);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn get_stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn get_value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    pub fn note_on(&mut self) {
+        self.stage = Stage::Attack;
+    }
+
+    pub fn note_off(&mut self) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.stage = Stage::Idle;
+        self.value = 0.0;
+    }
+
+    // The fraction of the remaining distance to cover each sample, so that
+    // the curve gets to within THRESHOLD of its target in about `time`.
+    fn get_coefficient(&self, time: f32) -> f32 {
+        1.0 - (-TIME_CONSTANTS / (time * self.sample_rate)).exp()
+    }
+
+    pub fn next_value(&mut self) -> f32 {
+        match self.stage {
+            Stage::Idle => (),
+            Stage::Attack => {
+                self.value += 1.0 / (self.attack * self.sample_rate);
+
+                if self.value >= 1.0 {
+                    self.value = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            },
+            Stage::Decay => {
+                self.value += (self.sustain - self.value) * self.get_coefficient(self.decay);
+
+                if (self.value - self.sustain).abs() < THRESHOLD {
+                    self.value = self.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            },
+            Stage::Sustain => self.value = self.sustain,
+            Stage::Release => {
+                self.value -= self.value * self.get_coefficient(self.release);
+
+                if self.value < THRESHOLD {
+                    self.reset();
+                }
+            },
+        }
+
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.001
+    }
+
+    // Runs the envelope for a number of seconds, returning its final value.
+    fn run(envelope: &mut Envelope, seconds: f32) -> f32 {
+        let samples = (seconds * envelope.sample_rate) as usize;
+        let mut value = envelope.get_value();
+
+        for _ in 0..samples {
+            value = envelope.next_value();
+        }
+
+        value
+    }
+
+    #[test]
+    fn test_stages()
+    {
+        let mut envelope = Envelope::new(1000.0);
+        envelope.set_attack(0.1);
+        envelope.set_decay(0.1);
+        envelope.set_sustain(0.5);
+        envelope.set_release(0.1);
+
+        assert_eq!(envelope.get_stage(), Stage::Idle);
+        assert!(floats_equal(envelope.next_value(), 0.0));
+
+        envelope.note_on();
+        assert!(floats_equal(run(&mut envelope, 0.05), 0.5));
+        assert_eq!(envelope.get_stage(), Stage::Attack);
+
+        run(&mut envelope, 0.06);
+        assert_eq!(envelope.get_stage(), Stage::Decay);
+
+        assert!(floats_equal(run(&mut envelope, 0.2), 0.5));
+        assert_eq!(envelope.get_stage(), Stage::Sustain);
+
+        envelope.note_off();
+        assert_eq!(envelope.get_stage(), Stage::Release);
+        assert!(floats_equal(run(&mut envelope, 0.2), 0.0));
+        assert!(!envelope.is_active());
+    }
+
+    #[test]
+    fn test_retrigger_from_current_value()
+    {
+        let mut envelope = Envelope::new(1000.0);
+        envelope.set_attack(0.1);
+        envelope.set_sustain(0.0);
+
+        envelope.note_on();
+        run(&mut envelope, 0.05);
+        envelope.note_off();
+        let released = run(&mut envelope, 0.01);
+
+        envelope.note_on();
+        assert!(envelope.next_value() > released);
+    }
+}
@@ -0,0 +1,483 @@
+use std::f32::consts::PI;
+
+pub const BUTTERWORTH_Q: f32 = ::std::f32::consts::FRAC_1_SQRT_2;
+
+// Keep cutoffs a little way below Nyquist, where the designs break down.
+const MAX_CUTOFF_RATIO: f32 = 0.49;
+
+fn get_omega(frequency: f32, sample_rate: f32) -> f32 {
+    2.0 * PI * frequency.clamp(0.0, sample_rate * MAX_CUTOFF_RATIO) / sample_rate
+}
+
+// The prewarped integrator gain shared by the zero-delay feedback filters.
+fn get_integrator_gain(frequency: f32, sample_rate: f32) -> f32 {
+    (get_omega(frequency, sample_rate) / 2.0).tan()
+}
+
+// Very low Q values stop the filters doing anything useful, while very high
+// ones give a ringing that never decays.
+const MIN_Q: f32 = 0.1;
+const MAX_Q: f32 = 50.0;
+
+/// SvfMode
+///
+/// Which of the state-variable filter's outputs to use.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SvfMode {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    Peak,
+}
+
+/// SvfOutput
+///
+/// Every response of the state-variable filter, which all come out of it
+/// at once.
+#[derive(Clone, Copy)]
+pub struct SvfOutput {
+    pub lowpass:  f32,
+    pub highpass: f32,
+    pub bandpass: f32,
+    pub notch:    f32,
+    pub peak:     f32,
+}
+
+impl SvfOutput {
+    pub fn get(&self, mode: SvfMode) -> f32 {
+        match mode {
+            SvfMode::Lowpass  => self.lowpass,
+            SvfMode::Highpass => self.highpass,
+            SvfMode::Bandpass => self.bandpass,
+            SvfMode::Notch    => self.notch,
+            SvfMode::Peak     => self.peak,
+        }
+    }
+}
+
+/// SvfCoefficients
+///
+/// Coefficients for the state-variable filter. They're cheap enough to
+/// recalculate every sample.
+#[derive(Clone, Copy)]
+pub struct SvfCoefficients {
+    k:  f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl SvfCoefficients {
+    pub fn new(frequency: f32, q: f32, sample_rate: f32) -> SvfCoefficients {
+        SvfCoefficients::from_gains(get_integrator_gain(frequency, sample_rate),
+                                    1.0 / q.clamp(MIN_Q, MAX_Q))
+    }
+
+    // Takes the integrator gain and the damping, which is 1/Q.
+    fn from_gains(g: f32, k: f32) -> SvfCoefficients {
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+
+        SvfCoefficients {
+            k,
+            a1,
+            a2,
+            a3: g * a2,
+        }
+    }
+}
+
+/// StateVariable
+///
+/// A topology-preserving state-variable filter, after Andrew Simper's
+/// trapezoidal integrator design. Its state is held in the integrators
+/// rather than in past outputs, so the cutoff and Q can be modulated every
+/// sample without the filter blowing up.
+#[derive(Clone, Copy, Default)]
+pub struct StateVariable {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl StateVariable {
+    pub fn process(&mut self, input: f32, coefficients: &SvfCoefficients) -> SvfOutput {
+        let v3 = input - self.ic2eq;
+        let v1 = coefficients.a1 * self.ic1eq + coefficients.a2 * v3;
+        let v2 = self.ic2eq + coefficients.a2 * self.ic1eq + coefficients.a3 * v3;
+
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        let highpass = input - coefficients.k * v1 - v2;
+
+        SvfOutput {
+            lowpass:  v2,
+            highpass,
+            bandpass: v1,
+            notch:    v2 + highpass,
+            peak:     v2 - highpass,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+    }
+}
+
+// At this much feedback the ladder oscillates on its own.
+const MAX_LADDER_FEEDBACK: f32 = 4.0;
+
+/// BiquadCoefficients
+///
+/// Coefficients for the responses from Robert Bristow-Johnson's Audio EQ
+/// Cookbook. Rather than the usual direct form, each response is built by
+/// mixing the outputs of a state-variable filter, which keeps the filter
+/// stable however quickly the coefficients change. They're kept separate
+/// from the filter state so that one set can be shared between several
+/// channels.
+#[derive(Clone, Copy)]
+pub struct BiquadCoefficients {
+    svf: SvfCoefficients,
+    m0:  f32,
+    m1:  f32,
+    m2:  f32,
+}
+
+impl BiquadCoefficients {
+    // Mixes the input, band-pass and low-pass outputs.
+    fn mix(svf: SvfCoefficients, m0: f32, m1: f32, m2: f32) -> BiquadCoefficients {
+        BiquadCoefficients {
+            svf,
+            m0,
+            m1,
+            m2,
+        }
+    }
+
+    /// Coefficients which pass the input through unchanged.
+    pub fn identity() -> BiquadCoefficients {
+        BiquadCoefficients::mix(SvfCoefficients::from_gains(0.0, 1.0), 1.0, 0.0, 0.0)
+    }
+
+    pub fn lowpass(frequency: f32, q: f32, sample_rate: f32) -> BiquadCoefficients {
+        BiquadCoefficients::mix(SvfCoefficients::new(frequency, q, sample_rate), 0.0, 0.0, 1.0)
+    }
+
+    pub fn highpass(frequency: f32, q: f32, sample_rate: f32) -> BiquadCoefficients {
+        let svf = SvfCoefficients::new(frequency, q, sample_rate);
+        BiquadCoefficients::mix(svf, 1.0, -svf.k, -1.0)
+    }
+
+    /// A band-pass with a peak gain of 0 dB, whatever the Q.
+    pub fn bandpass(frequency: f32, q: f32, sample_rate: f32) -> BiquadCoefficients {
+        let svf = SvfCoefficients::new(frequency, q, sample_rate);
+        BiquadCoefficients::mix(svf, 0.0, svf.k, 0.0)
+    }
+
+    pub fn notch(frequency: f32, q: f32, sample_rate: f32) -> BiquadCoefficients {
+        let svf = SvfCoefficients::new(frequency, q, sample_rate);
+        BiquadCoefficients::mix(svf, 1.0, -svf.k, 0.0)
+    }
+
+    pub fn peak(frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> BiquadCoefficients {
+        let a   = 10f32.powf(gain_db / 40.0);
+        let k   = 1.0 / (q.clamp(MIN_Q, MAX_Q) * a);
+        let svf = SvfCoefficients::from_gains(get_integrator_gain(frequency, sample_rate), k);
+
+        BiquadCoefficients::mix(svf, 1.0, k * (a * a - 1.0), 0.0)
+    }
+
+    pub fn allpass(frequency: f32, q: f32, sample_rate: f32) -> BiquadCoefficients {
+        let svf = SvfCoefficients::new(frequency, q, sample_rate);
+        BiquadCoefficients::mix(svf, 1.0, -2.0 * svf.k, 0.0)
+    }
+
+    /// A shelf with the steepest slope that doesn't overshoot.
+    pub fn low_shelf(frequency: f32, gain_db: f32, sample_rate: f32) -> BiquadCoefficients {
+        let a   = 10f32.powf(gain_db / 40.0);
+        let g   = get_integrator_gain(frequency, sample_rate) / a.sqrt();
+        let svf = SvfCoefficients::from_gains(g, 1.0 / BUTTERWORTH_Q);
+
+        BiquadCoefficients::mix(svf, 1.0, svf.k * (a - 1.0), a * a - 1.0)
+    }
+
+    /// A shelf with the steepest slope that doesn't overshoot.
+    pub fn high_shelf(frequency: f32, gain_db: f32, sample_rate: f32) -> BiquadCoefficients {
+        let a   = 10f32.powf(gain_db / 40.0);
+        let g   = get_integrator_gain(frequency, sample_rate) * a.sqrt();
+        let svf = SvfCoefficients::from_gains(g, 1.0 / BUTTERWORTH_Q);
+
+        BiquadCoefficients::mix(svf, a * a, svf.k * (1.0 - a) * a, 1.0 - a * a)
+    }
+}
+
+/// Biquad
+///
+/// The state of a biquad filter.
+#[derive(Clone, Copy, Default)]
+pub struct Biquad {
+    svf: StateVariable,
+}
+
+impl Biquad {
+    pub fn process(&mut self, input: f32, coefficients: &BiquadCoefficients) -> f32 {
+        let output = self.svf.process(input, &coefficients.svf);
+
+        coefficients.m0 * input
+            + coefficients.m1 * output.bandpass
+            + coefficients.m2 * output.lowpass
+    }
+
+    pub fn reset(&mut self) {
+        self.svf.reset();
+    }
+}
+
+/// LadderCoefficients
+///
+/// Coefficients for the ladder filter. Resonance is in the range 0-1, where
+/// 1 is the edge of self-oscillation.
+#[derive(Clone, Copy)]
+pub struct LadderCoefficients {
+    g: f32,
+    k: f32,
+}
+
+impl LadderCoefficients {
+    pub fn new(frequency: f32, resonance: f32, sample_rate: f32) -> LadderCoefficients {
+        let g = get_integrator_gain(frequency, sample_rate);
+
+        LadderCoefficients {
+            g: g / (1.0 + g),
+            k: resonance.clamp(0.0, 1.0) * MAX_LADDER_FEEDBACK,
+        }
+    }
+}
+
+/// Ladder
+///
+/// A 4-pole low-pass ladder built from trapezoidal one-pole stages, with the
+/// feedback loop solved without a unit delay. The signal entering the ladder
+/// is saturated, which keeps the resonance in check and gives the filter its
+/// character when driven. As with the state-variable filter, the
+/// coefficients can change every sample.
+#[derive(Clone, Copy, Default)]
+pub struct Ladder {
+    stages: [f32; 4],
+}
+
+impl Ladder {
+    pub fn process(&mut self, input: f32, coefficients: &LadderCoefficients) -> f32 {
+        let g = coefficients.g;
+
+        // Each stage's output is g * input plus a contribution from its
+        // state, so the whole ladder's output can be predicted from the
+        // input to the first stage.
+        let state_sum = self.stages.iter().fold(0.0, |sum, stage| sum * g + stage * (1.0 - g));
+        let g4        = g * g * g * g;
+        let feedback  = (input - coefficients.k * state_sum) / (1.0 + coefficients.k * g4);
+
+        let mut output = feedback.tanh();
+
+        for stage in self.stages.iter_mut() {
+            let v  = (output - *stage) * g;
+            output = v + *stage;
+            *stage = output + v;
+        }
+
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.stages = [0.0; 4];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maths::param_to_log_range;
+    use random::Random;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.001
+    }
+
+    fn gains_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.02
+    }
+
+    // Measures the peak gain of a filter for a sine at the given frequency,
+    // once it's settled.
+    fn get_gain<F: FnMut(f32) -> f32>(frequency: f32, amplitude: f32, mut filter: F) -> f32 {
+        let settle = SAMPLE_RATE as usize;
+        let mut peak = 0f32;
+
+        for i in 0..settle * 2 {
+            let phase  = 2.0 * PI * frequency * i as f32 / SAMPLE_RATE;
+            let output = filter(amplitude * phase.sin());
+
+            if i >= settle {
+                peak = peak.max(output.abs());
+            }
+        }
+
+        peak / amplitude
+    }
+
+    fn get_biquad_gain(frequency: f32, coefficients: &BiquadCoefficients) -> f32 {
+        let mut biquad = Biquad::default();
+        get_gain(frequency, 1.0, |input| biquad.process(input, coefficients))
+    }
+
+    fn get_svf_gain(frequency: f32, mode: SvfMode, coefficients: &SvfCoefficients) -> f32 {
+        let mut svf = StateVariable::default();
+        get_gain(frequency, 1.0, |input| svf.process(input, coefficients).get(mode))
+    }
+
+    fn get_dc_gain(coefficients: &BiquadCoefficients) -> f32 {
+        let mut biquad = Biquad::default();
+        let mut output = 0.0;
+
+        for _ in 0..10000 {
+            output = biquad.process(1.0, coefficients);
+        }
+
+        output
+    }
+
+    #[test]
+    fn test_dc_gain()
+    {
+        let sample_rate = 44100.0;
+
+        assert!(floats_equal(get_dc_gain(&BiquadCoefficients::identity()), 1.0));
+        assert!(floats_equal(
+            get_dc_gain(&BiquadCoefficients::lowpass(1000.0, BUTTERWORTH_Q, sample_rate)),
+            1.0));
+        assert!(floats_equal(
+            get_dc_gain(&BiquadCoefficients::highpass(1000.0, BUTTERWORTH_Q, sample_rate)),
+            0.0));
+        assert!(floats_equal(
+            get_dc_gain(&BiquadCoefficients::low_shelf(1000.0, 6.0, sample_rate)),
+            10f32.powf(6.0 / 20.0)));
+        assert!(floats_equal(
+            get_dc_gain(&BiquadCoefficients::high_shelf(1000.0, 6.0, sample_rate)),
+            1.0));
+    }
+
+    #[test]
+    fn test_biquad_response()
+    {
+        let q    = BUTTERWORTH_Q;
+        let gain = |frequency, coefficients| get_biquad_gain(frequency, &coefficients);
+
+        assert!(gains_equal(gain(1000.0, BiquadCoefficients::lowpass(1000.0, q, SAMPLE_RATE)), q));
+        assert!(gain(10000.0, BiquadCoefficients::lowpass(1000.0, q, SAMPLE_RATE)) < 0.02);
+        assert!(gains_equal(gain(1000.0, BiquadCoefficients::highpass(1000.0, q, SAMPLE_RATE)), q));
+        assert!(gain(100.0, BiquadCoefficients::highpass(1000.0, q, SAMPLE_RATE)) < 0.02);
+        assert!(gains_equal(gain(1000.0, BiquadCoefficients::bandpass(1000.0, 4.0, SAMPLE_RATE)),
+                            1.0));
+        assert!(gain(100.0, BiquadCoefficients::bandpass(1000.0, 4.0, SAMPLE_RATE)) < 0.1);
+        assert!(gain(1000.0, BiquadCoefficients::notch(1000.0, q, SAMPLE_RATE)) < 0.02);
+        assert!(gains_equal(gain(100.0, BiquadCoefficients::notch(1000.0, q, SAMPLE_RATE)), 1.0));
+        assert!(gains_equal(gain(1000.0, BiquadCoefficients::peak(1000.0, q, 6.0, SAMPLE_RATE)),
+                            10f32.powf(6.0 / 20.0)));
+        assert!(gains_equal(gain(100.0, BiquadCoefficients::allpass(1000.0, q, SAMPLE_RATE)), 1.0));
+        assert!(gains_equal(gain(5000.0, BiquadCoefficients::allpass(1000.0, q, SAMPLE_RATE)), 1.0));
+    }
+
+    #[test]
+    fn test_svf_response()
+    {
+        let butterworth = SvfCoefficients::new(1000.0, BUTTERWORTH_Q, SAMPLE_RATE);
+        let resonant    = SvfCoefficients::new(1000.0, 4.0, SAMPLE_RATE);
+        let gain        = |frequency, mode, coefficients| get_svf_gain(frequency, mode, &coefficients);
+
+        assert!(gains_equal(gain(100.0, SvfMode::Lowpass, butterworth), 1.0));
+        assert!(gains_equal(gain(1000.0, SvfMode::Lowpass, butterworth), BUTTERWORTH_Q));
+        assert!(gain(10000.0, SvfMode::Lowpass, butterworth) < 0.02);
+        assert!(gains_equal(gain(10000.0, SvfMode::Highpass, butterworth), 1.0));
+        assert!(gains_equal(gain(1000.0, SvfMode::Highpass, butterworth), BUTTERWORTH_Q));
+        assert!(gain(100.0, SvfMode::Highpass, butterworth) < 0.02);
+
+        // The band-pass output peaks at Q.
+        assert!(gains_equal(gain(1000.0, SvfMode::Bandpass, resonant) / 4.0, 1.0));
+        assert!(gain(100.0, SvfMode::Bandpass, resonant) < 0.15);
+        assert!(gain(1000.0, SvfMode::Notch, resonant) < 0.02);
+        assert!(gains_equal(gain(100.0, SvfMode::Notch, resonant), 1.0));
+        assert!(gains_equal(gain(1000.0, SvfMode::Peak, resonant) / 8.0, 1.0));
+        assert!(gains_equal(gain(100.0, SvfMode::Peak, resonant), 1.0));
+    }
+
+    #[test]
+    fn test_ladder_response()
+    {
+        // Keep the level low so that the saturation stays out of the way.
+        let amplitude = 0.01;
+        let gain      = |frequency, resonance| {
+            let coefficients = LadderCoefficients::new(1000.0, resonance, SAMPLE_RATE);
+            let mut ladder   = Ladder::default();
+
+            get_gain(frequency, amplitude, |input| ladder.process(input, &coefficients))
+        };
+
+        assert!(gains_equal(gain(20.0, 0.0), 1.0));
+        assert!(gains_equal(gain(1000.0, 0.0), 0.25));
+        assert!(gain(10000.0, 0.0) < 0.001);
+
+        // Resonance loses some of the bass and boosts around the cutoff.
+        assert!(gains_equal(gain(20.0, 0.5), 1.0 / 3.0));
+        assert!(gain(1000.0, 0.9) > gain(1000.0, 0.0));
+    }
+
+    #[test]
+    fn test_ladder_saturates()
+    {
+        let coefficients = LadderCoefficients::new(1000.0, 1.0, SAMPLE_RATE);
+        let mut ladder   = Ladder::default();
+
+        for _ in 0..10000 {
+            assert!(ladder.process(100.0, &coefficients).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_stable_under_modulation()
+    {
+        let mut random = Random::new(1);
+        let mut svf    = StateVariable::default();
+        let mut ladder = Ladder::default();
+        let mut biquad = Biquad::default();
+
+        for _ in 0..SAMPLE_RATE as usize {
+            // Jump the cutoff and resonance to somewhere new every sample.
+            let frequency = param_to_log_range(random.next_f32(), 20.0, 20000.0);
+            let resonance = random.next_f32();
+            let input     = random.next_bipolar();
+
+            let svf_output = svf.process(input,
+                                         &SvfCoefficients::new(frequency,
+                                                               0.5 + resonance * 20.0,
+                                                               SAMPLE_RATE));
+            let ladder_output = ladder.process(input,
+                                               &LadderCoefficients::new(frequency,
+                                                                        resonance,
+                                                                        SAMPLE_RATE));
+            let biquad_output = biquad.process(input,
+                                               &BiquadCoefficients::lowpass(frequency,
+                                                                            0.5 + resonance * 20.0,
+                                                                            SAMPLE_RATE));
+
+            assert!(svf_output.lowpass.abs() < 100.0);
+            assert!(svf_output.highpass.abs() < 100.0);
+            assert!(svf_output.bandpass.abs() < 100.0);
+            assert!(ladder_output.abs() < 10.0);
+            assert!(biquad_output.abs() < 100.0);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use generator::{Generator, Oscillator};
+
+const TAU: f32 = ::std::f32::consts::PI * 2.0;
+
+pub const MIN_OPERATORS: usize = 2;
+pub const MAX_OPERATORS: usize = 4;
+
+/// PmOperator
+pub struct PmOperator {
+    oscillator: Oscillator,
+    ratio:      f32,
+    index:      f32,
+    feedback:   f32,
+    history:    [f32; 2],
+}
+
+impl PmOperator {
+    pub fn new(sample_rate: f32) -> PmOperator {
+        PmOperator {
+            oscillator: Oscillator::sine(sample_rate),
+            ratio:      1.0,
+            index:      1.0,
+            feedback:   0.0,
+            history:    [0.0, 0.0],
+        }
+    }
+
+    /// Returns the next sample, with the phase of the operator shifted by
+    /// `modulation` radians.
+    pub fn next_sample(&mut self, modulation: f32) -> f32 {
+        // Averaging the last two outputs damps the oscillation which
+        // otherwise sets in at high feedback amounts.
+        let feedback = self.feedback * (self.history[0] + self.history[1]) / 2.0;
+
+        let value = self.oscillator
+                        .next_sample_with_phase((modulation + feedback) / TAU);
+
+        self.history = [value, self.history[0]];
+        value
+    }
+
+    /// Sets the frequency of the note being played. The operator runs at this
+    /// frequency multiplied by its ratio.
+    pub fn set_base_frequency(&mut self, frequency: f32) {
+        self.oscillator.set_frequency(frequency * self.ratio);
+    }
+
+    pub fn get_ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    pub fn set_ratio(&mut self, ratio: f32) {
+        let base_frequency = self.oscillator.get_frequency() / self.ratio;
+        self.ratio = ratio;
+        self.set_base_frequency(base_frequency);
+    }
+
+    /// The modulation index is the peak phase deviation, in radians, which
+    /// this operator applies to the operator it modulates.
+    pub fn get_index(&self) -> f32 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: f32) {
+        self.index = index;
+    }
+
+    /// Feedback is the modulation index this operator applies to itself.
+    pub fn get_feedback(&self) -> f32 {
+        self.feedback
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.oscillator.set_sample_rate(sample_rate);
+    }
+
+    pub fn reset(&mut self) {
+        self.oscillator.reset();
+        self.history = [0.0, 0.0];
+    }
+}
+
+/// Algorithm
+#[derive(Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// Each operator modulates the one below it, and operator 0 is the only
+    /// carrier.
+    Stack,
+    /// Every other operator modulates operator 0 directly.
+    Parallel,
+    /// Operators are split into modulator/carrier pairs: 1 modulates 0 and 3
+    /// modulates 2.
+    Pairs,
+}
+
+impl Algorithm {
+    /// Returns, for each operator, the operator it modulates, or None if it
+    /// is a carrier. Operators only ever modulate lower-numbered operators.
+    fn get_targets(self, num_operators: usize) -> Vec<Option<usize>> {
+        (0..num_operators).map(|operator| {
+            match (self, operator) {
+                (_, 0)                   => None,
+                (Algorithm::Stack,    _) => Some(operator - 1),
+                (Algorithm::Parallel, _) => Some(0),
+                (Algorithm::Pairs,    _) => {
+                    if operator % 2 == 1 {Some(operator - 1)} else {None}
+                },
+            }
+        }).collect()
+    }
+}
+
+/// OperatorGraph
+pub struct OperatorGraph {
+    operators:  Vec<PmOperator>,
+    algorithm:  Algorithm,
+    targets:    Vec<Option<usize>>,
+    modulation: Vec<f32>,
+    frequency:  f32,
+}
+
+impl Generator for OperatorGraph {
+    fn next_sample(&mut self) -> f32 {
+        for modulation in self.modulation.iter_mut() {
+            *modulation = 0.0;
+        }
+
+        let mut output   = 0.0;
+        let mut carriers = 0;
+
+        // Modulators always have higher indices than the operators they
+        // modulate, so working downwards means every operator's modulation
+        // input is complete by the time it is reached.
+        for operator_index in (0..self.operators.len()).rev() {
+            let operator = &mut self.operators[operator_index];
+            let value    = operator.next_sample(self.modulation[operator_index]);
+
+            match self.targets[operator_index] {
+                Some(target) => self.modulation[target] += value * operator.get_index(),
+                None         => {
+                    output   += value;
+                    carriers += 1;
+                },
+            }
+        }
+
+        output / carriers as f32
+    }
+}
+
+impl OperatorGraph {
+    pub fn new(num_operators: usize,
+               algorithm:     Algorithm,
+               sample_rate:   f32) -> OperatorGraph {
+        let num_operators = num_operators.clamp(MIN_OPERATORS, MAX_OPERATORS);
+
+        let mut graph = OperatorGraph {
+            operators:  (0..num_operators).map(|_| PmOperator::new(sample_rate))
+                                          .collect(),
+            algorithm,
+            targets:    algorithm.get_targets(num_operators),
+            modulation: vec![0.0; num_operators],
+            frequency:  440.0,
+        };
+        graph.set_frequency(440.0);
+        graph
+    }
+
+    pub fn get_algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        self.algorithm = algorithm;
+        self.targets   = algorithm.get_targets(self.operators.len());
+    }
+
+    pub fn get_num_operators(&self) -> usize {
+        self.operators.len()
+    }
+
+    pub fn get_operator(&self, index: usize) -> Option<&PmOperator> {
+        self.operators.get(index)
+    }
+
+    pub fn get_operator_mut(&mut self, index: usize) -> Option<&mut PmOperator> {
+        self.operators.get_mut(index)
+    }
+
+    pub fn get_frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+
+        for operator in self.operators.iter_mut() {
+            operator.set_base_frequency(frequency);
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        for operator in self.operators.iter_mut() {
+            operator.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for operator in self.operators.iter_mut() {
+            operator.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_targets()
+    {
+        assert_eq!(Algorithm::Stack.get_targets(4),
+                   [None, Some(0), Some(1), Some(2)]);
+        assert_eq!(Algorithm::Parallel.get_targets(4),
+                   [None, Some(0), Some(0), Some(0)]);
+        assert_eq!(Algorithm::Pairs.get_targets(4),
+                   [None, Some(0), None, Some(2)]);
+    }
+
+    #[test]
+    fn test_operator_count()
+    {
+        assert_eq!(OperatorGraph::new(1, Algorithm::Stack, 44100.0)
+                       .get_num_operators(), MIN_OPERATORS);
+        assert_eq!(OperatorGraph::new(8, Algorithm::Stack, 44100.0)
+                       .get_num_operators(), MAX_OPERATORS);
+    }
+
+    #[test]
+    fn test_unmodulated_carrier_is_sine()
+    {
+        let mut graph = OperatorGraph::new(2, Algorithm::Stack, 44100.0);
+        graph.get_operator_mut(1).unwrap().set_index(0.0);
+
+        let mut sine = Oscillator::sine(44100.0);
+
+        for _ in 0..1000 {
+            assert!(floats_equal(graph.next_sample(), sine.next_sample()));
+        }
+    }
+
+    #[test]
+    fn test_ratio()
+    {
+        let mut graph = OperatorGraph::new(2, Algorithm::Stack, 44100.0);
+        graph.set_frequency(100.0);
+        graph.get_operator_mut(1).unwrap().set_ratio(3.5);
+
+        assert!(floats_equal(graph.get_operator(1).unwrap()
+                                  .oscillator.get_frequency(), 350.0));
+
+        graph.set_frequency(200.0);
+
+        assert!(floats_equal(graph.get_operator(1).unwrap()
+                                  .oscillator.get_frequency(), 700.0));
+    }
+
+    #[test]
+    fn test_feedback_changes_waveform()
+    {
+        let mut operator = PmOperator::new(44100.0);
+        let mut sine     = Oscillator::sine(44100.0);
+        operator.set_base_frequency(441.0);
+        sine.set_frequency(441.0);
+
+        operator.set_feedback(1.5);
+
+        // Feedback bends the sine towards a sawtooth, so it should stray well
+        // away from the sine it starts as.
+        let difference = (0..1000).map(|_| (operator.next_sample(0.0) - sine.next_sample()).abs())
+                                  .fold(0.0f32, f32::max);
+
+        assert!(difference > 0.2);
+    }
+
+    #[test]
+    fn test_feedback_is_damped()
+    {
+        let mut operator = PmOperator::new(44100.0);
+        operator.set_base_frequency(441.0);
+        operator.set_feedback(1.5);
+
+        // Undamped, this much feedback breaks into an oscillation at half
+        // the sample rate, which makes neighbouring samples jump about.
+        let output: Vec<f32> = (0..10000).map(|_| operator.next_sample(0.0)).collect();
+        let tail = &output[5000..];
+        let step = tail.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum::<f32>()
+                   / tail.len() as f32;
+
+        assert!(step < 0.1);
+    }
+
+    #[test]
+    fn test_high_feedback_keeps_moving()
+    {
+        let mut graph = OperatorGraph::new(4, Algorithm::Pairs, 44100.0);
+        graph.set_frequency(441.0);
+
+        for index in 0..4 {
+            let operator = graph.get_operator_mut(index).unwrap();
+            operator.set_index(10.0);
+            operator.set_feedback(10.0);
+        }
+
+        let output: Vec<f32> = (0..10000).map(|_| graph.next_sample()).collect();
+        let tail = &output[5000..];
+
+        assert!(tail.iter().all(|sample| sample.is_finite() && sample.abs() <= 1.0));
+
+        // Even where feedback turns to noise, the output shouldn't freeze or
+        // settle on a constant.
+        let min = tail.iter().cloned().fold(f32::MAX, f32::min);
+        let max = tail.iter().cloned().fold(f32::MIN, f32::max);
+        assert!(max - min > 1.0);
+
+        let mean = tail.iter().sum::<f32>() / tail.len() as f32;
+        assert!(mean.abs() < 0.5);
+    }
+}
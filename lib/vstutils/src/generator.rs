@@ -1,7 +1,6 @@
 const TAU: f32 = ::std::f32::consts::PI * 2.0;
 
 /// WaveTable
-
 struct WaveTable {
     values: Box<[f32]>,
 }
@@ -34,19 +33,24 @@ impl WaveTable {
 }
 
 /// Generator
-
 pub trait Generator {
     fn next_sample(&mut self) -> f32;
 }
 
-/// OscillatorState
+/// StereoGenerator
+pub trait StereoGenerator {
+    /// Returns the next left and right samples.
+    fn next_stereo_sample(&mut self) -> (f32, f32);
+}
 
+/// OscillatorState
 struct OscillatorState {
     frequency:      f32,
     sample_rate:    f32,
     table_size_f:   f32,
     table_position: f32,
     table_rate:     f32,
+    wrapped:        bool,
 }
 
 impl OscillatorState {
@@ -57,6 +61,7 @@ impl OscillatorState {
             table_size_f:   table_size as f32,
             table_position: 0.0,
             table_rate:     0.0,
+            wrapped:        false,
         };
         state.update_table_rate();
         state
@@ -80,6 +85,19 @@ impl OscillatorState {
         self.update_table_rate();
     }
 
+    fn get_phase(&self) -> f32 {
+        self.table_position / self.table_size_f
+    }
+
+    fn set_phase(&mut self, phase: f32) {
+        self.table_position = phase.rem_euclid(1.0) * self.table_size_f;
+
+        // rem_euclid can round up to exactly 1.0 for tiny negative phases.
+        if self.table_position >= self.table_size_f {
+            self.table_position = 0.0;
+        }
+    }
+
     fn update_table_rate(&mut self) {
         self.table_rate = self.table_size_f * self.frequency / self.sample_rate;
     }
@@ -88,7 +106,8 @@ impl OscillatorState {
         let position = self.table_position;
 
         self.table_position += self.table_rate;
-        if self.table_position >= self.table_size_f {
+        self.wrapped = self.table_position >= self.table_size_f;
+        if self.wrapped {
             self.table_position -= self.table_size_f;
         }
 
@@ -97,7 +116,6 @@ impl OscillatorState {
 }
 
 /// Oscillator
-
 pub struct Oscillator {
     state:      OscillatorState,
     wavetable:  WaveTable,
@@ -117,7 +135,7 @@ impl Oscillator {
         state.set_sample_rate(sample_rate);
 
         Oscillator {
-            state:      state,
+            state,
             wavetable:  WaveTable::new(1024, |theta: f32| -> f32 { theta.sin() }),
         }
     }
@@ -137,4 +155,172 @@ impl Oscillator {
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.state.set_sample_rate(sample_rate);
     }
+
+    /// Returns the next sample, read from a position offset within the cycle
+    /// by `phase_offset` (in cycles) without affecting the oscillator's own
+    /// phase.
+    pub fn next_sample_with_phase(&mut self, phase_offset: f32) -> f32 {
+        let table_size = self.state.table_size_f;
+        let mut position = (self.state.next_position()
+                            + phase_offset * table_size).rem_euclid(table_size);
+
+        if position >= table_size {
+            position = 0.0;
+        }
+
+        self.wavetable.get_value(position)
+    }
+
+    /// Returns the position of the next sample within the waveform's cycle,
+    /// in the range 0-1.
+    pub fn get_phase(&self) -> f32 {
+        self.state.get_phase()
+    }
+
+    /// Moves the oscillator to a position within the waveform's cycle. Values
+    /// outside the range 0-1 wrap around.
+    pub fn set_phase(&mut self, phase: f32) {
+        self.state.set_phase(phase);
+    }
+
+    pub fn reset(&mut self) {
+        self.state.set_phase(0.0);
+    }
+
+    /// Returns true if the last call to `next_sample` completed a cycle.
+    pub fn has_wrapped(&self) -> bool {
+        self.state.wrapped
+    }
+}
+
+/// HardSync
+pub struct HardSync {
+    master: Oscillator,
+    slave:  Oscillator,
+}
+
+impl Generator for HardSync {
+    fn next_sample(&mut self) -> f32 {
+        self.master.next_sample();
+        let value = self.slave.next_sample();
+
+        if self.master.has_wrapped() {
+            // Carry over the fraction of a sample by which the master passed
+            // the end of its cycle, so the slave restarts in the right place.
+            let ratio = self.slave.get_frequency() / self.master.get_frequency();
+            self.slave.set_phase(self.master.get_phase() * ratio);
+        }
+
+        value
+    }
+}
+
+impl HardSync {
+    pub fn new(master: Oscillator, slave: Oscillator) -> HardSync {
+        HardSync {
+            master,
+            slave,
+        }
+    }
+
+    pub fn get_frequency(&self) -> f32 {
+        self.master.get_frequency()
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.master.set_frequency(frequency);
+    }
+
+    pub fn get_slave_frequency(&self) -> f32 {
+        self.slave.get_frequency()
+    }
+
+    pub fn set_slave_frequency(&mut self, frequency: f32) {
+        self.slave.set_frequency(frequency);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.master.set_sample_rate(sample_rate);
+        self.slave.set_sample_rate(sample_rate);
+    }
+
+    pub fn reset(&mut self) {
+        self.master.reset();
+        self.slave.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_phase()
+    {
+        let mut oscillator = Oscillator::sine(44100.0);
+        assert!(floats_equal(oscillator.get_phase(), 0.0));
+
+        oscillator.set_phase(0.25);
+        assert!(floats_equal(oscillator.get_phase(), 0.25));
+        assert!(floats_equal(oscillator.next_sample(), 1.0));
+
+        oscillator.set_phase(-0.25);
+        assert!(floats_equal(oscillator.get_phase(), 0.75));
+        assert!(floats_equal(oscillator.next_sample(), -1.0));
+
+        oscillator.reset();
+        assert!(floats_equal(oscillator.get_phase(), 0.0));
+        assert!(floats_equal(oscillator.next_sample(), 0.0));
+    }
+
+    #[test]
+    fn test_next_sample_with_phase()
+    {
+        let mut oscillator = Oscillator::sine(44100.0);
+
+        assert!(floats_equal(oscillator.next_sample_with_phase(0.25), 1.0));
+        assert!(!floats_equal(oscillator.get_phase(), 0.0));
+
+        oscillator.reset();
+        assert!(floats_equal(oscillator.next_sample_with_phase(-10.25), -1.0));
+    }
+
+    #[test]
+    fn test_wrap()
+    {
+        let mut oscillator = Oscillator::sine(100.0);
+        oscillator.set_frequency(25.0);
+
+        for _ in 0..3 {
+            oscillator.next_sample();
+            assert!(!oscillator.has_wrapped());
+        }
+
+        oscillator.next_sample();
+        assert!(oscillator.has_wrapped());
+    }
+
+    #[test]
+    fn test_hard_sync()
+    {
+        let mut master = Oscillator::sine(100.0);
+        let mut slave  = Oscillator::sine(100.0);
+        master.set_frequency(10.0);
+        slave.set_frequency(15.0);
+
+        let mut sync = HardSync::new(master, slave);
+
+        // The slave restarts its cycle every time the master wraps, so the
+        // output repeats with the master's period.
+        let first_cycle: Vec<f32> = (0..10).map(|_| sync.next_sample()).collect();
+        let second_cycle: Vec<f32> = (0..10).map(|_| sync.next_sample()).collect();
+
+        for (first, second) in first_cycle.iter().zip(second_cycle.iter()) {
+            assert!(floats_equal(*first, *second));
+        }
+    }
 }
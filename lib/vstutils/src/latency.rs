@@ -0,0 +1,21 @@
+extern crate vst;
+
+use std::ptr;
+
+use self::vst::host::OpCode;
+use self::vst::plugin::HostCallback;
+
+/// Tells the host how many samples of latency the plugin now has. VST 2
+/// hosts read it from the plugin's effect, and look again once they've been
+/// told its inputs and outputs have changed, which they only act on when
+/// the plugin is resumed. Without a host, this does nothing.
+pub fn set_latency(host: &HostCallback, latency: usize) {
+    let effect = host.raw_effect();
+
+    if let Some(callback) = host.raw_callback().filter(|_| !effect.is_null()) {
+        unsafe {
+            (*effect).initialDelay = latency as i32;
+        }
+        callback(effect, OpCode::IOChanged.into(), 0, 0, ptr::null_mut(), 0.0);
+    }
+}
@@ -0,0 +1,171 @@
+use std::f32::consts::PI;
+
+use param::{choice_to_param, param_to_choice};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shape {
+    Sine,
+    Triangle,
+    SawUp,
+    SawDown,
+    Square,
+}
+
+pub const NUM_SHAPES: usize = 5;
+
+pub fn get_shape(param: f32) -> Shape {
+    match param_to_choice(param, NUM_SHAPES) {
+        0 => Shape::Sine,
+        1 => Shape::Triangle,
+        2 => Shape::SawUp,
+        3 => Shape::SawDown,
+        _ => Shape::Square,
+    }
+}
+
+pub fn get_param(shape: Shape) -> f32 {
+    choice_to_param(shape as usize, NUM_SHAPES)
+}
+
+pub fn get_name(shape: Shape) -> String {
+    match shape {
+        Shape::Sine     => "Sine"    .to_string(),
+        Shape::Triangle => "Triangle".to_string(),
+        Shape::SawUp    => "Saw Up"  .to_string(),
+        Shape::SawDown  => "Saw Down".to_string(),
+        Shape::Square   => "Square"  .to_string(),
+    }
+}
+
+/// Returns the value of a shape at a point in its cycle, between -1 and 1.
+/// Apart from the rising saw, every shape starts its cycle at its peak, so
+/// that a tremolo starts at full volume.
+pub fn get_value(shape: Shape, phase: f32) -> f32 {
+    let phase = phase.rem_euclid(1.0);
+
+    match shape {
+        Shape::Sine     => (2.0 * PI * phase).cos(),
+        Shape::Triangle => (4.0 * phase - 2.0).abs() - 1.0,
+        Shape::SawUp    => 2.0 * phase - 1.0,
+        Shape::SawDown  => 1.0 - 2.0 * phase,
+        Shape::Square   => if phase < 0.5 {1.0} else {-1.0},
+    }
+}
+
+/// Lfo
+///
+/// A low frequency oscillator which can be read at several phase offsets
+/// at once, for modulating stereo channels out of step with each other.
+pub struct Lfo {
+    shape:       Shape,
+    frequency:   f32,
+    sample_rate: f32,
+    phase:       f32,
+}
+
+impl Lfo {
+    pub fn new(sample_rate: f32) -> Lfo {
+        Lfo {
+            shape:       Shape::Sine,
+            frequency:   1.0,
+            sample_rate,
+            phase:       0.0,
+        }
+    }
+
+    pub fn get_shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn set_shape(&mut self, shape: Shape) {
+        self.shape = shape;
+    }
+
+    pub fn get_frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn get_phase(&self) -> f32 {
+        self.phase
+    }
+
+    /// Moves the LFO to a position within its cycle. Values outside the
+    /// range 0-1 wrap around.
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    /// Returns the value at the current position, offset by `phase_offset`
+    /// cycles, without moving the LFO on.
+    pub fn get_value(&self, phase_offset: f32) -> f32 {
+        get_value(self.shape, self.phase + phase_offset)
+    }
+
+    /// Moves the LFO on by one sample.
+    pub fn advance(&mut self) {
+        self.set_phase(self.phase + self.frequency / self.sample_rate);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_shapes()
+    {
+        let shapes = [Shape::Sine, Shape::Triangle, Shape::SawUp, Shape::SawDown, Shape::Square];
+
+        for &shape in shapes.iter() {
+            assert_eq!(get_shape(get_param(shape)), shape);
+
+            for step in 0..100 {
+                let value = get_value(shape, step as f32 / 100.0);
+                assert!((-1.0..=1.0).contains(&value));
+            }
+        }
+
+        assert!(floats_equal(get_value(Shape::Sine, 0.0), 1.0));
+        assert!(floats_equal(get_value(Shape::Sine, 0.5), -1.0));
+        assert!(floats_equal(get_value(Shape::Triangle, 0.0), 1.0));
+        assert!(floats_equal(get_value(Shape::Triangle, 0.25), 0.0));
+        assert!(floats_equal(get_value(Shape::Triangle, 0.5), -1.0));
+        assert!(floats_equal(get_value(Shape::SawDown, 0.25), 0.5));
+        assert!(floats_equal(get_value(Shape::Square, 0.75), -1.0));
+    }
+
+    #[test]
+    fn test_advance()
+    {
+        let mut lfo = Lfo::new(100.0);
+        lfo.set_frequency(2.0);
+
+        for _ in 0..25 {
+            lfo.advance();
+        }
+        assert!(floats_equal(lfo.get_phase(), 0.5));
+        assert!(floats_equal(lfo.get_value(0.0), -1.0));
+        assert!(floats_equal(lfo.get_value(0.5), 1.0));
+
+        for _ in 0..50 {
+            lfo.advance();
+        }
+        assert!(floats_equal(lfo.get_phase(), 0.5));
+    }
+}
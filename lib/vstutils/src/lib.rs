@@ -1,5 +1,28 @@
+pub mod ccmap;
+pub mod chunk;
+pub mod delayline;
 pub mod division;
+pub mod envelope;
+pub mod filter;
+pub mod fm;
 pub mod generator;
+pub mod latency;
+pub mod lfo;
 pub mod maths;
+pub mod modmatrix;
+pub mod modulation;
+pub mod mpe;
+pub mod mts;
 pub mod notetracker;
+pub mod oversampler;
+pub mod pan;
+pub mod param;
+pub mod random;
+pub mod sendbuffer;
+pub mod sequencer;
 pub mod targetval;
+pub mod transport;
+pub mod tuning;
+pub mod unison;
+pub mod velocity;
+pub mod voiceallocator;
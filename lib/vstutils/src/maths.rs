@@ -10,12 +10,110 @@ pub fn get_beats_frequencies(f_target: f32, f_beats: f32) -> (f32, f32) {
     (f_centre - f_beats / 2., f_centre + f_beats / 2.)
 }
 
+/// Like `get_beats_frequencies`, but with the two frequencies separated by an
+/// interval in cents rather than a fixed number of Hz, so that the beat rate
+/// follows the pitch being played.
+pub fn get_beats_frequencies_cents(f_target: f32, cents: f32) -> (f32, f32) {
+    // Split the interval evenly either side of the target, which keeps the
+    // product of the two frequencies equal to f_target ^ 2.
+    let ratio = (cents / 2400.).exp2();
+
+    (f_target / ratio, f_target * ratio)
+}
+
+/// Maps a parameter value in the range 0-1 onto a logarithmic scale between
+/// `min` and `max`.
+pub fn param_to_log_range(param: f32, min: f32, max: f32) -> f32 {
+    min * (max / min).powf(param.clamp(0.0, 1.0))
+}
+
+/// The inverse of `param_to_log_range`.
+pub fn log_range_to_param(value: f32, min: f32, max: f32) -> f32 {
+    ((value / min).ln() / (max / min).ln()).clamp(0.0, 1.0)
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20. * gain.log10()
+}
+
+pub const A4_PITCH: f32 = 69.0;
+pub const A4_FREQ: f32 = 440.0;
+
 pub fn midi_pitch_to_freq(pitch: u8) -> f32 {
-    const A4_PITCH: i8 = 69;
-    const A4_FREQ: f32 = 440.0;
+    PitchConverter::default().get_frequency(f32::from(pitch))
+}
 
-    // Midi notes can be 0-127
-    ((f32::from(pitch as i8 - A4_PITCH)) / 12.).exp2() * A4_FREQ
+/// PitchConverter
+///
+/// Turns MIDI pitches into frequencies, with A4 tuned to a reference
+/// frequency, a transpose in semitones and a fine tune in cents. Pitches can
+/// be fractional, for pitch bends.
+#[derive(Clone, Copy)]
+pub struct PitchConverter {
+    reference: f32,
+    transpose: f32,
+    fine:      f32,
+}
+
+impl PitchConverter {
+    pub fn new(reference: f32, transpose: f32, fine: f32) -> PitchConverter {
+        PitchConverter {
+            reference,
+            transpose,
+            fine,
+        }
+    }
+
+    pub fn get_reference(&self) -> f32 {
+        self.reference
+    }
+
+    pub fn set_reference(&mut self, reference: f32) {
+        self.reference = reference;
+    }
+
+    pub fn get_transpose(&self) -> f32 {
+        self.transpose
+    }
+
+    pub fn set_transpose(&mut self, transpose: f32) {
+        self.transpose = transpose;
+    }
+
+    pub fn get_fine(&self) -> f32 {
+        self.fine
+    }
+
+    pub fn set_fine(&mut self, fine: f32) {
+        self.fine = fine;
+    }
+
+    /// Returns a pitch moved by the transpose.
+    pub fn transpose(&self, pitch: f32) -> f32 {
+        pitch + self.transpose
+    }
+
+    /// Retunes a frequency from A4 = 440 Hz to the reference, and applies
+    /// the fine tune. This is for frequencies which come from somewhere
+    /// other than `get_frequency`, such as a microtuning.
+    pub fn retune(&self, frequency: f32) -> f32 {
+        frequency * self.reference / A4_FREQ * (self.fine / 1200.).exp2()
+    }
+
+    /// Returns the frequency of a pitch in 12-tone equal temperament.
+    pub fn get_frequency(&self, pitch: f32) -> f32 {
+        self.retune(((self.transpose(pitch) - A4_PITCH) / 12.).exp2() * A4_FREQ)
+    }
+}
+
+impl Default for PitchConverter {
+    fn default() -> PitchConverter {
+        PitchConverter::new(A4_FREQ, 0.0, 0.0)
+    }
 }
 
 #[cfg(test)]
@@ -38,10 +136,56 @@ mod test {
         assert!(floats_equal(f_lower * f_upper, f_target * f_target));
     }
 
+    #[test]
+    fn test_get_beats_frequencies_cents()
+    {
+        let f_target = 440.0;
+
+        let (f_lower, f_upper) = get_beats_frequencies_cents(f_target, 1200.0);
+
+        assert!(floats_equal(f_upper, f_lower * 2.0));
+        assert!(floats_equal(f_lower * f_upper, f_target * f_target));
+    }
+
+    #[test]
+    fn test_log_range()
+    {
+        assert!(floats_equal(param_to_log_range(0.0, 0.01, 40.0), 0.01));
+        assert!(floats_equal(param_to_log_range(1.0, 0.01, 40.0), 40.0));
+        assert!(floats_equal(param_to_log_range(0.5, 1.0, 100.0), 10.0));
+        assert!(floats_equal(log_range_to_param(10.0, 1.0, 100.0), 0.5));
+    }
+
+    #[test]
+    fn test_db()
+    {
+        assert!(floats_equal(db_to_gain(0.0), 1.0));
+        assert!(floats_equal(db_to_gain(20.0), 10.0));
+        assert!(floats_equal(db_to_gain(-20.0), 0.1));
+        assert!(floats_equal(gain_to_db(db_to_gain(6.0)), 6.0));
+    }
+
     #[test]
     fn test_midi_pitch_to_freq() {
         assert!(floats_equal(midi_pitch_to_freq(57), 220.0));
         assert!(floats_equal(midi_pitch_to_freq(69), 440.0));
         assert!(floats_equal(midi_pitch_to_freq(81), 880.0));
     }
+
+    #[test]
+    fn test_pitch_converter()
+    {
+        let mut converter = PitchConverter::new(442.0, 0.0, 0.0);
+        assert!(floats_equal(converter.get_frequency(69.0), 442.0));
+        assert!(floats_equal(converter.get_frequency(57.0), 221.0));
+        assert!(floats_equal(converter.get_frequency(69.5), 442.0 * (1.0 / 24.0f32).exp2()));
+
+        converter.set_reference(415.0);
+        converter.set_transpose(-12.0);
+        assert!(floats_equal(converter.get_frequency(81.0), 415.0));
+
+        converter.set_fine(100.0);
+        assert!(floats_equal(converter.get_frequency(80.0), 415.0));
+        assert!(floats_equal(converter.retune(440.0), 415.0 * (1.0 / 12.0f32).exp2()));
+    }
 }
@@ -0,0 +1,602 @@
+use std::mem;
+
+use ccmap;
+use ccmap::Curve;
+use chunk::Chunk;
+use envelope::Envelope;
+use lfo;
+use lfo::Lfo;
+use maths::{log_range_to_param, param_to_log_range};
+use param::{choice_to_param, param_to_choice};
+use random::Random;
+
+const CONTROL_CHANGE: u8 = 0xb0;
+const POLY_PRESSURE: u8 = 0xa0;
+const CHANNEL_PRESSURE: u8 = 0xd0;
+const MOD_WHEEL: u8 = 1;
+const MAX_MIDI_VALUE: f32 = 127.0;
+
+const MIN_LFO_RATE: f32 = 0.01;
+const MAX_LFO_RATE: f32 = 20.0;
+const MIN_TIME: f32 = 0.001;
+const MAX_TIME: f32 = 10.0;
+
+/// Source
+///
+/// Where a slot's modulation comes from. The LFO swings either side of
+/// zero, and every other source runs from 0 to 1. Random takes a new value
+/// with each note.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Lfo,
+    Envelope,
+    Velocity,
+    Key,
+    Aftertouch,
+    ModWheel,
+    Random,
+}
+
+pub const NUM_SOURCES: usize = 7;
+
+pub fn get_source(param: f32) -> Source {
+    match param_to_choice(param, NUM_SOURCES) {
+        0 => Source::Lfo,
+        1 => Source::Envelope,
+        2 => Source::Velocity,
+        3 => Source::Key,
+        4 => Source::Aftertouch,
+        5 => Source::ModWheel,
+        _ => Source::Random,
+    }
+}
+
+pub fn get_param(source: Source) -> f32 {
+    choice_to_param(source as usize, NUM_SOURCES)
+}
+
+pub fn get_name(source: Source) -> String {
+    match source {
+        Source::Lfo        => "LFO"       .to_string(),
+        Source::Envelope   => "Envelope"  .to_string(),
+        Source::Velocity   => "Velocity"  .to_string(),
+        Source::Key        => "Key"       .to_string(),
+        Source::Aftertouch => "Aftertouch".to_string(),
+        Source::ModWheel   => "Mod Wheel" .to_string(),
+        Source::Random     => "Random"    .to_string(),
+    }
+}
+
+/// Slot
+///
+/// One connection in the matrix, from a source to a parameter. The amount
+/// is in parameter values, from -1 to 1, and the curve bends the source's
+/// travel on either side of zero.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Slot {
+    pub source:      Source,
+    pub destination: Option<i32>,
+    pub amount:      f32,
+    pub curve:       Curve,
+}
+
+impl Default for Slot {
+    fn default() -> Slot {
+        Slot {
+            source:      Source::Lfo,
+            destination: None,
+            amount:      0.0,
+            curve:       Curve::Linear,
+        }
+    }
+}
+
+impl Slot {
+    pub fn is_active(&self) -> bool {
+        self.destination.is_some() && self.amount != 0.0
+    }
+
+    /// Returns the slot's contribution for a source value.
+    pub fn get_modulation(&self, value: f32) -> f32 {
+        let shaped = ccmap::apply(self.curve, value.abs().min(1.0));
+
+        self.amount * if value < 0.0 {-shaped} else {shaped}
+    }
+}
+
+pub const NUM_SLOTS: usize = 4;
+
+fn get_destination_key(slot: usize) -> String {
+    format!("mod.{}.dest", slot)
+}
+
+// Saved destinations are written off when a slot has none.
+const NO_DESTINATION: &str = "off";
+
+// The matrix's own parameters: the LFO, the envelope, then four for each
+// slot.
+const LFO_SHAPE: i32 = 0;
+const LFO_RATE: i32 = 1;
+const ENV_ATTACK: i32 = 2;
+const ENV_DECAY: i32 = 3;
+const ENV_SUSTAIN: i32 = 4;
+const ENV_RELEASE: i32 = 5;
+const FIRST_SLOT_PARAM: i32 = 6;
+const PARAMS_PER_SLOT: i32 = 4;
+
+pub const NUM_PARAMETERS: i32 = FIRST_SLOT_PARAM + NUM_SLOTS as i32 * PARAMS_PER_SLOT;
+
+const LAST_SLOT_PARAM: i32 = NUM_PARAMETERS - 1;
+
+/// ModMatrix
+///
+/// Connects modulation sources to a plugin's parameters. Each slot adds its
+/// source, scaled by its amount, to the value the parameter was last given
+/// by the host, which is kept as the parameter's base. The plugin should
+/// show the base as the parameter's value, so that the host never records
+/// the modulation as automation.
+///
+/// The matrix has an LFO and an envelope of its own, and the plugin passes
+/// on its notes and MIDI messages for the rest of the sources. It's
+/// expected to be run once per block, which the smoothing of most
+/// parameters covers over.
+pub struct ModMatrix {
+    destinations:     Vec<i32>,
+    slots:            [Slot; NUM_SLOTS],
+    lfo:              Lfo,
+    envelope:         Envelope,
+    random:           Random,
+    velocity:         f32,
+    key:              f32,
+    aftertouch:       f32,
+    mod_wheel:        f32,
+    random_value:     f32,
+    bases:            Vec<Option<f32>>,
+    active:           Vec<bool>,
+}
+
+impl ModMatrix {
+    /// Creates a matrix which can modulate the parameters listed in
+    /// `destinations`.
+    pub fn new(destinations: &[i32], sample_rate: f32) -> ModMatrix {
+        let count = destinations.len();
+
+        ModMatrix {
+            destinations:     destinations.to_vec(),
+            slots:            [Slot::default(); NUM_SLOTS],
+            lfo:              Lfo::new(sample_rate),
+            envelope:         Envelope::new(sample_rate),
+            random:           Random::new(0x2545_f491),
+            velocity:         0.0,
+            key:              0.0,
+            aftertouch:       0.0,
+            mod_wheel:        0.0,
+            random_value:     0.0,
+            bases:            vec![None; count],
+            active:           vec![false; count],
+        }
+    }
+
+    pub fn get_slot(&self, slot: usize) -> Option<&Slot> {
+        self.slots.get(slot)
+    }
+
+    pub fn set_slot(&mut self, slot: usize, value: Slot) {
+        let destination = value.destination.filter(|&destination| self.is_destination(destination));
+
+        if let Some(existing) = self.slots.get_mut(slot) {
+            *existing = Slot {
+                destination,
+                amount: value.amount.clamp(-1.0, 1.0),
+                ..value
+            };
+        }
+    }
+
+    pub fn get_destinations(&self) -> &[i32] {
+        &self.destinations
+    }
+
+    fn get_position(&self, index: i32) -> Option<usize> {
+        self.destinations.iter().position(|&destination| destination == index)
+    }
+
+    fn is_destination(&self, index: i32) -> bool {
+        self.get_position(index).is_some()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.lfo.set_sample_rate(sample_rate);
+        self.envelope.set_sample_rate(sample_rate);
+    }
+
+    /// Starts a note, with its velocity in the range 0-1.
+    pub fn note_on(&mut self, note: u8, velocity: f32) {
+        self.key          = f32::from(note) / MAX_MIDI_VALUE;
+        self.velocity     = velocity.clamp(0.0, 1.0);
+        self.random_value = self.random.next_f32();
+        self.envelope.note_on();
+    }
+
+    /// Releases the envelope. Call this once no notes are held.
+    pub fn note_off(&mut self) {
+        self.envelope.note_off();
+    }
+
+    /// Picks up aftertouch and the mod wheel from a MIDI message.
+    pub fn process_midi_event(&mut self, data: [u8; 3]) {
+        match data[0] & 0xf0 {
+            CHANNEL_PRESSURE => self.aftertouch = f32::from(data[1]) / MAX_MIDI_VALUE,
+            POLY_PRESSURE    => self.aftertouch = f32::from(data[2]) / MAX_MIDI_VALUE,
+            CONTROL_CHANGE if data[1] == MOD_WHEEL =>
+                self.mod_wheel = f32::from(data[2]) / MAX_MIDI_VALUE,
+            _ => (),
+        }
+    }
+
+    /// Moves the LFO and envelope on by a block.
+    pub fn advance(&mut self, samples: usize) {
+        for _ in 0..samples {
+            self.lfo.advance();
+            self.envelope.next_value();
+        }
+    }
+
+    pub fn get_source_value(&self, source: Source) -> f32 {
+        match source {
+            Source::Lfo        => self.lfo.get_value(0.0),
+            Source::Envelope   => self.envelope.get_value(),
+            Source::Velocity   => self.velocity,
+            Source::Key        => self.key,
+            Source::Aftertouch => self.aftertouch,
+            Source::ModWheel   => self.mod_wheel,
+            Source::Random     => self.random_value,
+        }
+    }
+
+    pub fn is_modulated(&self, destination: i32) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| slot.is_active() && slot.destination == Some(destination))
+    }
+
+    /// Returns the total of every slot's modulation of a parameter.
+    pub fn get_modulation(&self, destination: i32) -> f32 {
+        self.slots
+            .iter()
+            .filter(|slot| slot.is_active() && slot.destination == Some(destination))
+            .map(|slot| slot.get_modulation(self.get_source_value(slot.source)))
+            .sum()
+    }
+
+    pub fn get_base(&self, destination: i32) -> Option<f32> {
+        self.get_position(destination).and_then(|position| self.bases[position])
+    }
+
+    /// Records the value the host gave a parameter.
+    pub fn set_base(&mut self, destination: i32, value: f32) {
+        if let Some(position) = self.get_position(destination) {
+            self.bases[position] = Some(value);
+        }
+    }
+
+    /// Returns a parameter's base if it's being modulated, which is the
+    /// value the host should see.
+    pub fn get_modulated_base(&self, destination: i32) -> Option<f32> {
+        self.get_base(destination).filter(|_| self.is_modulated(destination))
+    }
+
+    /// Returns the value to give a parameter while it's modulated.
+    pub fn get_value(&self, destination: i32) -> Option<f32> {
+        self.get_modulated_base(destination)
+            .map(|base| (base + self.get_modulation(destination)).clamp(0.0, 1.0))
+    }
+
+    /// Returns the value a parameter should be set to for the next block:
+    /// its modulated value while it's modulated, and its base once more when
+    /// the modulation stops, so that it isn't left where the modulation put
+    /// it. Parameters which don't need setting give `None`.
+    pub fn next_value(&mut self, destination: i32) -> Option<f32> {
+        let position  = self.get_position(destination)?;
+        let modulated = self.is_modulated(destination);
+        let was       = mem::replace(&mut self.active[position], modulated);
+
+        if modulated {
+            self.get_value(destination)
+        }
+        else if was {
+            self.get_base(destination)
+        }
+        else {
+            None
+        }
+    }
+
+    fn get_num_destination_choices(&self) -> usize {
+        self.destinations.len() + 1
+    }
+
+    // The destination choices are off, then each of the destinations.
+    fn get_destination_param(&self, destination: Option<i32>) -> f32 {
+        let choice = destination.and_then(|destination| self.get_position(destination))
+                                .map_or(0, |position| position + 1);
+        choice_to_param(choice, self.get_num_destination_choices())
+    }
+
+    fn get_destination(&self, param: f32) -> Option<i32> {
+        match param_to_choice(param, self.get_num_destination_choices()) {
+            0      => None,
+            choice => self.destinations.get(choice - 1).cloned(),
+        }
+    }
+
+    /// Saves each slot's destination by parameter index. The Dest
+    /// parameters choose from the list of destinations, which moves as a
+    /// plugin gains parameters, so these are what keep saved slots pointing
+    /// at the same parameters.
+    pub fn save(&self, chunk: &mut Chunk) {
+        for (index, slot) in self.slots.iter().enumerate() {
+            let value = slot.destination.map_or(NO_DESTINATION.to_string(),
+                                                |destination| destination.to_string());
+            chunk.set(&get_destination_key(index), &value);
+        }
+    }
+
+    /// Restores the destinations saved in a chunk. Load these after the
+    /// parameters, so that they take the place of the saved Dest parameters.
+    /// Slots with no saved destination are left as they are.
+    pub fn load(&mut self, chunk: &Chunk) {
+        for index in 0..NUM_SLOTS {
+            let destination = match chunk.get(&get_destination_key(index)) {
+                Some(NO_DESTINATION) => None,
+                Some(value)          => match value.parse() {
+                    Ok(destination) => Some(destination),
+                    Err(_)          => continue,
+                },
+                None                 => continue,
+            };
+            let slot = Slot { destination, ..self.slots[index] };
+            self.set_slot(index, slot);
+        }
+    }
+
+    fn get_slot_param(index: i32) -> (usize, i32) {
+        let offset = index - FIRST_SLOT_PARAM;
+        ((offset / PARAMS_PER_SLOT) as usize, offset % PARAMS_PER_SLOT)
+    }
+
+    /// Returns one of the matrix's own parameters, numbered from 0 to
+    /// `NUM_PARAMETERS - 1`.
+    pub fn get_parameter(&self, index: i32) -> f32 {
+        match index {
+            LFO_SHAPE   => lfo::get_param(self.lfo.get_shape()),
+            LFO_RATE    => log_range_to_param(self.lfo.get_frequency(), MIN_LFO_RATE, MAX_LFO_RATE),
+            ENV_ATTACK  => log_range_to_param(self.envelope.get_attack(), MIN_TIME, MAX_TIME),
+            ENV_DECAY   => log_range_to_param(self.envelope.get_decay(), MIN_TIME, MAX_TIME),
+            ENV_SUSTAIN => self.envelope.get_sustain(),
+            ENV_RELEASE => log_range_to_param(self.envelope.get_release(), MIN_TIME, MAX_TIME),
+            FIRST_SLOT_PARAM..=LAST_SLOT_PARAM => {
+                let (slot, param) = ModMatrix::get_slot_param(index);
+
+                self.slots.get(slot).map_or(0.0, |slot| match param {
+                    0 => get_param(slot.source),
+                    1 => self.get_destination_param(slot.destination),
+                    2 => (slot.amount + 1.0) / 2.0,
+                    _ => ccmap::get_param(slot.curve),
+                })
+            },
+            _ => 0.0,
+        }
+    }
+
+    pub fn set_parameter(&mut self, index: i32, value: f32) {
+        match index {
+            LFO_SHAPE   => self.lfo.set_shape(lfo::get_shape(value)),
+            LFO_RATE    => self.lfo.set_frequency(param_to_log_range(value, MIN_LFO_RATE, MAX_LFO_RATE)),
+            ENV_ATTACK  => self.envelope.set_attack(param_to_log_range(value, MIN_TIME, MAX_TIME)),
+            ENV_DECAY   => self.envelope.set_decay(param_to_log_range(value, MIN_TIME, MAX_TIME)),
+            ENV_SUSTAIN => self.envelope.set_sustain(value),
+            ENV_RELEASE => self.envelope.set_release(param_to_log_range(value, MIN_TIME, MAX_TIME)),
+            FIRST_SLOT_PARAM..=LAST_SLOT_PARAM => {
+                let (slot, param) = ModMatrix::get_slot_param(index);
+
+                if let Some(&existing) = self.slots.get(slot) {
+                    let updated = match param {
+                        0 => Slot { source: get_source(value), ..existing },
+                        1 => Slot { destination: self.get_destination(value), ..existing },
+                        2 => Slot { amount: value * 2.0 - 1.0, ..existing },
+                        _ => Slot { curve: ccmap::get_curve(value), ..existing },
+                    };
+                    self.set_slot(slot, updated);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    pub fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            LFO_SHAPE   => "Mod LFO Shape".to_string(),
+            LFO_RATE    => "Mod LFO Rate".to_string(),
+            ENV_ATTACK  => "Mod Attack".to_string(),
+            ENV_DECAY   => "Mod Decay".to_string(),
+            ENV_SUSTAIN => "Mod Sustain".to_string(),
+            ENV_RELEASE => "Mod Release".to_string(),
+            FIRST_SLOT_PARAM..=LAST_SLOT_PARAM => {
+                let (slot, param) = ModMatrix::get_slot_param(index);
+                let name = match param {
+                    0 => "Source",
+                    1 => "Dest",
+                    2 => "Amount",
+                    _ => "Curve",
+                };
+
+                format!("Slot {} {}", slot + 1, name)
+            },
+            _ => "".to_string(),
+        }
+    }
+
+    /// Returns the text for one of the matrix's parameters, naming
+    /// destinations with `get_destination_name`.
+    pub fn get_parameter_text<F: Fn(i32) -> String>(&self, index: i32,
+                                                    get_destination_name: F) -> String {
+        match index {
+            LFO_SHAPE   => lfo::get_name(self.lfo.get_shape()),
+            LFO_RATE    => format!("{:.2}", self.lfo.get_frequency()),
+            ENV_ATTACK  => format!("{:.3}", self.envelope.get_attack()),
+            ENV_DECAY   => format!("{:.3}", self.envelope.get_decay()),
+            ENV_SUSTAIN => format!("{}", self.envelope.get_sustain() * 100.0),
+            ENV_RELEASE => format!("{:.3}", self.envelope.get_release()),
+            FIRST_SLOT_PARAM..=LAST_SLOT_PARAM => {
+                let (slot, param) = ModMatrix::get_slot_param(index);
+
+                self.slots.get(slot).map_or("".to_string(), |slot| match param {
+                    0 => get_name(slot.source),
+                    1 => slot.destination.map_or("Off".to_string(), get_destination_name),
+                    2 => format!("{:.0}", slot.amount * 100.0),
+                    _ => ccmap::get_name(slot.curve),
+                })
+            },
+            _ => "".to_string(),
+        }
+    }
+
+    pub fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            LFO_RATE => "Hz".to_string(),
+            ENV_ATTACK | ENV_DECAY | ENV_RELEASE => "s".to_string(),
+            ENV_SUSTAIN => "%".to_string(),
+            FIRST_SLOT_PARAM..=LAST_SLOT_PARAM
+                if ModMatrix::get_slot_param(index).1 == 2 => "%".to_string(),
+            _ => "".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    fn get_slot(source: Source, destination: i32, amount: f32) -> Slot {
+        Slot {
+            source,
+            destination: Some(destination),
+            amount,
+            ..Slot::default()
+        }
+    }
+
+    #[test]
+    fn test_modulation()
+    {
+        let mut matrix = ModMatrix::new(&[0, 1, 2, 5], 44100.0);
+        matrix.set_base(2, 0.25);
+        matrix.set_slot(0, get_slot(Source::Velocity, 2, 0.5));
+        matrix.set_slot(1, get_slot(Source::ModWheel, 2, -1.0));
+        matrix.note_on(60, 1.0);
+
+        assert!(matrix.is_modulated(2));
+        assert!(!matrix.is_modulated(1));
+        assert_eq!(matrix.get_modulated_base(2), Some(0.25));
+        assert!(floats_equal(matrix.next_value(2).unwrap(), 0.75));
+        assert_eq!(matrix.next_value(1), None);
+
+        // Slots add together, and the result stays in range.
+        matrix.process_midi_event([0xb0, 1, 127]);
+        assert!(floats_equal(matrix.next_value(2).unwrap(), 0.0));
+
+        // Once the modulation stops, the base is given back just once.
+        matrix.set_slot(0, Slot::default());
+        matrix.set_slot(1, Slot::default());
+        assert_eq!(matrix.next_value(2), Some(0.25));
+        assert_eq!(matrix.next_value(2), None);
+        assert_eq!(matrix.get_modulated_base(2), None);
+
+        // Only the listed parameters can be modulated.
+        matrix.set_slot(0, get_slot(Source::Velocity, 4, 1.0));
+        assert_eq!(matrix.get_slot(0).unwrap().destination, None);
+        matrix.set_slot(0, get_slot(Source::Velocity, 5, 1.0));
+        assert_eq!(matrix.get_slot(0).unwrap().destination, Some(5));
+    }
+
+    #[test]
+    fn test_sources()
+    {
+        let mut matrix = ModMatrix::new(&[0], 44100.0);
+        matrix.note_on(127, 0.0);
+        matrix.process_midi_event([0xd3, 64, 0]);
+
+        assert!(floats_equal(matrix.get_source_value(Source::Key), 1.0));
+        assert!(floats_equal(matrix.get_source_value(Source::Velocity), 0.0));
+        assert!(floats_equal(matrix.get_source_value(Source::Aftertouch), 64.0 / 127.0));
+
+        // The envelope and LFO move on with each block.
+        matrix.set_parameter(ENV_ATTACK, 0.0);
+        matrix.advance(441);
+        assert!(floats_equal(matrix.get_source_value(Source::Envelope), 1.0));
+
+        // Curves keep the sign of bipolar sources.
+        let slot = Slot {
+            curve: Curve::Exponential,
+            ..get_slot(Source::Lfo, 0, 0.5)
+        };
+        assert!(floats_equal(slot.get_modulation(-0.5), -0.125));
+        assert!(floats_equal(slot.get_modulation(0.5), 0.125));
+    }
+
+    #[test]
+    fn test_parameters()
+    {
+        let mut matrix = ModMatrix::new(&[0, 1, 3], 44100.0);
+        let destination = FIRST_SLOT_PARAM + PARAMS_PER_SLOT + 1;
+
+        // The choices are off, then each of the destinations.
+        matrix.set_parameter(destination, choice_to_param(3, 4));
+        assert_eq!(matrix.get_slot(1).unwrap().destination, Some(3));
+        assert_eq!(matrix.get_parameter_name(destination), "Slot 2 Dest");
+        assert_eq!(matrix.get_parameter_text(destination, |index| format!("P{}", index)), "P3");
+        assert!(floats_equal(matrix.get_parameter(destination), choice_to_param(3, 4)));
+
+        matrix.set_parameter(destination, choice_to_param(1, 4));
+        assert_eq!(matrix.get_slot(1).unwrap().destination, Some(0));
+
+        matrix.set_parameter(destination + 1, 0.25);
+        assert!(floats_equal(matrix.get_slot(1).unwrap().amount, -0.5));
+        assert!(floats_equal(matrix.get_parameter(destination + 1), 0.25));
+
+        matrix.set_parameter(destination, 0.0);
+        assert_eq!(matrix.get_parameter_text(destination, |index| format!("P{}", index)), "Off");
+    }
+
+    #[test]
+    fn test_chunk()
+    {
+        let mut matrix = ModMatrix::new(&[0, 1, 3], 44100.0);
+        matrix.set_slot(0, get_slot(Source::Lfo, 3, 0.5));
+        matrix.set_slot(2, get_slot(Source::Key, 1, 0.5));
+
+        let mut chunk = Chunk::new();
+        matrix.save(&mut chunk);
+
+        // A plugin which has gained parameters still finds the same ones,
+        // whatever the Dest parameters were saved as.
+        let mut loaded = ModMatrix::new(&[0, 1, 2, 3], 44100.0);
+        loaded.set_slot(1, get_slot(Source::Lfo, 2, 0.5));
+        loaded.set_slot(2, get_slot(Source::Key, 3, 0.5));
+        loaded.load(&Chunk::parse(&chunk.to_bytes()));
+
+        assert_eq!(loaded.get_slot(0).unwrap().destination, Some(3));
+        assert_eq!(loaded.get_slot(1).unwrap().destination, None);
+        assert_eq!(loaded.get_slot(2).unwrap().destination, Some(1));
+        assert_eq!(loaded.get_slot(2).unwrap().source, Source::Key);
+
+        // Without saved destinations, the slots are left alone.
+        loaded.load(&Chunk::new());
+        assert_eq!(loaded.get_slot(0).unwrap().destination, Some(3));
+    }
+}
@@ -0,0 +1,272 @@
+extern crate vst;
+
+use std::mem;
+
+use self::vst::host::Host;
+use self::vst::plugin::Plugin;
+
+use ccmap::CcMap;
+use chunk::Chunk;
+use modmatrix;
+use modmatrix::ModMatrix;
+
+/// Modulation
+///
+/// The modulation matrix and CC map of a plugin, which both drive its
+/// parameters. Every parameter can be modulated apart from the CC learn
+/// controls and the matrix's own, and every one can be mapped apart from
+/// the CC learn controls.
+pub struct Modulation {
+    matrix: ModMatrix,
+    cc_map: CcMap,
+}
+
+impl Modulation {
+    /// Creates the modulation for a plugin whose matrix parameters start at
+    /// `first_matrix_param`, and which controls CC learning with the
+    /// parameters in `cc_params`.
+    pub fn new(num_parameters: i32, cc_params: &[i32], first_matrix_param: i32,
+               sample_rate: f32) -> Modulation {
+        let matrix_params = first_matrix_param..first_matrix_param + modmatrix::NUM_PARAMETERS;
+        let destinations: Vec<i32> = (0..num_parameters)
+                                         .filter(|index| !cc_params.contains(index)
+                                                         && !matrix_params.contains(index))
+                                         .collect();
+
+        Modulation {
+            matrix: ModMatrix::new(&destinations, sample_rate),
+            cc_map: CcMap::new(num_parameters, cc_params),
+        }
+    }
+
+    pub fn get_matrix(&self) -> &ModMatrix {
+        &self.matrix
+    }
+
+    pub fn get_matrix_mut(&mut self) -> &mut ModMatrix {
+        &mut self.matrix
+    }
+
+    pub fn get_cc_map(&self) -> &CcMap {
+        &self.cc_map
+    }
+
+    pub fn get_cc_map_mut(&mut self) -> &mut CcMap {
+        &mut self.cc_map
+    }
+
+    /// Returns where the host left a parameter, while it's modulated.
+    pub fn get_modulated_base(&self, index: i32) -> Option<f32> {
+        self.matrix.get_modulated_base(index)
+    }
+
+    pub fn save(&self, chunk: &mut Chunk) {
+        self.matrix.save(chunk);
+        self.cc_map.save(chunk);
+    }
+
+    /// Restores the matrix's destinations and the CC mappings from a chunk.
+    /// Call this after loading the parameters, so that the saved
+    /// destinations take the place of the saved Dest parameters.
+    pub fn load(&mut self, chunk: &Chunk) {
+        self.matrix.load(chunk);
+        self.cc_map.load(chunk);
+    }
+}
+
+/// Modulated
+///
+/// A plugin whose parameters are driven by its Modulation. The host sets
+/// the base of each parameter, and the plugin applies it along with any
+/// modulation through `set_parameter_value`. The plugin's `get_parameter`
+/// should give the host back the base of modulated parameters.
+pub trait Modulated: Plugin + Sized {
+    fn get_modulation(&self) -> &Modulation;
+
+    fn get_modulation_mut(&mut self) -> &mut Modulation;
+
+    /// Changes a parameter without touching its modulation base.
+    fn set_parameter_value(&mut self, index: i32, value: f32);
+
+    /// Sets a parameter's base, and applies it with any modulation on top.
+    /// This is what the plugin's `set_parameter` should do.
+    fn set_modulated_parameter(&mut self, index: i32, value: f32) {
+        let matrix = self.get_modulation_mut().get_matrix_mut();
+        matrix.set_base(index, value);
+
+        let value = matrix.get_value(index).unwrap_or(value);
+        self.set_parameter_value(index, value);
+    }
+
+    /// Moves the modulation sources on by a block, and applies them to the
+    /// parameters they modulate.
+    fn apply_modulation(&mut self, samples: usize) {
+        self.get_modulation_mut().get_matrix_mut().advance(samples);
+
+        for position in 0..self.get_modulation().get_matrix().get_destinations().len() {
+            let index = self.get_modulation().get_matrix().get_destinations()[position];
+
+            // Parameters the host hasn't set yet are modulated from where
+            // they are now.
+            let matrix = self.get_modulation().get_matrix();
+
+            if matrix.is_modulated(index) && matrix.get_base(index).is_none() {
+                let base = self.get_parameter(index);
+                self.get_modulation_mut().get_matrix_mut().set_base(index, base);
+            }
+
+            if let Some(value) = self.get_modulation_mut().get_matrix_mut().next_value(index) {
+                self.set_parameter_value(index, value);
+            }
+        }
+    }
+
+    /// Passes a MIDI message on to the matrix's sources and the CC map,
+    /// which tells the host about any parameters it sets.
+    fn process_modulation_event<H: Host>(&mut self, data: [u8; 3], host: &mut H) {
+        self.get_modulation_mut().get_matrix_mut().process_midi_event(data);
+
+        // The map sets parameters on the plugin, so it has to be taken out
+        // while it does.
+        let mut cc_map = mem::replace(self.get_modulation_mut().get_cc_map_mut(),
+                                      CcMap::new(0, &[]));
+        cc_map.process_midi_event(data, self, host);
+        *self.get_modulation_mut().get_cc_map_mut() = cc_map;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use self::vst::plugin::Info;
+    use modmatrix::{Slot, Source};
+    use param::choice_to_param;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    // Parameters 0 and 1 are ordinary ones, 2 is CC learning, and the
+    // matrix comes after them.
+    const NUM_PARAMETERS: i32 = 3 + modmatrix::NUM_PARAMETERS;
+
+    struct TestPlugin {
+        parameters: [f32; 2],
+        modulation: Modulation,
+    }
+
+    impl Default for TestPlugin {
+        fn default() -> TestPlugin {
+            TestPlugin {
+                parameters: [0.25, 0.5],
+                modulation: Modulation::new(NUM_PARAMETERS, &[2], 3, 44100.0),
+            }
+        }
+    }
+
+    impl Plugin for TestPlugin {
+        fn get_info(&self) -> Info {
+            Info {
+                parameters: NUM_PARAMETERS,
+                ..Info::default()
+            }
+        }
+
+        fn get_parameter(&self, index: i32) -> f32 {
+            if let Some(base) = self.modulation.get_modulated_base(index) {
+                return base;
+            }
+
+            self.parameters.get(index as usize).cloned().unwrap_or(0.0)
+        }
+
+        fn set_parameter(&mut self, index: i32, value: f32) {
+            self.set_modulated_parameter(index, value);
+        }
+    }
+
+    impl Modulated for TestPlugin {
+        fn get_modulation(&self) -> &Modulation {
+            &self.modulation
+        }
+
+        fn get_modulation_mut(&mut self) -> &mut Modulation {
+            &mut self.modulation
+        }
+
+        fn set_parameter_value(&mut self, index: i32, value: f32) {
+            match index {
+                0 | 1 => self.parameters[index as usize] = value,
+                2     => self.modulation.get_cc_map_mut().set_learn_param(value),
+                _     => (),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct TestHost {
+        automated: Vec<(i32, f32)>,
+    }
+
+    impl Host for TestHost {
+        fn automate(&mut self, index: i32, value: f32) {
+            self.automated.push((index, value));
+        }
+    }
+
+    #[test]
+    fn test_destinations()
+    {
+        let plugin = TestPlugin::default();
+        let matrix = plugin.modulation.get_matrix();
+
+        assert_eq!(matrix.get_destinations(), [0, 1]);
+        assert!(plugin.modulation.get_cc_map().is_target(3));
+        assert!(!plugin.modulation.get_cc_map().is_target(2));
+    }
+
+    #[test]
+    fn test_modulation()
+    {
+        let mut plugin = TestPlugin::default();
+        let mut host   = TestHost::default();
+
+        plugin.modulation.get_matrix_mut().set_slot(0, Slot {
+            source:      Source::ModWheel,
+            destination: Some(0),
+            amount:      0.5,
+            ..Slot::default()
+        });
+
+        // The base is taken from where the parameter is, and the host keeps
+        // seeing it there.
+        plugin.process_modulation_event([0xb0, 1, 127], &mut host);
+        plugin.apply_modulation(64);
+        assert!(floats_equal(plugin.parameters[0], 0.75));
+        assert!(floats_equal(plugin.get_parameter(0), 0.25));
+
+        plugin.set_parameter(0, 0.5);
+        assert!(floats_equal(plugin.parameters[0], 1.0));
+        assert!(floats_equal(plugin.get_parameter(0), 0.5));
+
+        // Unmodulated parameters are set as they are.
+        plugin.set_parameter(1, 0.75);
+        assert!(floats_equal(plugin.parameters[1], 0.75));
+        assert!(host.automated.is_empty());
+    }
+
+    #[test]
+    fn test_control_change()
+    {
+        let mut plugin = TestPlugin::default();
+        let mut host   = TestHost::default();
+
+        // The learn choices are off, then parameters 0, 1 and the matrix's.
+        plugin.set_parameter(2, choice_to_param(2, modmatrix::NUM_PARAMETERS as usize + 3));
+        plugin.process_modulation_event([0xb0, 7, 127], &mut host);
+
+        assert!(floats_equal(plugin.parameters[1], 1.0));
+        assert_eq!(host.automated, vec![(1, 1.0)]);
+        assert_eq!(plugin.modulation.get_cc_map().get_mappings().len(), 1);
+    }
+}
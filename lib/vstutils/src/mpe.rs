@@ -0,0 +1,401 @@
+// MIDI Polyphonic Expression, where each note gets a channel of its own so
+// that it can be bent and pressed independently of the others. Channels are
+// grouped into zones by the MPE configuration message: the lower zone is
+// managed from channel 1 and uses the channels above it, and the upper zone
+// is managed from channel 16 and uses the channels below it.
+
+const NUM_CHANNELS: usize = 16;
+const NUM_NOTES: usize = 128;
+
+const LOWER_MASTER: u8 = 0;
+const UPPER_MASTER: u8 = 15;
+
+// With both zones in use, they have to share the channels between the two
+// masters.
+const MAX_MEMBERS: u8 = UPPER_MASTER - LOWER_MASTER - 1;
+
+const CONTROL_CHANGE: u8 = 0xb0;
+const CHANNEL_PRESSURE: u8 = 0xd0;
+const PITCH_BEND: u8 = 0xe0;
+
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const TIMBRE: u8 = 74;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+
+const RPN_PITCH_BEND_RANGE: (u8, u8) = (0, 0);
+const RPN_MPE_CONFIGURATION: (u8, u8) = (0, 6);
+
+// The bend ranges which the MPE specification asks for when a zone is set
+// up, in semitones.
+const DEFAULT_BEND_RANGE: f32 = 2.0;
+const DEFAULT_MEMBER_BEND_RANGE: f32 = 48.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Zone {
+    Lower,
+    Upper,
+}
+
+/// Expression
+///
+/// The expression for a note. The bend is in semitones, and pressure and
+/// timbre are in the range 0-1. Outside of a zone, pressure is full and
+/// timbre is centred, so notes sound as they would without MPE.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Expression {
+    pub bend:     f32,
+    pub pressure: f32,
+    pub timbre:   f32,
+}
+
+impl Default for Expression {
+    fn default() -> Expression {
+        Expression {
+            bend:     0.0,
+            pressure: 1.0,
+            timbre:   0.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    bend:       f32,
+    bend_range: f32,
+    pressure:   f32,
+    timbre:     f32,
+    // The registered parameter which data entry applies to, if any.
+    rpn:        Option<(u8, u8)>,
+    rpn_msb:    u8,
+}
+
+impl Default for Channel {
+    fn default() -> Channel {
+        Channel {
+            bend:       0.0,
+            bend_range: DEFAULT_BEND_RANGE,
+            pressure:   0.0,
+            timbre:     0.5,
+            rpn:        None,
+            rpn_msb:    0x7f,
+        }
+    }
+}
+
+/// Mpe
+///
+/// Tracks the MPE zones and the pitch bend, pressure and timbre (CC 74) of
+/// every channel. Until a zone is configured every channel is an ordinary
+/// one, which still responds to pitch bend. The channel each note was last
+/// played on is kept too, so that a note's expression can be found from
+/// the note.
+pub struct Mpe {
+    channels:      [Channel; NUM_CHANNELS],
+    note_channels: [u8; NUM_NOTES],
+    lower_members: u8,
+    upper_members: u8,
+}
+
+impl Default for Mpe {
+    fn default() -> Mpe {
+        Mpe {
+            channels:      [Channel::default(); NUM_CHANNELS],
+            note_channels: [0; NUM_NOTES],
+            lower_members: 0,
+            upper_members: 0,
+        }
+    }
+}
+
+impl Mpe {
+    pub fn new() -> Mpe {
+        Mpe::default()
+    }
+
+    /// Returns the number of member channels in a zone, which is 0 if the
+    /// zone isn't in use.
+    pub fn get_members(&self, zone: Zone) -> u8 {
+        match zone {
+            Zone::Lower => self.lower_members,
+            Zone::Upper => self.upper_members,
+        }
+    }
+
+    /// Sets the number of member channels in a zone, as the MPE
+    /// configuration message does. A zone which grows into the other one
+    /// shrinks it, and setting up a zone resets its bend ranges.
+    pub fn set_members(&mut self, zone: Zone, members: u8) {
+        let members = members.min(UPPER_MASTER - LOWER_MASTER);
+
+        match zone {
+            Zone::Lower => {
+                self.lower_members = members;
+                self.upper_members = self.upper_members.min(MAX_MEMBERS.saturating_sub(members));
+            },
+            Zone::Upper => {
+                self.upper_members = members;
+                self.lower_members = self.lower_members.min(MAX_MEMBERS.saturating_sub(members));
+            },
+        }
+
+        for channel in 0..NUM_CHANNELS as u8 {
+            if self.get_zone(channel) == Some(zone) {
+                self.channels[channel as usize].bend_range = if self.is_member(channel) {
+                    DEFAULT_MEMBER_BEND_RANGE
+                }
+                else {
+                    DEFAULT_BEND_RANGE
+                };
+            }
+        }
+    }
+
+    /// Returns the zone which a channel (0-15) belongs to, as either its
+    /// master or one of its members.
+    pub fn get_zone(&self, channel: u8) -> Option<Zone> {
+        if self.lower_members > 0 && channel <= LOWER_MASTER + self.lower_members {
+            Some(Zone::Lower)
+        }
+        else if self.upper_members > 0 && channel >= UPPER_MASTER - self.upper_members
+                && channel <= UPPER_MASTER {
+            Some(Zone::Upper)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Returns whether a channel carries the expression of individual notes.
+    pub fn is_member(&self, channel: u8) -> bool {
+        match self.get_zone(channel) {
+            Some(Zone::Lower) => channel != LOWER_MASTER,
+            Some(Zone::Upper) => channel != UPPER_MASTER,
+            None              => false,
+        }
+    }
+
+    fn get_bend(&self, channel: u8) -> f32 {
+        let state = &self.channels[channel as usize];
+        state.bend * state.bend_range
+    }
+
+    /// Returns the expression for a note playing on a channel. Notes on a
+    /// member channel are also bent by their zone's master channel.
+    pub fn get_expression(&self, channel: u8) -> Expression {
+        let channel = channel & 0x0f;
+
+        if self.is_member(channel) {
+            let master = match self.get_zone(channel) {
+                Some(Zone::Upper) => UPPER_MASTER,
+                _                 => LOWER_MASTER,
+            };
+            let state = &self.channels[channel as usize];
+
+            Expression {
+                bend:     self.get_bend(channel) + self.get_bend(master),
+                pressure: state.pressure,
+                timbre:   state.timbre,
+            }
+        }
+        else {
+            Expression {
+                bend: self.get_bend(channel),
+                ..Expression::default()
+            }
+        }
+    }
+
+    /// Remembers which channel a note was played on.
+    pub fn note_on(&mut self, note: u8, channel: u8) {
+        if let Some(note_channel) = self.note_channels.get_mut(note as usize) {
+            *note_channel = channel & 0x0f;
+        }
+    }
+
+    /// Returns the expression for a note, from the channel it was last
+    /// played on.
+    pub fn get_note_expression(&self, note: u8) -> Expression {
+        self.note_channels
+            .get(note as usize)
+            .map_or(Expression::default(), |&channel| self.get_expression(channel))
+    }
+
+    // A bend range sent to a member channel applies to the whole zone.
+    fn set_bend_range(&mut self, channel: u8, bend_range: f32) {
+        if self.is_member(channel) {
+            for member in 0..NUM_CHANNELS as u8 {
+                if self.is_member(member) && self.get_zone(member) == self.get_zone(channel) {
+                    self.channels[member as usize].bend_range = bend_range;
+                }
+            }
+        }
+        else {
+            self.channels[channel as usize].bend_range = bend_range;
+        }
+    }
+
+    fn data_entry(&mut self, channel: u8, controller: u8, value: u8) {
+        match (self.channels[channel as usize].rpn, controller) {
+            (Some(RPN_PITCH_BEND_RANGE), DATA_ENTRY_MSB) =>
+                self.set_bend_range(channel, f32::from(value)),
+            // The fine part of the bend range is in cents.
+            (Some(RPN_PITCH_BEND_RANGE), DATA_ENTRY_LSB) => {
+                let semitones = self.channels[channel as usize].bend_range.trunc();
+                self.set_bend_range(channel, semitones + f32::from(value) / 100.0);
+            },
+            (Some(RPN_MPE_CONFIGURATION), DATA_ENTRY_MSB) => match channel {
+                LOWER_MASTER => self.set_members(Zone::Lower, value),
+                UPPER_MASTER => self.set_members(Zone::Upper, value),
+                _            => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn control_change(&mut self, channel: u8, controller: u8, value: u8) {
+        let state = &mut self.channels[channel as usize];
+
+        match controller {
+            TIMBRE  => state.timbre = f32::from(value) / 127.0,
+            RPN_MSB => state.rpn_msb = value,
+            RPN_LSB => state.rpn = if state.rpn_msb == 0x7f && value == 0x7f {
+                None
+            }
+            else {
+                Some((state.rpn_msb, value))
+            },
+            // Data entry for non-registered parameters mustn't change
+            // registered ones.
+            NRPN_MSB | NRPN_LSB => state.rpn = None,
+            DATA_ENTRY_MSB | DATA_ENTRY_LSB => self.data_entry(channel, controller, value),
+            _ => (),
+        }
+    }
+
+    /// Updates the expression from a MIDI message. Notes are passed in with
+    /// `note_on`, so note on and off messages are ignored.
+    pub fn process_midi_event(&mut self, data: [u8; 3]) {
+        let channel = data[0] & 0x0f;
+
+        match data[0] & 0xf0 {
+            CONTROL_CHANGE => self.control_change(channel, data[1], data[2]),
+            CHANNEL_PRESSURE =>
+                self.channels[channel as usize].pressure = f32::from(data[1]) / 127.0,
+            PITCH_BEND => {
+                let value = u32::from(data[2]) << 7 | u32::from(data[1]);
+                self.channels[channel as usize].bend = (value as f32 - 8192.0) / 8192.0;
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.001
+    }
+
+    fn set_rpn(mpe: &mut Mpe, channel: u8, rpn: (u8, u8), value: u8) {
+        mpe.process_midi_event([CONTROL_CHANGE | channel, RPN_MSB, rpn.0]);
+        mpe.process_midi_event([CONTROL_CHANGE | channel, RPN_LSB, rpn.1]);
+        mpe.process_midi_event([CONTROL_CHANGE | channel, DATA_ENTRY_MSB, value]);
+    }
+
+    #[test]
+    fn test_without_zones()
+    {
+        let mut mpe = Mpe::new();
+
+        // Bend up by half of the default two semitones.
+        mpe.process_midi_event([PITCH_BEND, 0x00, 0x60]);
+        mpe.process_midi_event([CHANNEL_PRESSURE, 0x00, 0x00]);
+
+        let expression = mpe.get_expression(0);
+        assert!(floats_equal(expression.bend, 1.0));
+        assert!(floats_equal(expression.pressure, 1.0));
+        assert!(floats_equal(expression.timbre, 0.5));
+        assert_eq!(mpe.get_zone(0), None);
+    }
+
+    #[test]
+    fn test_zones()
+    {
+        let mut mpe = Mpe::new();
+        set_rpn(&mut mpe, LOWER_MASTER, RPN_MPE_CONFIGURATION, 7);
+        set_rpn(&mut mpe, UPPER_MASTER, RPN_MPE_CONFIGURATION, 7);
+
+        assert_eq!(mpe.get_members(Zone::Lower), 7);
+        assert_eq!(mpe.get_members(Zone::Upper), 7);
+        assert_eq!(mpe.get_zone(7), Some(Zone::Lower));
+        assert_eq!(mpe.get_zone(8), Some(Zone::Upper));
+        assert!(!mpe.is_member(0));
+        assert!(mpe.is_member(1));
+
+        // Growing the lower zone shrinks the upper one.
+        set_rpn(&mut mpe, LOWER_MASTER, RPN_MPE_CONFIGURATION, 10);
+        assert_eq!(mpe.get_members(Zone::Upper), 4);
+        assert_eq!(mpe.get_zone(10), Some(Zone::Lower));
+        assert_eq!(mpe.get_zone(11), Some(Zone::Upper));
+
+        set_rpn(&mut mpe, UPPER_MASTER, RPN_MPE_CONFIGURATION, 0);
+        assert_eq!(mpe.get_zone(15), None);
+    }
+
+    #[test]
+    fn test_member_expression()
+    {
+        let mut mpe = Mpe::new();
+        set_rpn(&mut mpe, LOWER_MASTER, RPN_MPE_CONFIGURATION, 15);
+
+        // A full bend on a member is 48 semitones, and the master adds its
+        // own two semitones on top.
+        mpe.process_midi_event([PITCH_BEND | 3, 0x7f, 0x7f]);
+        mpe.process_midi_event([PITCH_BEND | LOWER_MASTER, 0x00, 0x00]);
+        mpe.process_midi_event([CHANNEL_PRESSURE | 3, 0x7f, 0x00]);
+        mpe.process_midi_event([CONTROL_CHANGE | 3, TIMBRE, 0x00]);
+
+        let expression = mpe.get_expression(3);
+        assert!(floats_equal(expression.bend, 48.0 * 8191.0 / 8192.0 - 2.0));
+        assert!(floats_equal(expression.pressure, 1.0));
+        assert!(floats_equal(expression.timbre, 0.0));
+
+        // Other members are unaffected, apart from the master's bend.
+        let expression = mpe.get_expression(4);
+        assert!(floats_equal(expression.bend, -2.0));
+        assert!(floats_equal(expression.pressure, 0.0));
+
+        // Notes follow the channel they were played on.
+        mpe.note_on(60, 3);
+        mpe.note_on(64, 4);
+        assert_eq!(mpe.get_note_expression(60), mpe.get_expression(3));
+        assert_eq!(mpe.get_note_expression(64), mpe.get_expression(4));
+        mpe.note_on(60, 4);
+        assert_eq!(mpe.get_note_expression(60), mpe.get_expression(4));
+    }
+
+    #[test]
+    fn test_bend_range()
+    {
+        let mut mpe = Mpe::new();
+        set_rpn(&mut mpe, LOWER_MASTER, RPN_MPE_CONFIGURATION, 15);
+
+        // A range sent to one member applies to all of them.
+        set_rpn(&mut mpe, 2, RPN_PITCH_BEND_RANGE, 24);
+        mpe.process_midi_event([CONTROL_CHANGE | 2, DATA_ENTRY_LSB, 50]);
+        mpe.process_midi_event([PITCH_BEND | 5, 0x00, 0x00]);
+        assert!(floats_equal(mpe.get_expression(5).bend, -24.5));
+
+        // Non-registered parameters don't change the range.
+        mpe.process_midi_event([CONTROL_CHANGE | 5, NRPN_MSB, 0]);
+        mpe.process_midi_event([CONTROL_CHANGE | 5, NRPN_LSB, 0]);
+        mpe.process_midi_event([CONTROL_CHANGE | 5, DATA_ENTRY_MSB, 12]);
+        assert!(floats_equal(mpe.get_expression(5).bend, -24.5));
+    }
+}
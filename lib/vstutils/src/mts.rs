@@ -0,0 +1,211 @@
+// Parsing for MIDI Tuning Standard messages, which retune notes while an
+// instrument is playing. Tuning program and bank numbers are ignored, and
+// every message applies to whatever tuning is in use.
+
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+
+const NON_REAL_TIME: u8 = 0x7e;
+const REAL_TIME: u8 = 0x7f;
+const MIDI_TUNING: u8 = 0x08;
+
+const SINGLE_NOTE: u8 = 0x02;
+const SINGLE_NOTE_BANK: u8 = 0x07;
+const SCALE_OCTAVE_1_BYTE: u8 = 0x08;
+const SCALE_OCTAVE_2_BYTE: u8 = 0x09;
+
+// A frequency of 7f 7f 7f means "leave this note alone".
+const NO_CHANGE: [u8; 3] = [0x7f, 0x7f, 0x7f];
+
+const PITCH_CLASSES: usize = 12;
+
+pub enum Message<'a> {
+    /// New pitches for individual notes.
+    SingleNote(SingleNoteChanges<'a>),
+    /// Offsets in cents from equal temperament for each of the twelve pitch
+    /// classes, starting from C, which apply in every octave.
+    ScaleOctave([f32; PITCH_CLASSES]),
+}
+
+/// SingleNoteChanges
+///
+/// The changes in a single note tuning message, read straight from the
+/// message as (note, pitch) pairs. Pitches are MIDI note numbers, with a
+/// fractional part for the tuning between semitones.
+pub struct SingleNoteChanges<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for SingleNoteChanges<'a> {
+    type Item = (u8, f32);
+
+    fn next(&mut self) -> Option<(u8, f32)> {
+        while self.data.len() >= 4 {
+            let (change, rest) = self.data.split_at(4);
+            self.data = rest;
+
+            if change[1..] != NO_CHANGE {
+                let fraction = (u32::from(change[2]) << 7 | u32::from(change[3])) as f32
+                             / 16384.0;
+
+                return Some((change[0], f32::from(change[1]) + fraction));
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_single_note(data: &[u8]) -> Option<Message<'_>> {
+    let (&count, changes) = data.split_first()?;
+    let length = count as usize * 4;
+
+    if changes.len() < length {
+        return None;
+    }
+
+    Some(Message::SingleNote(SingleNoteChanges {
+        data: &changes[..length],
+    }))
+}
+
+fn parse_scale_octave(data: &[u8], bytes_per_class: usize) -> Option<Message<'_>> {
+    // Skip the three bytes which select MIDI channels. This instrument
+    // plays the same tuning on every channel.
+    let offsets = data.get(3..3 + PITCH_CLASSES * bytes_per_class)?;
+    let mut cents = [0.0; PITCH_CLASSES];
+
+    for (class, offset) in offsets.chunks(bytes_per_class).enumerate() {
+        cents[class] = if bytes_per_class == 1 {
+            // 0 to 127 covers -64 to +63 cents.
+            f32::from(offset[0]) - 64.0
+        }
+        else {
+            // 14 bits cover -100 to +100 cents.
+            let value = u32::from(offset[0]) << 7 | u32::from(offset[1]);
+            (value as f32 - 8192.0) * 100.0 / 8192.0
+        };
+    }
+
+    Some(Message::ScaleOctave(cents))
+}
+
+/// Parses a SysEx message, returning None if it isn't a tuning message
+/// which can be applied in real time. The start and end bytes are optional.
+pub fn parse(payload: &[u8]) -> Option<Message<'_>> {
+    let mut data = payload;
+
+    if data.first() == Some(&SYSEX_START) {
+        data = &data[1..];
+    }
+    if data.last() == Some(&SYSEX_END) {
+        data = &data[..data.len() - 1];
+    }
+
+    if data.len() < 4
+       || (data[0] != NON_REAL_TIME && data[0] != REAL_TIME)
+       || data[2] != MIDI_TUNING
+       || data.iter().any(|&byte| byte >= 0x80) {
+        return None;
+    }
+
+    let body = &data[4..];
+
+    match data[3] {
+        // Skip the program, and the bank as well if there is one.
+        SINGLE_NOTE         => parse_single_note(body.get(1..)?),
+        SINGLE_NOTE_BANK    => parse_single_note(body.get(2..)?),
+        SCALE_OCTAVE_1_BYTE => parse_scale_octave(body, 1),
+        SCALE_OCTAVE_2_BYTE => parse_scale_octave(body, 2),
+        _                   => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_single_note()
+    {
+        // Retune A4 up by half a semitone, and leave C4 alone.
+        let payload = [0xf0, 0x7f, 0x00, 0x08, 0x02, 0x00, 0x02,
+                       0x45, 0x45, 0x40, 0x00,
+                       0x3c, 0x7f, 0x7f, 0x7f,
+                       0xf7];
+
+        match parse(&payload) {
+            Some(Message::SingleNote(changes)) => {
+                let changes: Vec<(u8, f32)> = changes.collect();
+                assert_eq!(changes.len(), 1);
+                assert_eq!(changes[0].0, 69);
+                assert!(floats_equal(changes[0].1, 69.5));
+            },
+            _ => panic!("expected a single note message"),
+        }
+
+        // The banked form, without the start and end bytes.
+        let payload = [0x7e, 0x7f, 0x08, 0x07, 0x01, 0x00, 0x01,
+                       0x3c, 0x3b, 0x7f, 0x7f];
+
+        match parse(&payload) {
+            Some(Message::SingleNote(mut changes)) => {
+                let (note, pitch) = changes.next().unwrap();
+                assert_eq!(note, 60);
+                assert!(floats_equal(pitch, 59.0 + 16383.0 / 16384.0));
+                assert!(changes.next().is_none());
+            },
+            _ => panic!("expected a single note message"),
+        }
+    }
+
+    #[test]
+    fn test_scale_octave()
+    {
+        let mut payload = vec![0xf0, 0x7e, 0x7f, 0x08, 0x08, 0x03, 0x7f, 0x7f];
+        payload.extend_from_slice(&[0x40, 0x00, 0x7f, 0x40, 0x40, 0x40,
+                                    0x40, 0x40, 0x40, 0x40, 0x40, 0x40]);
+        payload.push(0xf7);
+
+        match parse(&payload) {
+            Some(Message::ScaleOctave(cents)) => {
+                assert!(floats_equal(cents[0], 0.0));
+                assert!(floats_equal(cents[1], -64.0));
+                assert!(floats_equal(cents[2], 63.0));
+            },
+            _ => panic!("expected a scale/octave message"),
+        }
+
+        let mut payload = vec![0x7f, 0x7f, 0x08, 0x09, 0x03, 0x7f, 0x7f];
+        payload.extend_from_slice(&[0x40, 0x00, 0x00, 0x00, 0x7f, 0x7f]);
+        for _ in 0..9 {
+            payload.extend_from_slice(&[0x40, 0x00]);
+        }
+
+        match parse(&payload) {
+            Some(Message::ScaleOctave(cents)) => {
+                assert!(floats_equal(cents[0], 0.0));
+                assert!(floats_equal(cents[1], -100.0));
+                assert!(floats_equal(cents[2], 16383.0 * 100.0 / 8192.0 - 100.0));
+                assert!(floats_equal(cents[11], 0.0));
+            },
+            _ => panic!("expected a scale/octave message"),
+        }
+    }
+
+    #[test]
+    fn test_ignored_messages()
+    {
+        // Too short, not a tuning message, a bulk dump and a truncated
+        // message.
+        assert!(parse(&[0xf0, 0x7f, 0xf7]).is_none());
+        assert!(parse(&[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]).is_none());
+        assert!(parse(&[0xf0, 0x7e, 0x7f, 0x08, 0x01, 0x00, 0xf7]).is_none());
+        assert!(parse(&[0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x02, 0x45, 0x45, 0x40, 0x00, 0xf7])
+                    .is_none());
+    }
+}
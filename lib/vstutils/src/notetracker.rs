@@ -11,9 +11,9 @@ pub struct NoteTracker {
 impl NoteTracker {
     pub fn new(polyphony: usize, extra_notes_count: usize) -> NoteTracker {
         NoteTracker {
-            polyphony:         polyphony,
+            polyphony,
             playing_notes:     VecDeque::with_capacity(polyphony),
-            extra_notes_count: extra_notes_count,
+            extra_notes_count,
             extra_notes:       Vec::with_capacity(extra_notes_count),
         }
     }
@@ -31,12 +31,9 @@ impl NoteTracker {
             self.playing_notes.push_back(note);
         }
         else if self.space_for_extra_note() {
-            match self.playing_notes.pop_front() {
-                Some(popped_note) => {
-                    self.extra_notes.push(popped_note);
-                    self.playing_notes.push_back(note);
-                },
-                None => (),
+            if let Some(popped_note) = self.playing_notes.pop_front() {
+                self.extra_notes.push(popped_note);
+                self.playing_notes.push_back(note);
             }
         }
     }
@@ -46,11 +43,8 @@ impl NoteTracker {
         self.playing_notes.retain(|&x| x != note);
 
         if self.space_for_playing_note() {
-            match self.extra_notes.pop() {
-                Some(popped_note) => {
-                    self.playing_notes.push_front(popped_note);
-                },
-                None => (),
+            if let Some(popped_note) = self.extra_notes.pop() {
+                self.playing_notes.push_front(popped_note);
             }
         }
     }
@@ -58,6 +52,25 @@ impl NoteTracker {
     pub fn get_playing_notes(&self) -> Vec<u8> {
         self.playing_notes.iter().copied().collect()
     }
+
+    /// Goes through the playing notes, oldest first, without allocating.
+    pub fn iter_playing_notes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.playing_notes.iter().copied()
+    }
+
+    pub fn is_playing(&self, note: u8) -> bool {
+        self.playing_notes.contains(&note)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.playing_notes.is_empty()
+    }
+
+    /// Forgets every note, keeping the space set aside for them.
+    pub fn clear(&mut self) {
+        self.playing_notes.clear();
+        self.extra_notes.clear();
+    }
 }
 
 #[cfg(test)]
@@ -131,4 +144,22 @@ mod test {
         assert_eq!(tracker.playing_notes, []);
         assert_eq!(tracker.extra_notes,   []);
     }
+
+    #[test]
+    fn test_clear()
+    {
+        let mut tracker = NoteTracker::new(2, 1);
+
+        tracker.note_on(60);
+        tracker.note_on(64);
+        tracker.note_on(67);
+        assert!(tracker.is_playing(67));
+        assert!(!tracker.is_playing(60));
+        assert_eq!(tracker.iter_playing_notes().collect::<Vec<u8>>(), [64, 67]);
+
+        tracker.clear();
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.extra_notes, []);
+        assert_eq!(tracker.playing_notes.capacity(), 2);
+    }
 }
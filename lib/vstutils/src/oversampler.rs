@@ -0,0 +1,476 @@
+use std::f32::consts::PI;
+
+/// Quality
+///
+/// Higher qualities use longer half-band filters, which give a sharper
+/// cut-off and better alias rejection at the cost of CPU and latency.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    // Half-band filters have 4k + 3 taps, where every other tap apart from
+    // the centre one is zero. This returns k.
+    fn get_half_order(self) -> usize {
+        match self {
+            Quality::Low    => 3,
+            Quality::Medium => 7,
+            Quality::High   => 15,
+        }
+    }
+}
+
+pub const MAX_FACTOR: usize = 8;
+
+const MAX_STAGES: usize = 3;
+const MAX_HALF_ORDER: usize = 15;
+
+/// Returns the number of 2x stages needed for a factor of 1, 2, 4 or 8.
+/// Other factors are rounded down to one of those.
+fn get_num_stages(factor: usize) -> usize {
+    let factor = factor.clamp(1, MAX_FACTOR);
+
+    (usize::BITS - 1 - factor.leading_zeros()) as usize
+}
+
+/// Returns the delay added by the filters for an oversampling factor and
+/// quality, in samples at the base rate. Fractional delays are made up to
+/// whole samples, so that hosts can compensate for them exactly.
+pub fn get_latency(factor: usize, quality: Quality) -> usize {
+    get_whole_latency(get_filter_latency(get_num_stages(factor), quality))
+}
+
+fn get_filter_latency(stages: usize, quality: Quality) -> f32 {
+    // Each stage delays by the centre tap on the way up and again on the
+    // way down, at twice the rate of the stage below it.
+    let centre = (2 * quality.get_half_order() + 1) as f32;
+
+    (0..stages).map(|stage| centre / (1 << stage) as f32).sum()
+}
+
+// The fractional delay which makes up the rest of a sample is kept to at
+// least half a sample, where its allpass is well behaved.
+fn get_whole_latency(latency: f32) -> usize {
+    if latency.fract() == 0.0 {
+        latency as usize
+    }
+    else {
+        (latency + 0.5).ceil() as usize
+    }
+}
+
+/// Fills `coefficients` with the non-zero coefficients of a half-band
+/// lowpass filter with 4k + 3 taps, excluding the centre tap (which is
+/// always 0.5).
+fn design_half_band(half_order: usize, coefficients: &mut Vec<f32>) {
+    let num_taps = 4 * half_order + 3;
+    let centre   = (2 * half_order + 1) as f32;
+
+    coefficients.clear();
+    coefficients.extend((0..2 * half_order + 2).map(|index| {
+        let tap    = (index * 2) as f32;
+        let offset = (tap - centre) / 2.0;
+        let sinc   = (PI * offset).sin() / (PI * offset);
+
+        // Blackman window
+        let theta  = 2.0 * PI * tap / (num_taps - 1) as f32;
+        let window = 0.42 - 0.5 * theta.cos() + 0.08 * (2.0 * theta).cos();
+
+        0.5 * sinc * window
+    }));
+
+    // Normalise for unity gain at DC: the centre tap provides half of it.
+    let sum: f32 = coefficients.iter().sum();
+    for coefficient in coefficients.iter_mut() {
+        *coefficient *= 0.5 / sum;
+    }
+}
+
+/// Clears a delay line and sets its length, without reallocating it as
+/// long as it's within its capacity.
+fn resize(history: &mut Vec<f32>, length: usize) {
+    history.clear();
+    history.resize(length, 0.0);
+}
+
+/// Pushes a value onto the front of a delay line, dropping the oldest.
+fn push(history: &mut [f32], value: f32) {
+    history.rotate_right(1);
+    history[0] = value;
+}
+
+/// HalfBandUp
+///
+/// Doubles the sample rate. The polyphase form splits the half-band filter
+/// into the branch of non-zero taps, which produces the even output samples,
+/// and the centre tap, which reduces to a delay for the odd ones.
+struct HalfBandUp {
+    coefficients: Vec<f32>,
+    history:      Vec<f32>,
+    half_order:   usize,
+}
+
+impl HalfBandUp {
+    fn new(quality: Quality) -> HalfBandUp {
+        let mut stage = HalfBandUp {
+            coefficients: Vec::with_capacity(2 * MAX_HALF_ORDER + 2),
+            history:      Vec::with_capacity(2 * MAX_HALF_ORDER + 2),
+            half_order:   0,
+        };
+
+        stage.set_quality(quality);
+        stage
+    }
+
+    fn set_quality(&mut self, quality: Quality) {
+        self.half_order = quality.get_half_order();
+        design_half_band(self.half_order, &mut self.coefficients);
+        resize(&mut self.history, self.coefficients.len());
+    }
+
+    fn process(&mut self, input: f32) -> (f32, f32) {
+        push(&mut self.history, input);
+
+        // Zero-stuffing halves the level, so both branches are doubled.
+        let even: f32 = self.coefficients.iter()
+                                         .zip(self.history.iter())
+                                         .map(|(coefficient, sample)| coefficient * sample)
+                                         .sum();
+
+        (2.0 * even, self.history[self.half_order])
+    }
+
+    fn reset(&mut self) {
+        for sample in self.history.iter_mut() {
+            *sample = 0.0;
+        }
+    }
+}
+
+/// HalfBandDown
+///
+/// Halves the sample rate, only calculating the output samples which are
+/// kept.
+struct HalfBandDown {
+    coefficients: Vec<f32>,
+    even_history: Vec<f32>,
+    odd_history:  Vec<f32>,
+}
+
+impl HalfBandDown {
+    fn new(quality: Quality) -> HalfBandDown {
+        let mut stage = HalfBandDown {
+            coefficients: Vec::with_capacity(2 * MAX_HALF_ORDER + 2),
+            even_history: Vec::with_capacity(2 * MAX_HALF_ORDER + 2),
+            odd_history:  Vec::with_capacity(MAX_HALF_ORDER + 2),
+        };
+
+        stage.set_quality(quality);
+        stage
+    }
+
+    fn set_quality(&mut self, quality: Quality) {
+        let half_order = quality.get_half_order();
+        design_half_band(half_order, &mut self.coefficients);
+        resize(&mut self.even_history, self.coefficients.len());
+        resize(&mut self.odd_history, half_order + 2);
+    }
+
+    fn process(&mut self, even: f32, odd: f32) -> f32 {
+        push(&mut self.even_history, even);
+        push(&mut self.odd_history, odd);
+
+        let branch: f32 = self.coefficients.iter()
+                                           .zip(self.even_history.iter())
+                                           .map(|(coefficient, sample)| coefficient * sample)
+                                           .sum();
+
+        branch + 0.5 * self.odd_history[self.odd_history.len() - 1]
+    }
+
+    fn reset(&mut self) {
+        for sample in self.even_history.iter_mut().chain(self.odd_history.iter_mut()) {
+            *sample = 0.0;
+        }
+    }
+}
+
+/// FractionalDelay
+///
+/// A first-order allpass filter which delays by part of a sample without
+/// changing the level at any frequency. The delay is exact at low
+/// frequencies, and it's best kept between a half and one and a half
+/// samples.
+struct FractionalDelay {
+    coefficient: f32,
+    input:       f32,
+    output:      f32,
+}
+
+impl FractionalDelay {
+    fn new() -> FractionalDelay {
+        FractionalDelay {
+            coefficient: 0.0,
+            input:       0.0,
+            output:      0.0,
+        }
+    }
+
+    fn set_delay(&mut self, delay: f32) {
+        // Thiran's first-order allpass
+        self.coefficient = (1.0 - delay) / (1.0 + delay);
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.coefficient * (input - self.output) + self.input;
+
+        self.input  = input;
+        self.output = output;
+        output
+    }
+
+    fn reset(&mut self) {
+        self.input  = 0.0;
+        self.output = 0.0;
+    }
+}
+
+/// Oversampler
+///
+/// Runs a function at a multiple of the sample rate, by cascading 2x
+/// half-band stages on the way up and back down again. Enough stages are
+/// allocated up front for the highest factor and quality, so that they can
+/// be changed while processing.
+pub struct Oversampler {
+    up:       Vec<HalfBandUp>,
+    down:     Vec<HalfBandDown>,
+    stages:   usize,
+    quality:  Quality,
+    fraction: Option<FractionalDelay>,
+    buffer:   [f32; MAX_FACTOR],
+    scratch:  [f32; MAX_FACTOR],
+}
+
+impl Oversampler {
+    /// Creates an oversampler for a factor of 1, 2, 4 or 8. Other factors are
+    /// rounded down to one of those.
+    pub fn new(factor: usize, quality: Quality) -> Oversampler {
+        let mut oversampler = Oversampler {
+            up:       (0..MAX_STAGES).map(|_| HalfBandUp::new(Quality::High)).collect(),
+            down:     (0..MAX_STAGES).map(|_| HalfBandDown::new(Quality::High)).collect(),
+            stages:   0,
+            quality,
+            fraction: None,
+            buffer:   [0.0; MAX_FACTOR],
+            scratch:  [0.0; MAX_FACTOR],
+        };
+
+        oversampler.configure(factor, quality);
+        oversampler
+    }
+
+    /// Changes the factor and quality, clearing the filters. This doesn't
+    /// allocate, so it's safe to call while processing.
+    pub fn configure(&mut self, factor: usize, quality: Quality) {
+        self.stages  = get_num_stages(factor);
+        self.quality = quality;
+
+        for stage in self.up.iter_mut() {
+            stage.set_quality(quality);
+        }
+        for stage in self.down.iter_mut() {
+            stage.set_quality(quality);
+        }
+
+        let filter_latency = get_filter_latency(self.stages, quality);
+        let delay          = self.get_latency() as f32 - filter_latency;
+
+        self.fraction = if delay > 0.0 {
+            let mut fraction = FractionalDelay::new();
+            fraction.set_delay(delay);
+            Some(fraction)
+        }
+        else {
+            None
+        };
+    }
+
+    pub fn get_factor(&self) -> usize {
+        1 << self.stages
+    }
+
+    pub fn get_quality(&self) -> Quality {
+        self.quality
+    }
+
+    /// Returns the delay added by the filters, in whole samples at the base
+    /// rate.
+    pub fn get_latency(&self) -> usize {
+        get_latency(self.get_factor(), self.quality)
+    }
+
+    /// Upsamples one input sample, passes each of the resulting samples
+    /// through `function`, and downsamples the results back to one sample.
+    pub fn process<F: FnMut(f32) -> f32>(&mut self, input: f32, mut function: F) -> f32 {
+        let mut length = 1;
+        self.buffer[0] = input;
+
+        for stage in self.up[..self.stages].iter_mut() {
+            self.scratch[..length].copy_from_slice(&self.buffer[..length]);
+
+            for index in 0..length {
+                let (even, odd) = stage.process(self.scratch[index]);
+                self.buffer[index * 2]     = even;
+                self.buffer[index * 2 + 1] = odd;
+            }
+
+            length *= 2;
+        }
+
+        for sample in self.buffer[..length].iter_mut() {
+            *sample = function(*sample);
+        }
+
+        for stage in self.down[..self.stages].iter_mut().rev() {
+            length /= 2;
+
+            for index in 0..length {
+                self.buffer[index] = stage.process(self.buffer[index * 2],
+                                                   self.buffer[index * 2 + 1]);
+            }
+        }
+
+        match self.fraction {
+            Some(ref mut fraction) => fraction.process(self.buffer[0]),
+            None                   => self.buffer[0],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.up.iter_mut() {
+            stage.reset();
+        }
+        for stage in self.down.iter_mut() {
+            stage.reset();
+        }
+        if let Some(ref mut fraction) = self.fraction {
+            fraction.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALL_QUALITIES: [Quality; 3] = [Quality::Low, Quality::Medium, Quality::High];
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.001
+    }
+
+    #[test]
+    fn test_factor()
+    {
+        assert_eq!(Oversampler::new(0, Quality::Low).get_factor(), 1);
+        assert_eq!(Oversampler::new(1, Quality::Low).get_factor(), 1);
+        assert_eq!(Oversampler::new(2, Quality::Low).get_factor(), 2);
+        assert_eq!(Oversampler::new(3, Quality::Low).get_factor(), 2);
+        assert_eq!(Oversampler::new(4, Quality::Low).get_factor(), 4);
+        assert_eq!(Oversampler::new(8, Quality::Low).get_factor(), 8);
+        assert_eq!(Oversampler::new(16, Quality::Low).get_factor(), 8);
+    }
+
+    #[test]
+    fn test_function_runs_at_oversampled_rate()
+    {
+        let mut oversampler = Oversampler::new(4, Quality::Low);
+        let mut calls = 0;
+
+        oversampler.process(0.0, |sample| { calls += 1; sample });
+
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn test_dc_gain()
+    {
+        for &quality in ALL_QUALITIES.iter() {
+            for &factor in [1, 2, 4, 8].iter() {
+                let mut oversampler = Oversampler::new(factor, quality);
+                let mut output = 0.0;
+
+                for _ in 0..200 {
+                    output = oversampler.process(1.0, |sample| sample);
+                }
+
+                assert!(floats_equal(output, 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_latency()
+    {
+        for &quality in ALL_QUALITIES.iter() {
+            for &factor in [2, 4, 8].iter() {
+                let mut oversampler = Oversampler::new(factor, quality);
+
+                // The peak of the impulse response should land on the
+                // reported latency, which is made up to whole samples.
+                let response: Vec<f32> = (0..200).map(|index| {
+                    oversampler.process(if index == 0 {1.0} else {0.0}, |sample| sample)
+                }).collect();
+
+                let peak = response.iter()
+                                   .enumerate()
+                                   .fold(0, |peak, (index, value)| {
+                                       if *value > response[peak] {index} else {peak}
+                                   });
+
+                assert_eq!(peak, oversampler.get_latency());
+                assert_eq!(oversampler.get_latency(), get_latency(factor, quality));
+            }
+        }
+    }
+
+    #[test]
+    fn test_configure()
+    {
+        let mut oversampler = Oversampler::new(8, Quality::High);
+        oversampler.configure(2, Quality::Low);
+
+        assert_eq!(oversampler.get_factor(), 2);
+        assert_eq!(oversampler.get_latency(), 7);
+        assert_eq!(get_latency(8, Quality::High), 55);
+        assert_eq!(get_latency(1, Quality::High), 0);
+
+        let mut fresh = Oversampler::new(2, Quality::Low);
+
+        for index in 0..50 {
+            let input = if index == 0 {1.0} else {0.0};
+
+            assert_eq!(oversampler.process(input, |sample| sample),
+                       fresh.process(input, |sample| sample));
+        }
+    }
+
+    #[test]
+    fn test_sine_passes()
+    {
+        let mut oversampler = Oversampler::new(2, Quality::High);
+        let frequency = 0.05;
+
+        let output: Vec<f32> = (0..1000).map(|index| {
+            let input = (2.0 * PI * frequency * index as f32).sin();
+            oversampler.process(input, |sample| sample)
+        }).collect();
+
+        let peak = output[500..].iter().fold(0.0f32, |peak, value| peak.max(value.abs()));
+
+        assert!(floats_equal(peak, 1.0));
+    }
+}
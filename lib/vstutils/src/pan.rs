@@ -0,0 +1,123 @@
+use param::{choice_to_param, param_to_choice};
+
+const QUARTER_PI: f32 = ::std::f32::consts::PI / 4.0;
+
+/// Law
+///
+/// How the gains of the two sides trade off as a signal moves across the
+/// stereo field.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Law {
+    Linear,
+    EqualPower,
+    Compromise,
+}
+
+pub const NUM_LAWS: usize = 3;
+
+pub fn get_law(param: f32) -> Law {
+    match param_to_choice(param, NUM_LAWS) {
+        0 => Law::Linear,
+        1 => Law::EqualPower,
+        _ => Law::Compromise,
+    }
+}
+
+pub fn get_param(law: Law) -> f32 {
+    choice_to_param(law as usize, NUM_LAWS)
+}
+
+pub fn get_name(law: Law) -> String {
+    match law {
+        Law::Linear     => "Linear" .to_string(),
+        Law::EqualPower => "-3 dB"  .to_string(),
+        Law::Compromise => "-4.5 dB".to_string(),
+    }
+}
+
+/// Returns the left and right gains for a pan position between 0 (hard left)
+/// and 1 (hard right), crossfading linearly so that the gains always add up
+/// to 1. At the centre both gains are -6 dB.
+pub fn linear(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(0.0, 1.0);
+
+    (1.0 - pan, pan)
+}
+
+/// Returns the left and right gains for a pan position between 0 (hard left)
+/// and 1 (hard right), keeping the total power constant. At the centre both
+/// gains are -3 dB.
+pub fn equal_power(pan: f32) -> (f32, f32) {
+    let theta = pan.clamp(0.0, 1.0) * 2.0 * QUARTER_PI;
+
+    (theta.cos(), theta.sin())
+}
+
+/// Returns the left and right gains for a pan position between 0 (hard left)
+/// and 1 (hard right), halfway between the linear and equal-power laws. At
+/// the centre both gains are -4.5 dB.
+pub fn compromise(pan: f32) -> (f32, f32) {
+    let (linear_left, linear_right) = linear(pan);
+    let (power_left, power_right)   = equal_power(pan);
+
+    ((linear_left * power_left).sqrt(), (linear_right * power_right).sqrt())
+}
+
+pub fn get_gains(law: Law, pan: f32) -> (f32, f32) {
+    match law {
+        Law::Linear     => linear(pan),
+        Law::EqualPower => equal_power(pan),
+        Law::Compromise => compromise(pan),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.000001
+    }
+
+    #[test]
+    fn test_equal_power()
+    {
+        for step in 0..=10 {
+            let (left, right) = equal_power(step as f32 / 10.0);
+            assert!(floats_equal(left * left + right * right, 1.0));
+        }
+
+        let (left, right) = equal_power(0.0);
+        assert!(floats_equal(left, 1.0));
+        assert!(floats_equal(right, 0.0));
+
+        let (left, right) = equal_power(0.5);
+        assert!(floats_equal(left, right));
+    }
+
+    #[test]
+    fn test_laws()
+    {
+        let centre_db = |law| {
+            let (left, right) = get_gains(law, 0.5);
+            assert!(floats_equal(left, right));
+            20.0 * left.log10()
+        };
+
+        assert!((centre_db(Law::Linear) + 6.02).abs() < 0.01);
+        assert!((centre_db(Law::EqualPower) + 3.01).abs() < 0.01);
+        assert!((centre_db(Law::Compromise) + 4.52).abs() < 0.01);
+
+        for &law in [Law::Linear, Law::EqualPower, Law::Compromise].iter() {
+            assert_eq!(get_law(get_param(law)), law);
+
+            let (left, right) = get_gains(law, 0.0);
+            assert!(floats_equal(left, 1.0));
+            assert!(floats_equal(right, 0.0));
+
+            let (left, right) = get_gains(law, 1.0);
+            assert!(floats_equal(left, 0.0));
+            assert!(floats_equal(right, 1.0));
+        }
+    }
+}
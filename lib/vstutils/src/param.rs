@@ -0,0 +1,49 @@
+// Helpers for converting between host parameter values, which are always in
+// the range 0-1, and switches or lists of choices.
+
+pub fn bool_to_param(value: bool) -> f32 {
+    if value {1.0} else {0.0}
+}
+
+pub fn param_to_bool(param: f32) -> bool {
+    param >= 0.5
+}
+
+/// Returns the index of the choice a parameter value selects, with the range
+/// 0-1 split evenly between `count` choices. With no choices at all, this is
+/// always 0.
+pub fn param_to_choice(param: f32, count: usize) -> usize {
+    let clamped_param = param.clamp(0.0, 1.0);
+
+    ((clamped_param * count as f32) as usize).min(count.saturating_sub(1))
+}
+
+/// Returns a parameter value in the middle of the range which selects a
+/// choice, so that it survives the round trip through `param_to_choice`.
+pub fn choice_to_param(choice: usize, count: usize) -> f32 {
+    (choice as f32 + 0.5) / count as f32
+}
+
+pub fn bool_to_name(value: bool) -> String {
+    if value {"On".to_string()} else {"Off".to_string()}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_choices()
+    {
+        assert_eq!(param_to_choice(0.0, 3), 0);
+        assert_eq!(param_to_choice(0.5, 3), 1);
+        assert_eq!(param_to_choice(1.0, 3), 2);
+        assert_eq!(param_to_choice(-1.0, 3), 0);
+        assert_eq!(param_to_choice(2.0, 3), 2);
+        assert_eq!(param_to_choice(0.5, 0), 0);
+
+        for choice in 0..5 {
+            assert_eq!(param_to_choice(choice_to_param(choice, 5), 5), choice);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+/// Random
+///
+/// A small xorshift generator. It's nowhere near good enough for anything
+/// that matters, but it's cheap, deterministic and never allocates, which is
+/// all that's needed for audio-rate noise and randomised phases.
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Random {
+        // xorshift gets stuck at zero, so never let the state be zero.
+        Random {
+            state: if seed == 0 {0x9e37_79b9} else {seed},
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Returns a value in the range 0-1.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Returns a value in the range -1 to 1.
+    pub fn next_bipolar(&mut self) -> f32 {
+        self.next_f32() * 2.0 - 1.0
+    }
+}
+
+impl Default for Random {
+    fn default() -> Random {
+        Random::new(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_range()
+    {
+        let mut random = Random::default();
+
+        for _ in 0..10000 {
+            let value = random.next_f32();
+            assert!((0.0..1.0).contains(&value));
+
+            let value = random.next_bipolar();
+            assert!((-1.0..1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_deterministic()
+    {
+        let mut first  = Random::new(1234);
+        let mut second = Random::new(1234);
+
+        for _ in 0..100 {
+            assert_eq!(first.next_u32(), second.next_u32());
+        }
+    }
+}
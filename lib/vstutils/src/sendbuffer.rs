@@ -0,0 +1,145 @@
+extern crate vst;
+
+use std::mem;
+
+use self::vst::api;
+use self::vst::event::MidiEvent;
+use self::vst::host::Host;
+
+fn get_api_event(event: &MidiEvent) -> api::MidiEvent {
+    api::MidiEvent {
+        event_type:        api::EventType::Midi,
+        byte_size:         mem::size_of::<api::MidiEvent>() as i32,
+        delta_frames:      event.delta_frames,
+        flags:             if event.live {api::MidiEventFlags::REALTIME_EVENT.bits()} else {0},
+        note_length:       event.note_length.unwrap_or(0),
+        note_offset:       event.note_offset.unwrap_or(0),
+        midi_data:         event.data,
+        _midi_reserved:    0,
+        detune:            event.detune,
+        note_off_velocity: event.note_off_velocity,
+        _reserved1:        0,
+        _reserved2:        0,
+    }
+}
+
+// The size of api::Events without its list of pointers, which really runs
+// on for as many events as there are.
+fn get_header_size() -> usize {
+    mem::size_of::<api::Events>() - 2 * mem::size_of::<*mut api::Event>()
+}
+
+/// SendBuffer
+///
+/// Sends MIDI events to the host, in place of vst's SendEventBuffer, which
+/// fills its events with zeroes and so can't be created on current
+/// compilers. Space for the events is set aside up front, so sending them
+/// doesn't allocate.
+pub struct SendBuffer {
+    events: Vec<api::MidiEvent>,
+    // An api::Events, followed by room for a pointer to every event. It's
+    // kept as words so that the pointers are aligned.
+    header: Vec<usize>,
+}
+
+impl SendBuffer {
+    pub fn new(capacity: usize) -> SendBuffer {
+        let empty     = MidiEvent {
+            data:              [0; 3],
+            delta_frames:      0,
+            live:              false,
+            note_length:       None,
+            note_offset:       None,
+            detune:            0,
+            note_off_velocity: 0,
+        };
+        let word_size = mem::size_of::<usize>();
+        let size      = get_header_size() + capacity.max(2) * mem::size_of::<*mut api::Event>();
+
+        SendBuffer {
+            events: (0..capacity).map(|_| get_api_event(&empty)).collect(),
+            header: vec![0; size.div_ceil(word_size)],
+        }
+    }
+
+    /// Sends as many of the events as there's room for.
+    pub fn send<H: Host>(&mut self, events: &[MidiEvent], host: &mut H) {
+        let count = events.len().min(self.events.len());
+
+        for (api_event, event) in self.events.iter_mut().zip(events) {
+            *api_event = get_api_event(event);
+        }
+
+        let header = self.header.as_mut_ptr() as *mut api::Events;
+
+        #[allow(clippy::cast_ptr_alignment)]
+        unsafe {
+            let pointers = (header as *mut u8).add(get_header_size()) as *mut *mut api::Event;
+
+            for (index, api_event) in self.events[..count].iter_mut().enumerate() {
+                *pointers.add(index) = api_event as *mut api::MidiEvent as *mut api::Event;
+            }
+
+            (*header).num_events = count as i32;
+
+            host.process_events(&*header);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use self::vst::event::Event;
+
+    #[derive(Default)]
+    struct TestHost {
+        received: Vec<([u8; 3], i32)>,
+    }
+
+    impl Host for TestHost {
+        fn process_events(&mut self, events: &api::Events) {
+            for event in events.events() {
+                if let Event::Midi(event) = event {
+                    self.received.push((event.data, event.delta_frames));
+                }
+            }
+        }
+    }
+
+    fn get_event(note: u8, delta_frames: i32) -> MidiEvent {
+        MidiEvent {
+            data:              [0x90, note, 100],
+            delta_frames,
+            live:              false,
+            note_length:       None,
+            note_offset:       None,
+            detune:            0,
+            note_off_velocity: 0,
+        }
+    }
+
+    #[test]
+    fn test_send()
+    {
+        let mut buffer = SendBuffer::new(3);
+        let mut host   = TestHost::default();
+
+        let events: Vec<MidiEvent> = (0..4).map(|index| get_event(60 + index, index as i32))
+                                           .collect();
+        buffer.send(&events, &mut host);
+
+        // Only as many as there's room for are sent.
+        assert_eq!(host.received, [([0x90, 60, 100], 0),
+                                   ([0x90, 61, 100], 1),
+                                   ([0x90, 62, 100], 2)]);
+
+        host.received.clear();
+        buffer.send(&events[3..], &mut host);
+        assert_eq!(host.received, [([0x90, 63, 100], 3)]);
+
+        host.received.clear();
+        buffer.send(&[], &mut host);
+        assert!(host.received.is_empty());
+    }
+}
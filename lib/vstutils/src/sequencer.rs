@@ -0,0 +1,226 @@
+use division;
+use division::Division;
+use param::{choice_to_param, param_to_choice};
+use transport::Transport;
+
+pub const MAX_STEPS: usize = 32;
+
+// The pattern lengths which can be chosen from a parameter.
+const LENGTHS: [usize; 2] = [16, 32];
+
+pub fn get_length(param: f32) -> usize {
+    LENGTHS[param_to_choice(param, LENGTHS.len())]
+}
+
+pub fn get_length_param(length: usize) -> f32 {
+    let choice = LENGTHS.iter().position(|&option| option == length).unwrap_or(0);
+    choice_to_param(choice, LENGTHS.len())
+}
+
+/// Sequencer
+///
+/// A step sequencer for modulation, which plays a loop of values in the
+/// range 0-1 at a note division. While the host is playing, the position is
+/// taken from the host's song position at the start of every block, so the
+/// loop stays in step with the song however the host jumps around. When the
+/// host is stopped it runs at the host's tempo from wherever it was last
+/// reset.
+///
+/// Glide is the fraction of each step spent sliding from the previous
+/// step's value.
+pub struct Sequencer {
+    steps:          [f32; MAX_STEPS],
+    length:         usize,
+    glide:          f32,
+    division:       Division,
+    step_length:    f64,
+    ppq_per_sample: f64,
+    position:       f64,
+}
+
+impl Default for Sequencer {
+    fn default() -> Sequencer {
+        Sequencer {
+            steps:          [0.5; MAX_STEPS],
+            length:         LENGTHS[0],
+            glide:          0.0,
+            division:       Division::Sixteenth,
+            step_length:    0.25,
+            ppq_per_sample: 0.0,
+            position:       0.0,
+        }
+    }
+}
+
+impl Sequencer {
+    pub fn new() -> Sequencer {
+        Sequencer::default()
+    }
+
+    pub fn get_step_value(&self, step: usize) -> f32 {
+        self.steps.get(step).copied().unwrap_or(0.0)
+    }
+
+    pub fn set_step_value(&mut self, step: usize, value: f32) {
+        if let Some(existing) = self.steps.get_mut(step) {
+            *existing = value.clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length.clamp(1, MAX_STEPS);
+    }
+
+    pub fn get_glide(&self) -> f32 {
+        self.glide
+    }
+
+    pub fn set_glide(&mut self, glide: f32) {
+        self.glide = glide.clamp(0.0, 1.0);
+    }
+
+    pub fn get_division(&self) -> Division {
+        self.division
+    }
+
+    pub fn set_division(&mut self, division: Division) {
+        self.division = division;
+    }
+
+    /// Picks up the tempo, time signature and song position from the host.
+    /// Call this at the start of every block.
+    pub fn sync(&mut self, transport: &Transport, sample_rate: f32) {
+        self.step_length    = f64::from(division::get_length(self.division, transport.time_sig));
+        self.ppq_per_sample = transport.get_ppq_per_sample(sample_rate);
+
+        if let Some(ppq_pos) = transport.ppq_pos.filter(|_| transport.playing) {
+            self.position = ppq_pos;
+        }
+    }
+
+    /// Goes back to the start of the first step.
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+    }
+
+    // The position in steps from the start of the song, which may be
+    // negative during a pre-roll.
+    fn get_step_position(&self) -> f64 {
+        self.position / self.step_length
+    }
+
+    fn wrap(&self, step: i64) -> usize {
+        step.rem_euclid(self.length as i64) as usize
+    }
+
+    pub fn get_step(&self) -> usize {
+        self.wrap(self.get_step_position().floor() as i64)
+    }
+
+    pub fn get_value(&self) -> f32 {
+        let position = self.get_step_position();
+        let index    = position.floor() as i64;
+        let fraction = (position - position.floor()) as f32;
+        let value    = self.steps[self.wrap(index)];
+
+        if fraction < self.glide {
+            let previous = self.steps[self.wrap(index - 1)];
+            previous + (value - previous) * fraction / self.glide
+        }
+        else {
+            value
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.position += self.ppq_per_sample;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    fn get_sequencer() -> Sequencer {
+        let mut sequencer = Sequencer::new();
+
+        for step in 0..MAX_STEPS {
+            sequencer.set_step_value(step, step as f32 / MAX_STEPS as f32);
+        }
+
+        sequencer
+    }
+
+    fn get_transport(ppq_pos: f64) -> Transport {
+        Transport {
+            ppq_pos: Some(ppq_pos),
+            playing: true,
+            ..Transport::default()
+        }
+    }
+
+    #[test]
+    fn test_lengths()
+    {
+        assert_eq!(get_length(0.0), 16);
+        assert_eq!(get_length(1.0), 32);
+        assert_eq!(get_length(get_length_param(32)), 32);
+    }
+
+    #[test]
+    fn test_song_position()
+    {
+        let mut sequencer = get_sequencer();
+
+        // Sixteenth notes, so step 5 starts at the second beat of the
+        // second bar, and the sixteen steps loop every bar.
+        sequencer.sync(&get_transport(5.25), 44100.0);
+        assert_eq!(sequencer.get_step(), 5);
+        assert!(floats_equal(sequencer.get_value(), 5.0 / 32.0));
+
+        sequencer.set_length(32);
+        sequencer.sync(&get_transport(5.25), 44100.0);
+        assert_eq!(sequencer.get_step(), 21);
+
+        // Pre-rolls before the start of the song still land on the loop.
+        sequencer.sync(&get_transport(-0.25), 44100.0);
+        assert_eq!(sequencer.get_step(), 31);
+
+        // Once stopped, the position carries on from where it was.
+        let stopped = Transport {
+            playing: false,
+            ..get_transport(100.0)
+        };
+        sequencer.sync(&stopped, 44100.0);
+        assert_eq!(sequencer.get_step(), 31);
+
+        // An eighth of a second is a sixteenth note at 120 BPM.
+        for _ in 0..(44100 / 8 + 1) {
+            sequencer.advance();
+        }
+        assert_eq!(sequencer.get_step(), 0);
+    }
+
+    #[test]
+    fn test_glide()
+    {
+        let mut sequencer = get_sequencer();
+        sequencer.set_glide(0.5);
+
+        // A quarter of the way into step 2 is halfway through the glide
+        // from step 1.
+        sequencer.sync(&get_transport(0.5625), 44100.0);
+        assert!(floats_equal(sequencer.get_value(), 1.5 / 32.0));
+
+        sequencer.sync(&get_transport(0.625), 44100.0);
+        assert!(floats_equal(sequencer.get_value(), 2.0 / 32.0));
+    }
+}
@@ -3,7 +3,6 @@ extern crate num_traits;
 use self::num_traits::Num;
 
 /// TargetVal
-
 pub enum Rate<T> {
     Absolute(T),
     Relative(T),
@@ -19,10 +18,10 @@ pub struct TargetVal<T: Copy+Num+PartialOrd> {
 impl<T: Copy+Num+PartialOrd> TargetVal<T> {
     pub fn new(inc_rate: Rate<T>, dec_rate: Rate<T>, value: T) -> TargetVal<T> {
         TargetVal {
-            inc_rate: inc_rate,
-            dec_rate: dec_rate,
+            inc_rate,
+            dec_rate,
             target:   value,
-            value:    value,
+            value,
         }
     }
 
@@ -0,0 +1,137 @@
+extern crate vst;
+
+use self::vst::api::{TimeInfo, TimeInfoFlags};
+use self::vst::host::Host;
+
+use division;
+use division::{Division, TimeSignature};
+
+const DEFAULT_TEMPO: f32 = 120.0;
+
+/// Transport
+///
+/// The host's tempo, time signature and song position at the start of the
+/// current block. Anything the host can't supply falls back to 120 BPM in
+/// 4/4, stopped and with no position.
+#[derive(Clone, Copy)]
+pub struct Transport {
+    pub tempo:    f32,
+    pub time_sig: TimeSignature,
+    /// The song position, in quarter notes.
+    pub ppq_pos:  Option<f64>,
+    pub playing:  bool,
+}
+
+impl Transport {
+    pub fn from_host<H: Host>(host: &H) -> Transport {
+        let flags = TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::TIME_SIG_VALID
+                  | TimeInfoFlags::PPQ_POS_VALID;
+
+        match host.get_time_info(flags.bits()) {
+            None            => Transport::default(),
+            Some(time_info) => Transport::from_time_info(&time_info),
+        }
+    }
+
+    pub fn from_time_info(time_info: &TimeInfo) -> Transport {
+        let flags = TimeInfoFlags::from_bits_truncate(time_info.flags);
+
+        Transport {
+            tempo:    if flags.contains(TimeInfoFlags::TEMPO_VALID) && time_info.tempo > 0.0 {
+                time_info.tempo as f32
+            }
+            else {
+                DEFAULT_TEMPO
+            },
+            time_sig: if flags.contains(TimeInfoFlags::TIME_SIG_VALID) {
+                TimeSignature::new(time_info.time_sig_numerator,
+                                   time_info.time_sig_denominator)
+            }
+            else {
+                TimeSignature::default()
+            },
+            ppq_pos:  if flags.contains(TimeInfoFlags::PPQ_POS_VALID) {
+                Some(time_info.ppq_pos)
+            }
+            else {
+                None
+            },
+            playing:  flags.contains(TimeInfoFlags::TRANSPORT_PLAYING),
+        }
+    }
+
+    /// Returns the frequency of a division at the current tempo, in Hz.
+    pub fn get_frequency(&self, division: Division) -> f32 {
+        self.tempo * division::get_tempo_multiplier(division, self.time_sig)
+    }
+
+    /// Returns the length of a division at the current tempo, in seconds.
+    pub fn get_seconds(&self, division: Division) -> f32 {
+        1.0 / self.get_frequency(division)
+    }
+
+    /// Returns how far the song position moves in one sample, in quarter
+    /// notes.
+    pub fn get_ppq_per_sample(&self, sample_rate: f32) -> f64 {
+        f64::from(self.tempo) / 60.0 / f64::from(sample_rate)
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Transport {
+        Transport {
+            tempo:    DEFAULT_TEMPO,
+            time_sig: TimeSignature::default(),
+            ppq_pos:  None,
+            playing:  false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.000001
+    }
+
+    #[test]
+    fn test_from_time_info()
+    {
+        let mut time_info = TimeInfo {
+            tempo:                90.0,
+            time_sig_numerator:   6,
+            time_sig_denominator: 8,
+            ppq_pos:              12.5,
+            ..TimeInfo::default()
+        };
+
+        // None of the values are valid until the host says so.
+        let transport = Transport::from_time_info(&time_info);
+        assert!(floats_equal(transport.tempo, DEFAULT_TEMPO));
+        assert_eq!(transport.time_sig.numerator, 4);
+        assert_eq!(transport.ppq_pos, None);
+        assert!(!transport.playing);
+
+        time_info.flags = (TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::TIME_SIG_VALID
+                           | TimeInfoFlags::PPQ_POS_VALID | TimeInfoFlags::TRANSPORT_PLAYING)
+                          .bits();
+        let transport = Transport::from_time_info(&time_info);
+        assert!(floats_equal(transport.tempo, 90.0));
+        assert_eq!(transport.time_sig.numerator, 6);
+        assert_eq!(transport.time_sig.denominator, 8);
+        assert_eq!(transport.ppq_pos, Some(12.5));
+        assert!(transport.playing);
+    }
+
+    #[test]
+    fn test_division_timing()
+    {
+        let transport = Transport::default();
+
+        assert!(floats_equal(transport.get_seconds(Division::Quarter), 0.5));
+        assert!(floats_equal(transport.get_frequency(Division::Eighth), 4.0));
+        assert!(floats_equal(transport.get_seconds(Division::Whole), 2.0));
+    }
+}
@@ -0,0 +1,729 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chunk::Chunk;
+use maths::PitchConverter;
+use mts::Message;
+use param::{choice_to_param, param_to_choice};
+
+pub const NUM_NOTES: usize = 128;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    MissingLine(&'static str),
+    InvalidLine(String),
+    UnmappedReference,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error)         => write!(formatter, "{}", error),
+            Error::MissingLine(name) => write!(formatter, "missing {}", name),
+            Error::InvalidLine(line) => write!(formatter, "invalid line \"{}\"", line),
+            Error::UnmappedReference => write!(formatter, "the reference note isn't mapped"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+// Returns the lines of a Scala file, without the comments.
+fn get_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.starts_with('!'))
+}
+
+fn parse_value<T: ::std::str::FromStr>(line: Option<&str>, name: &'static str)
+                                       -> Result<T, Error> {
+    let line = line.ok_or(Error::MissingLine(name))?;
+
+    line.split_whitespace()
+        .next()
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| Error::InvalidLine(line.to_string()))
+}
+
+// A pitch is either in cents, which always have a decimal point, or is a
+// ratio such as 3/2 or 2. Anything after the value is a comment.
+fn parse_pitch(line: &str) -> Result<f64, Error> {
+    let invalid = || Error::InvalidLine(line.to_string());
+    let token   = line.split_whitespace().next().ok_or_else(invalid)?;
+
+    let ratio = if token.contains('.') {
+        let cents: f64 = token.parse().map_err(|_| invalid())?;
+        2f64.powf(cents / 1200.0)
+    }
+    else {
+        let mut parts = token.splitn(2, '/');
+        let numerator: u64 = parts.next()
+                                  .and_then(|part| part.parse().ok())
+                                  .ok_or_else(invalid)?;
+        let denominator: u64 = match parts.next() {
+            Some(part) => part.parse().map_err(|_| invalid())?,
+            None       => 1,
+        };
+
+        numerator as f64 / denominator as f64
+    };
+
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    }
+    else {
+        Err(invalid())
+    }
+}
+
+/// Scale
+///
+/// The pitches of a scale, as read from a Scala .scl file. Each pitch is a
+/// ratio above the first degree of the scale, which is implicitly 1/1, and
+/// the last pitch is the interval at which the scale repeats.
+#[derive(Clone, Debug)]
+pub struct Scale {
+    description: String,
+    ratios:      Vec<f64>,
+}
+
+impl Scale {
+    pub fn parse(text: &str) -> Result<Scale, Error> {
+        let mut lines   = get_lines(text);
+        let description = lines.next().ok_or(Error::MissingLine("description"))?;
+        let count_line  = lines.next();
+        let count: usize = parse_value(count_line, "note count")?;
+
+        if count == 0 {
+            return Err(Error::InvalidLine(count_line.unwrap_or("").to_string()));
+        }
+
+        let ratios = (0..count).map(|_| {
+                                   lines.next()
+                                        .ok_or(Error::MissingLine("pitch"))
+                                        .and_then(parse_pitch)
+                               })
+                               .collect::<Result<Vec<f64>, Error>>()?;
+
+        Ok(Scale {
+            description: description.trim().to_string(),
+            ratios,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scale, Error> {
+        Scale::parse(&fs::read_to_string(path)?)
+    }
+
+    /// A scale which divides an octave into `divisions` equal steps.
+    pub fn equal_temperament(divisions: usize) -> Scale {
+        Scale {
+            description: format!("{}-tone equal temperament", divisions),
+            ratios:      (1..=divisions).map(|step| 2f64.powf(step as f64 / divisions as f64))
+                                        .collect(),
+        }
+    }
+
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the number of degrees before the scale repeats.
+    pub fn get_length(&self) -> usize {
+        self.ratios.len()
+    }
+
+    /// Returns the ratio of a degree above the first degree. Degrees outside
+    /// the scale, including negative ones, are transposed by the period.
+    pub fn get_ratio(&self, degree: i32) -> f64 {
+        let length = self.ratios.len() as i32;
+        let period = self.ratios[self.ratios.len() - 1];
+        let index  = degree.rem_euclid(length);
+        let base   = if index == 0 {1.0} else {self.ratios[index as usize - 1]};
+
+        base * period.powi(degree.div_euclid(length))
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale::equal_temperament(12)
+    }
+}
+
+/// KeyboardMapping
+///
+/// How MIDI notes are mapped onto the degrees of a scale, and which note is
+/// tuned to a reference frequency, as read from a Scala .kbm file.
+#[derive(Clone, Debug)]
+pub struct KeyboardMapping {
+    first_note:          i32,
+    last_note:           i32,
+    middle_note:         i32,
+    reference_note:      i32,
+    reference_frequency: f64,
+    octave_degree:       i32,
+    // Scale degrees for each key of the repeating pattern, starting at the
+    // middle note. An empty mapping maps keys to consecutive degrees.
+    keys:                Vec<Option<i32>>,
+}
+
+impl KeyboardMapping {
+    pub fn parse(text: &str) -> Result<KeyboardMapping, Error> {
+        let mut lines = get_lines(text).filter(|line| !line.trim().is_empty());
+
+        let size: usize = parse_value(lines.next(), "map size")?;
+        let mut mapping = KeyboardMapping {
+            first_note:          parse_value(lines.next(), "first note")?,
+            last_note:           parse_value(lines.next(), "last note")?,
+            middle_note:         parse_value(lines.next(), "middle note")?,
+            reference_note:      parse_value(lines.next(), "reference note")?,
+            reference_frequency: parse_value(lines.next(), "reference frequency")?,
+            octave_degree:       parse_value(lines.next(), "octave degree")?,
+            keys:                Vec::with_capacity(size),
+        };
+
+        // Keys left off the end of the mapping are unmapped.
+        for line in lines.take(size) {
+            mapping.keys.push(match line.split_whitespace().next() {
+                Some("x") => None,
+                _         => Some(parse_value(Some(line), "key")?),
+            });
+        }
+        mapping.keys.resize(size, None);
+
+        if mapping.get_degree(mapping.reference_note, 1).is_none() {
+            return Err(Error::UnmappedReference);
+        }
+
+        Ok(mapping)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<KeyboardMapping, Error> {
+        KeyboardMapping::parse(&fs::read_to_string(path)?)
+    }
+
+    /// A mapping of consecutive notes to consecutive degrees, starting from
+    /// middle C, with one note tuned to a reference frequency.
+    pub fn linear(reference_note: u8, reference_frequency: f32) -> KeyboardMapping {
+        KeyboardMapping {
+            first_note:          0,
+            last_note:           NUM_NOTES as i32 - 1,
+            middle_note:         60,
+            reference_note:      reference_note as i32,
+            reference_frequency: reference_frequency as f64,
+            octave_degree:       0,
+            keys:                Vec::new(),
+        }
+    }
+
+    // Returns the scale degree a note plays, ignoring the range of notes
+    // which are retuned.
+    fn get_degree(&self, note: i32, scale_length: usize) -> Option<i32> {
+        let offset = note - self.middle_note;
+
+        if self.keys.is_empty() {
+            return Some(offset);
+        }
+
+        let size          = self.keys.len() as i32;
+        let octave_degree = if self.octave_degree > 0 {self.octave_degree}
+                            else                      {scale_length as i32};
+
+        self.keys[offset.rem_euclid(size) as usize]
+            .map(|degree| degree + offset.div_euclid(size) * octave_degree)
+    }
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> KeyboardMapping {
+        KeyboardMapping::linear(69, 440.0)
+    }
+}
+
+/// Tuning
+///
+/// A scale and a keyboard mapping, with the frequency of every MIDI note
+/// worked out in advance. Notes which the mapping leaves out have no
+/// frequency, and shouldn't sound.
+#[derive(Clone, Debug)]
+pub struct Tuning {
+    scale:             Scale,
+    mapping:           KeyboardMapping,
+    frequencies:       Vec<Option<f32>>,
+    // The frequencies before any MIDI Tuning Standard messages.
+    scale_frequencies: Vec<Option<f32>>,
+}
+
+impl Tuning {
+    pub fn new(scale: Scale, mapping: KeyboardMapping) -> Tuning {
+        let length    = scale.get_length();
+        let reference = mapping.get_degree(mapping.reference_note, length).unwrap_or(0);
+        let base      = mapping.reference_frequency / scale.get_ratio(reference);
+
+        let frequencies: Vec<Option<f32>> = (0..NUM_NOTES as i32).map(|note| {
+            if note < mapping.first_note || note > mapping.last_note {
+                None
+            }
+            else {
+                mapping.get_degree(note, length)
+                       .map(|degree| (base * scale.get_ratio(degree)) as f32)
+            }
+        }).collect();
+
+        Tuning {
+            scale,
+            mapping,
+            scale_frequencies: frequencies.clone(),
+            frequencies,
+        }
+    }
+
+    pub fn get_scale(&self) -> &Scale {
+        &self.scale
+    }
+
+    pub fn get_mapping(&self) -> &KeyboardMapping {
+        &self.mapping
+    }
+
+    pub fn get_frequency(&self, note: u8) -> Option<f32> {
+        self.frequencies.get(note as usize).and_then(|frequency| *frequency)
+    }
+
+    /// Applies a MIDI Tuning Standard message on top of the tuning. Single
+    /// note changes retune just those notes, while a scale/octave message
+    /// retunes every note relative to equal temperament.
+    pub fn retune(&mut self, message: Message) {
+        let converter = PitchConverter::default();
+
+        match message {
+            Message::SingleNote(changes) => {
+                for (note, pitch) in changes {
+                    if let Some(frequency) = self.frequencies.get_mut(note as usize) {
+                        *frequency = Some(converter.get_frequency(pitch));
+                    }
+                }
+            },
+            Message::ScaleOctave(cents) => {
+                for (note, frequency) in self.frequencies.iter_mut().enumerate() {
+                    let offset = cents[note % cents.len()] / 100.0;
+                    *frequency = Some(converter.get_frequency(note as f32 + offset));
+                }
+            },
+        }
+    }
+
+    /// Undoes any retuning, going back to the frequencies of the scale and
+    /// mapping.
+    pub fn reset(&mut self) {
+        self.frequencies.copy_from_slice(&self.scale_frequencies);
+    }
+
+    /// Returns the frequency of a pitch which may fall between two notes,
+    /// bending smoothly from one note's frequency to the next. Both notes
+    /// need to be mapped.
+    pub fn get_pitch_frequency(&self, pitch: f32) -> Option<f32> {
+        let note = pitch.floor();
+
+        if note < 0.0 || note >= NUM_NOTES as f32 {
+            return None;
+        }
+
+        let lower    = self.get_frequency(note as u8)?;
+        let fraction = pitch - note;
+
+        if fraction == 0.0 {
+            return Some(lower);
+        }
+
+        let upper = self.frequencies.get(note as usize + 1).and_then(|frequency| *frequency)?;
+
+        Some(lower * (upper / lower).powf(fraction))
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning::new(Scale::default(), KeyboardMapping::default())
+    }
+}
+
+const JUST_INTONATION: &str = "\
+! just.scl
+5-limit just intonation
+12
+16/15
+9/8
+6/5
+5/4
+4/3
+45/32
+3/2
+8/5
+5/3
+9/5
+15/8
+2/1
+";
+
+const PYTHAGOREAN: &str = "\
+! pythagorean.scl
+Pythagorean tuning
+12
+256/243
+9/8
+32/27
+81/64
+4/3
+729/512
+3/2
+128/81
+27/16
+16/9
+243/128
+2/1
+";
+
+const MEANTONE: &str = "\
+! meantone.scl
+Quarter-comma meantone
+12
+76.049
+193.157
+310.265
+386.314
+503.422
+579.471
+696.578
+772.627
+889.735
+1006.843
+1082.892
+2/1
+";
+
+pub const NUM_PRESETS: usize = 7;
+
+pub fn get_preset_name(preset: usize) -> String {
+    match preset {
+        0 => "12-TET"     .to_string(),
+        1 => "Just"       .to_string(),
+        2 => "Pythagorean".to_string(),
+        3 => "Meantone"   .to_string(),
+        4 => "19-TET"     .to_string(),
+        5 => "24-TET"     .to_string(),
+        _ => "31-TET"     .to_string(),
+    }
+}
+
+/// Returns one of the tunings built into the plugins. They're all tuned
+/// with A4 at 440 Hz.
+pub fn get_preset(preset: usize) -> Tuning {
+    let scale = match preset {
+        0 => Scale::equal_temperament(12),
+        1 => Scale::parse(JUST_INTONATION).unwrap(),
+        2 => Scale::parse(PYTHAGOREAN).unwrap(),
+        3 => Scale::parse(MEANTONE).unwrap(),
+        4 => Scale::equal_temperament(19),
+        5 => Scale::equal_temperament(24),
+        _ => Scale::equal_temperament(31),
+    };
+
+    Tuning::new(scale, KeyboardMapping::default())
+}
+
+// The choice after the presets is the tuning loaded from files.
+const NUM_CHOICES: usize = NUM_PRESETS + 1;
+const FILE_CHOICE: usize = NUM_PRESETS;
+
+const SCALE_KEY: &str = "tuning.scl";
+const MAPPING_KEY: &str = "tuning.kbm";
+
+/// Selector
+///
+/// Chooses an instrument's tuning from the presets, or from a Scala scale
+/// and keyboard mapping on disk. The presets can be chosen with a
+/// parameter, while files are named in the plugin's chunk and are loaded
+/// along with it. The presets are all built up front, so that switching
+/// between them doesn't allocate.
+pub struct Selector {
+    choice:       usize,
+    scale_path:   Option<String>,
+    mapping_path: Option<String>,
+    presets:      Vec<Tuning>,
+    file_tuning:  Option<Tuning>,
+}
+
+impl Selector {
+    pub fn new() -> Selector {
+        Selector {
+            choice:       0,
+            scale_path:   None,
+            mapping_path: None,
+            presets:      (0..NUM_PRESETS).map(get_preset).collect(),
+            file_tuning:  None,
+        }
+    }
+
+    pub fn get_tuning(&self) -> &Tuning {
+        match self.file_tuning {
+            Some(ref tuning) if self.choice == FILE_CHOICE => tuning,
+            _                                              => &self.presets[self.choice],
+        }
+    }
+
+    fn get_tuning_mut(&mut self) -> &mut Tuning {
+        match self.file_tuning {
+            Some(ref mut tuning) if self.choice == FILE_CHOICE => tuning,
+            _                                                  => &mut self.presets[self.choice],
+        }
+    }
+
+    pub fn get_param(&self) -> f32 {
+        choice_to_param(self.choice, NUM_CHOICES)
+    }
+
+    /// Chooses a preset, or the tuning from files if one has been loaded.
+    pub fn set_param(&mut self, value: f32) {
+        let choice = param_to_choice(value, NUM_CHOICES);
+
+        if choice == self.choice {
+            return;
+        }
+
+        if choice == FILE_CHOICE && self.file_tuning.is_none() {
+            return;
+        }
+
+        self.choice = choice;
+        self.get_tuning_mut().reset();
+    }
+
+    /// Applies a MIDI Tuning Standard message to the tuning in use, until
+    /// another tuning is chosen.
+    pub fn retune(&mut self, message: Message) {
+        self.get_tuning_mut().retune(message);
+    }
+
+    pub fn get_name(&self) -> String {
+        if self.choice == FILE_CHOICE {
+            self.scale_path
+                .as_ref()
+                .and_then(|path| Path::new(path).file_stem())
+                .map_or("File".to_string(), |stem| stem.to_string_lossy().into_owned())
+        }
+        else {
+            get_preset_name(self.choice)
+        }
+    }
+
+    /// Loads a scale, and optionally a keyboard mapping, and switches to
+    /// them. Without a mapping, the scale starts at middle C with A4 at
+    /// 440 Hz.
+    pub fn load_files(&mut self, scale_path: &str, mapping_path: Option<&str>)
+                      -> Result<(), Error> {
+        let scale   = Scale::load(scale_path)?;
+        let mapping = match mapping_path {
+            Some(path) => KeyboardMapping::load(path)?,
+            None       => KeyboardMapping::default(),
+        };
+
+        self.scale_path   = Some(scale_path.to_string());
+        self.mapping_path = mapping_path.map(|path| path.to_string());
+        self.file_tuning  = Some(Tuning::new(scale, mapping));
+        self.choice       = FILE_CHOICE;
+
+        Ok(())
+    }
+
+    pub fn save(&self, chunk: &mut Chunk) {
+        if let Some(ref path) = self.scale_path {
+            chunk.set(SCALE_KEY, path);
+        }
+        if let Some(ref path) = self.mapping_path {
+            chunk.set(MAPPING_KEY, path);
+        }
+    }
+
+    /// Loads the files named in a chunk. If they can't be loaded, the
+    /// current tuning is kept.
+    pub fn load(&mut self, chunk: &Chunk) {
+        if let Some(scale_path) = chunk.get(SCALE_KEY) {
+            let _ = self.load_files(scale_path, chunk.get(MAPPING_KEY));
+        }
+    }
+}
+
+impl Default for Selector {
+    fn default() -> Selector {
+        Selector::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use maths::midi_pitch_to_freq;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() / second < 0.00001
+    }
+
+    #[test]
+    fn test_parse_scale()
+    {
+        let scale = Scale::parse(JUST_INTONATION).unwrap();
+        assert_eq!(scale.get_description(), "5-limit just intonation");
+        assert_eq!(scale.get_length(), 12);
+        assert!(floats_equal(scale.get_ratio(0) as f32, 1.0));
+        assert!(floats_equal(scale.get_ratio(7) as f32, 1.5));
+        assert!(floats_equal(scale.get_ratio(12) as f32, 2.0));
+        assert!(floats_equal(scale.get_ratio(19) as f32, 3.0));
+        assert!(floats_equal(scale.get_ratio(-5) as f32, 0.75));
+
+        let scale = Scale::parse("! cents.scl\n!\n\n 2\n 700.0 fifth\n 2\n").unwrap();
+        assert_eq!(scale.get_description(), "");
+        assert!(floats_equal(scale.get_ratio(1) as f32, 2f32.powf(700.0 / 1200.0)));
+        assert!(floats_equal(scale.get_ratio(2) as f32, 2.0));
+    }
+
+    #[test]
+    fn test_invalid_scales()
+    {
+        assert!(Scale::parse("").is_err());
+        assert!(Scale::parse("Too short\n3\n9/8\n5/4\n").is_err());
+        assert!(Scale::parse("Bad pitch\n1\nfoo\n").is_err());
+        assert!(Scale::parse("Zero\n1\n0/1\n").is_err());
+        assert!(Scale::parse("Empty\n0\n").is_err());
+    }
+
+    #[test]
+    fn test_default_is_twelve_tet()
+    {
+        let tuning = Tuning::default();
+
+        for note in 0..NUM_NOTES as u8 {
+            assert!(floats_equal(tuning.get_frequency(note).unwrap(), midi_pitch_to_freq(note)));
+        }
+    }
+
+    #[test]
+    fn test_keyboard_mapping()
+    {
+        // A pentatonic scale on the white keys from C, with the black keys
+        // unmapped and C4 tuned to 261.63 Hz.
+        let mapping = KeyboardMapping::parse("\
+! pentatonic.kbm
+12
+48
+72
+60
+60
+261.63
+5
+0
+x
+1
+x
+2
+x
+x
+3
+x
+4
+x
+x
+").unwrap();
+        let scale  = Scale::parse("Pentatonic\n5\n9/8\n5/4\n3/2\n5/3\n2/1\n").unwrap();
+        let tuning = Tuning::new(scale, mapping);
+
+        assert!(floats_equal(tuning.get_frequency(60).unwrap(), 261.63));
+        assert_eq!(tuning.get_frequency(61), None);
+        assert!(floats_equal(tuning.get_frequency(62).unwrap(), 261.63 * 9.0 / 8.0));
+        assert!(floats_equal(tuning.get_frequency(67).unwrap(), 261.63 * 1.5));
+        assert!(floats_equal(tuning.get_frequency(72).unwrap(), 261.63 * 2.0));
+        assert!(floats_equal(tuning.get_frequency(57).unwrap(), 261.63 * 5.0 / 6.0));
+
+        // Notes outside the range aren't mapped.
+        assert_eq!(tuning.get_frequency(47), None);
+        assert_eq!(tuning.get_frequency(74), None);
+    }
+
+    #[test]
+    fn test_pitch_frequency()
+    {
+        let tuning = get_preset(1);
+
+        assert!(floats_equal(tuning.get_pitch_frequency(60.0).unwrap(),
+                             tuning.get_frequency(60).unwrap()));
+        assert!(floats_equal(tuning.get_pitch_frequency(60.5).unwrap(),
+                             (tuning.get_frequency(60).unwrap()
+                              * tuning.get_frequency(61).unwrap()).sqrt()));
+        assert!(tuning.get_pitch_frequency(-0.5).is_none());
+        assert!(tuning.get_pitch_frequency(127.5).is_none());
+    }
+
+    #[test]
+    fn test_retune()
+    {
+        let mut tuning = Tuning::default();
+
+        let payload = [0x7f, 0x00, 0x08, 0x02, 0x00, 0x01, 0x45, 0x45, 0x40, 0x00];
+        tuning.retune(::mts::parse(&payload).unwrap());
+        assert!(floats_equal(tuning.get_frequency(69).unwrap(), 440.0 * (1.0 / 24.0f32).exp2()));
+        assert!(floats_equal(tuning.get_frequency(70).unwrap(), midi_pitch_to_freq(70)));
+
+        let mut payload = vec![0x7f, 0x00, 0x08, 0x08, 0x03, 0x7f, 0x7f];
+        payload.extend_from_slice(&[0x40, 0x40, 0x40, 0x40, 0x40, 0x40,
+                                    0x40, 0x40, 0x40, 0x72, 0x40, 0x40]);
+        tuning.retune(::mts::parse(&payload).unwrap());
+        assert!(floats_equal(tuning.get_frequency(69).unwrap(), 440.0 * (0.5 / 12.0f32).exp2()));
+        assert!(floats_equal(tuning.get_frequency(57).unwrap(), 220.0 * (0.5 / 12.0f32).exp2()));
+        assert!(floats_equal(tuning.get_frequency(60).unwrap(), midi_pitch_to_freq(60)));
+    }
+
+    #[test]
+    fn test_unmapped_reference()
+    {
+        assert!(KeyboardMapping::parse("2\n0\n127\n60\n61\n440.0\n1\n0\nx\n").is_err());
+    }
+
+    #[test]
+    fn test_presets()
+    {
+        for preset in 0..NUM_PRESETS {
+            let tuning = get_preset(preset);
+            assert!(floats_equal(tuning.get_frequency(69).unwrap(), 440.0));
+        }
+
+        let mut selector = Selector::new();
+        selector.set_param(choice_to_param(4, NUM_CHOICES));
+        assert_eq!(selector.get_name(), "19-TET");
+
+        // There's no file to choose, so the preset stays.
+        selector.set_param(1.0);
+        assert_eq!(selector.get_name(), "19-TET");
+    }
+
+    #[test]
+    fn test_retune_until_switched()
+    {
+        let mut selector = Selector::new();
+
+        let payload = [0x7f, 0x00, 0x08, 0x02, 0x00, 0x01, 0x45, 0x45, 0x40, 0x00];
+        selector.retune(::mts::parse(&payload).unwrap());
+        assert!(!floats_equal(selector.get_tuning().get_frequency(69).unwrap(), 440.0));
+
+        // Coming back to a preset finds it as it was built.
+        selector.set_param(choice_to_param(1, NUM_CHOICES));
+        selector.set_param(choice_to_param(0, NUM_CHOICES));
+        assert!(floats_equal(selector.get_tuning().get_frequency(69).unwrap(), 440.0));
+    }
+}
@@ -0,0 +1,227 @@
+use generator::{Generator, Oscillator, StereoGenerator};
+use pan;
+use random::Random;
+
+pub const MAX_VOICES: usize = 16;
+
+/// Unison
+///
+/// A stack of detuned oscillators spread across the stereo field. The voices
+/// are detuned and panned symmetrically about the centre, with the outermost
+/// voices detuned by `detune` cents and panned by `spread`.
+pub struct Unison {
+    oscillators:  Vec<Oscillator>,
+    gains:        Vec<(f32, f32)>,
+    num_voices:   usize,
+    frequency:    f32,
+    detune:       f32,
+    spread:       f32,
+    random_phase: bool,
+    random:       Random,
+}
+
+impl Generator for Unison {
+    /// Returns the voices mixed down to mono, ignoring the stereo spread.
+    fn next_sample(&mut self) -> f32 {
+        let sum: f32 = self.oscillators[..self.num_voices]
+                           .iter_mut()
+                           .map(|oscillator| oscillator.next_sample())
+                           .sum();
+
+        sum / (self.num_voices as f32).sqrt()
+    }
+}
+
+impl StereoGenerator for Unison {
+    fn next_stereo_sample(&mut self) -> (f32, f32) {
+        let mut left  = 0.0;
+        let mut right = 0.0;
+
+        for (oscillator, &(left_gain, right_gain))
+            in self.oscillators[..self.num_voices].iter_mut().zip(self.gains.iter()) {
+            let value = oscillator.next_sample();
+            left  += value * left_gain;
+            right += value * right_gain;
+        }
+
+        // Scale so that the total power doesn't depend on the number of
+        // voices, and a single centred voice comes out at unity gain in
+        // each channel.
+        let scale = (2.0 / self.num_voices as f32).sqrt();
+
+        (left * scale, right * scale)
+    }
+}
+
+impl Unison {
+    pub fn sine(sample_rate: f32) -> Unison {
+        let mut unison = Unison {
+            oscillators:  (0..MAX_VOICES).map(|_| Oscillator::sine(sample_rate))
+                                         .collect(),
+            gains:        vec![(0.0, 0.0); MAX_VOICES],
+            num_voices:   1,
+            frequency:    440.0,
+            detune:       0.0,
+            spread:       0.0,
+            random_phase: true,
+            random:       Random::default(),
+        };
+        unison.update_voices();
+        unison
+    }
+
+    /// Returns the position of a voice relative to the centre of the stack,
+    /// in the range -1 to 1.
+    fn get_voice_offset(&self, voice: usize) -> f32 {
+        if self.num_voices == 1 {
+            0.0
+        }
+        else {
+            voice as f32 * 2.0 / (self.num_voices - 1) as f32 - 1.0
+        }
+    }
+
+    fn update_voices(&mut self) {
+        for voice in 0..self.num_voices {
+            let offset = self.get_voice_offset(voice);
+            let ratio  = (offset * self.detune / 1200.0).exp2();
+
+            self.oscillators[voice].set_frequency(self.frequency * ratio);
+            self.gains[voice] = pan::equal_power(0.5 + offset * self.spread / 2.0);
+        }
+    }
+
+    pub fn get_num_voices(&self) -> usize {
+        self.num_voices
+    }
+
+    pub fn set_num_voices(&mut self, num_voices: usize) {
+        self.num_voices = num_voices.clamp(1, MAX_VOICES);
+        self.update_voices();
+    }
+
+    pub fn get_frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.update_voices();
+    }
+
+    /// The detune is the distance, in cents, between the centre of the stack
+    /// and each of the outermost voices.
+    pub fn get_detune(&self) -> f32 {
+        self.detune
+    }
+
+    pub fn set_detune(&mut self, detune: f32) {
+        self.detune = detune;
+        self.update_voices();
+    }
+
+    /// The spread is how far the outermost voices are panned from the centre,
+    /// from 0 (all voices centred) to 1 (hard left and right).
+    pub fn get_spread(&self) -> f32 {
+        self.spread
+    }
+
+    pub fn set_spread(&mut self, spread: f32) {
+        self.spread = spread.clamp(0.0, 1.0);
+        self.update_voices();
+    }
+
+    /// If set, `reset` starts each voice at a random phase, which avoids the
+    /// voices all lining up into a loud peak on every note. A single voice
+    /// always starts at zero.
+    pub fn set_random_phase(&mut self, random_phase: bool) {
+        self.random_phase = random_phase;
+    }
+
+    pub fn get_sample_rate(&self) -> f32 {
+        self.oscillators[0].get_sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        for oscillator in self.oscillators.iter_mut() {
+            oscillator.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for oscillator in self.oscillators.iter_mut() {
+            if self.random_phase && self.num_voices > 1 {
+                oscillator.set_phase(self.random.next_f32());
+            }
+            else {
+                oscillator.reset();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_single_voice()
+    {
+        let mut unison = Unison::sine(44100.0);
+        unison.set_random_phase(false);
+        unison.set_detune(50.0);
+        unison.set_spread(1.0);
+        unison.reset();
+
+        let mut sine = Oscillator::sine(44100.0);
+
+        for _ in 0..1000 {
+            let expected      = sine.next_sample();
+            let (left, right) = unison.next_stereo_sample();
+            assert!(floats_equal(left, expected));
+            assert!(floats_equal(right, expected));
+        }
+    }
+
+    #[test]
+    fn test_detune()
+    {
+        let mut unison = Unison::sine(44100.0);
+        unison.set_frequency(440.0);
+        unison.set_num_voices(3);
+        unison.set_detune(1200.0);
+
+        assert!(floats_equal(unison.oscillators[0].get_frequency(), 220.0));
+        assert!(floats_equal(unison.oscillators[1].get_frequency(), 440.0));
+        assert!(floats_equal(unison.oscillators[2].get_frequency(), 880.0));
+    }
+
+    #[test]
+    fn test_spread()
+    {
+        let mut unison = Unison::sine(44100.0);
+        unison.set_num_voices(2);
+        unison.set_spread(1.0);
+
+        assert!(floats_equal(unison.gains[0].0, 1.0));
+        assert!(floats_equal(unison.gains[0].1, 0.0));
+        assert!(floats_equal(unison.gains[1].0, 0.0));
+        assert!(floats_equal(unison.gains[1].1, 1.0));
+    }
+
+    #[test]
+    fn test_num_voices()
+    {
+        let mut unison = Unison::sine(44100.0);
+
+        unison.set_num_voices(0);
+        assert_eq!(unison.get_num_voices(), 1);
+
+        unison.set_num_voices(100);
+        assert_eq!(unison.get_num_voices(), MAX_VOICES);
+    }
+}
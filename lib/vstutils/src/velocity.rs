@@ -0,0 +1,282 @@
+use param::{choice_to_param, param_to_choice};
+
+const CONTROL_CHANGE: u8 = 0xb0;
+const NUM_CHANNELS: usize = 16;
+const MAX_VELOCITY: f32 = 127.0;
+
+// The controller which carries the low seven bits of the next note on's
+// velocity, as in the MIDI high resolution velocity prefix.
+const HIGH_RES_VELOCITY: u8 = 88;
+const MAX_HIGH_RES_VELOCITY: f32 = 16383.0;
+
+/// Shape
+///
+/// How the force of playing turns into level. Soft curves make quiet notes
+/// louder, and hard ones need more force. Fixed plays every note at full
+/// level, and custom follows the breakpoints.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shape {
+    Linear,
+    Soft,
+    Hard,
+    Fixed,
+    Custom,
+}
+
+pub const NUM_SHAPES: usize = 5;
+
+pub fn get_shape(param: f32) -> Shape {
+    match param_to_choice(param, NUM_SHAPES) {
+        0 => Shape::Linear,
+        1 => Shape::Soft,
+        2 => Shape::Hard,
+        3 => Shape::Fixed,
+        _ => Shape::Custom,
+    }
+}
+
+pub fn get_param(shape: Shape) -> f32 {
+    choice_to_param(shape as usize, NUM_SHAPES)
+}
+
+pub fn get_name(shape: Shape) -> String {
+    match shape {
+        Shape::Linear => "Linear".to_string(),
+        Shape::Soft   => "Soft"  .to_string(),
+        Shape::Hard   => "Hard"  .to_string(),
+        Shape::Fixed  => "Fixed" .to_string(),
+        Shape::Custom => "Custom".to_string(),
+    }
+}
+
+/// The custom curve's levels are set at evenly spaced velocities, from the
+/// softest to the hardest.
+pub const NUM_BREAKPOINTS: usize = 5;
+
+// The curve's own parameters: the shape, the sensitivity, then the level at
+// each breakpoint.
+const SHAPE: i32 = 0;
+const SENSITIVITY: i32 = 1;
+const FIRST_BREAKPOINT_PARAM: i32 = 2;
+
+pub const NUM_PARAMETERS: i32 = FIRST_BREAKPOINT_PARAM + NUM_BREAKPOINTS as i32;
+
+const LAST_BREAKPOINT_PARAM: i32 = NUM_PARAMETERS - 1;
+
+/// VelocityCurve
+///
+/// Turns note velocities into levels in the range 0-1. Notes preceded by a
+/// high resolution velocity prefix on their channel use its extra bits.
+///
+/// Sensitivity is how far the velocity reaches into the gain: at zero every
+/// note plays at full level, and at one a note's level is its velocity.
+pub struct VelocityCurve {
+    shape:       Shape,
+    sensitivity: f32,
+    breakpoints: [f32; NUM_BREAKPOINTS],
+    prefixes:    [Option<u8>; NUM_CHANNELS],
+}
+
+impl Default for VelocityCurve {
+    fn default() -> VelocityCurve {
+        let mut breakpoints = [0.0; NUM_BREAKPOINTS];
+
+        for (index, breakpoint) in breakpoints.iter_mut().enumerate() {
+            *breakpoint = index as f32 / (NUM_BREAKPOINTS - 1) as f32;
+        }
+
+        VelocityCurve {
+            shape:       Shape::Linear,
+            sensitivity: 1.0,
+            breakpoints,
+            prefixes:    [None; NUM_CHANNELS],
+        }
+    }
+}
+
+impl VelocityCurve {
+    pub fn new() -> VelocityCurve {
+        VelocityCurve::default()
+    }
+
+    pub fn get_shape(&self) -> Shape {
+        self.shape
+    }
+
+    pub fn set_shape(&mut self, shape: Shape) {
+        self.shape = shape;
+    }
+
+    pub fn get_sensitivity(&self) -> f32 {
+        self.sensitivity
+    }
+
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity.clamp(0.0, 1.0);
+    }
+
+    pub fn get_breakpoint(&self, index: usize) -> f32 {
+        self.breakpoints.get(index).copied().unwrap_or(0.0)
+    }
+
+    pub fn set_breakpoint(&mut self, index: usize, level: f32) {
+        if let Some(existing) = self.breakpoints.get_mut(index) {
+            *existing = level.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Picks up high resolution velocity prefixes from a MIDI message.
+    pub fn process_midi_event(&mut self, data: [u8; 3]) {
+        if data[0] & 0xf0 == CONTROL_CHANGE && data[1] == HIGH_RES_VELOCITY {
+            self.prefixes[(data[0] & 0x0f) as usize] = Some(data[2] & 0x7f);
+        }
+    }
+
+    /// Returns the curved velocity of a note on, in the range 0-1, using up
+    /// any prefix waiting on its channel.
+    pub fn note_on(&mut self, channel: u8, velocity: u8) -> f32 {
+        let velocity = velocity & 0x7f;
+        let prefix   = self.prefixes.get_mut(channel as usize).and_then(|prefix| prefix.take());
+
+        let value = match prefix {
+            Some(low) => f32::from(u16::from(velocity) << 7 | u16::from(low)) / MAX_HIGH_RES_VELOCITY,
+            None      => f32::from(velocity) / MAX_VELOCITY,
+        };
+
+        self.apply(value)
+    }
+
+    /// Applies the curve to a velocity in the range 0-1.
+    pub fn apply(&self, velocity: f32) -> f32 {
+        let velocity = velocity.clamp(0.0, 1.0);
+
+        match self.shape {
+            Shape::Linear => velocity,
+            Shape::Soft   => velocity.sqrt(),
+            Shape::Hard   => velocity * velocity,
+            Shape::Fixed  => 1.0,
+            Shape::Custom => {
+                let position = velocity * (NUM_BREAKPOINTS - 1) as f32;
+                let index    = (position.floor() as usize).min(NUM_BREAKPOINTS - 2);
+                let fraction = position - index as f32;
+
+                let lower = self.breakpoints[index];
+                let upper = self.breakpoints[index + 1];
+                lower + (upper - lower) * fraction
+            },
+        }
+    }
+
+    /// Returns the gain for a curved velocity, after the sensitivity.
+    pub fn get_gain(&self, velocity: f32) -> f32 {
+        1.0 - self.sensitivity * (1.0 - velocity)
+    }
+
+    /// Returns one of the curve's own parameters, numbered from 0 to
+    /// `NUM_PARAMETERS - 1`.
+    pub fn get_parameter(&self, index: i32) -> f32 {
+        match index {
+            SHAPE       => get_param(self.shape),
+            SENSITIVITY => self.sensitivity,
+            FIRST_BREAKPOINT_PARAM..=LAST_BREAKPOINT_PARAM =>
+                self.get_breakpoint((index - FIRST_BREAKPOINT_PARAM) as usize),
+            _ => 0.0,
+        }
+    }
+
+    pub fn set_parameter(&mut self, index: i32, value: f32) {
+        match index {
+            SHAPE       => self.set_shape(get_shape(value)),
+            SENSITIVITY => self.set_sensitivity(value),
+            FIRST_BREAKPOINT_PARAM..=LAST_BREAKPOINT_PARAM =>
+                self.set_breakpoint((index - FIRST_BREAKPOINT_PARAM) as usize, value),
+            _ => (),
+        }
+    }
+
+    pub fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            SHAPE       => "Vel Curve".to_string(),
+            SENSITIVITY => "Vel Sensitivity".to_string(),
+            FIRST_BREAKPOINT_PARAM..=LAST_BREAKPOINT_PARAM =>
+                format!("Vel Point {}", index - FIRST_BREAKPOINT_PARAM + 1),
+            _ => "".to_string(),
+        }
+    }
+
+    pub fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            SHAPE => get_name(self.shape),
+            SENSITIVITY | FIRST_BREAKPOINT_PARAM..=LAST_BREAKPOINT_PARAM =>
+                format!("{:.0}", self.get_parameter(index) * 100.0),
+            _ => "".to_string(),
+        }
+    }
+
+    pub fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            SENSITIVITY | FIRST_BREAKPOINT_PARAM..=LAST_BREAKPOINT_PARAM => "%".to_string(),
+            _ => "".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn floats_equal(first: f32, second: f32) -> bool {
+        (first - second).abs() < 0.0001
+    }
+
+    #[test]
+    fn test_shapes()
+    {
+        let mut curve = VelocityCurve::new();
+        assert!(floats_equal(curve.apply(0.25), 0.25));
+
+        curve.set_shape(Shape::Soft);
+        assert!(floats_equal(curve.apply(0.25), 0.5));
+
+        curve.set_shape(Shape::Hard);
+        assert!(floats_equal(curve.apply(0.5), 0.25));
+
+        curve.set_shape(Shape::Fixed);
+        assert!(floats_equal(curve.apply(0.0), 1.0));
+
+        // Custom curves pass straight through their breakpoints, and join
+        // them with straight lines.
+        curve.set_shape(Shape::Custom);
+        curve.set_breakpoint(1, 0.5);
+        assert!(floats_equal(curve.apply(0.25), 0.5));
+        assert!(floats_equal(curve.apply(0.375), 0.5));
+        assert!(floats_equal(curve.apply(1.0), 1.0));
+    }
+
+    #[test]
+    fn test_sensitivity()
+    {
+        let mut curve = VelocityCurve::new();
+        assert!(floats_equal(curve.get_gain(0.25), 0.25));
+
+        curve.set_parameter(SENSITIVITY, 0.5);
+        assert!(floats_equal(curve.get_gain(0.0), 0.5));
+        assert!(floats_equal(curve.get_gain(1.0), 1.0));
+
+        curve.set_sensitivity(0.0);
+        assert!(floats_equal(curve.get_gain(0.0), 1.0));
+    }
+
+    #[test]
+    fn test_high_resolution()
+    {
+        let mut curve = VelocityCurve::new();
+        assert!(floats_equal(curve.note_on(0, 127), 1.0));
+
+        // The prefix only applies to the next note on its own channel.
+        curve.process_midi_event([0xb2, 88, 0x7f]);
+        assert!(floats_equal(curve.note_on(0, 64), 64.0 / 127.0));
+        assert!(floats_equal(curve.note_on(2, 64), (64.0 * 128.0 + 127.0) / 16383.0));
+        assert!(floats_equal(curve.note_on(2, 64), 64.0 / 127.0));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+use notetracker::NoteTracker;
+
+/// Release
+///
+/// What becomes of a voice when its note ends. Either it's free, and
+/// should be released, or it goes back to a note which was still held when
+/// the voice was taken from it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Release {
+    Free(usize),
+    Resume(usize, u8),
+}
+
+/// VoiceAllocator
+///
+/// Shares a fixed number of voices between the notes a NoteTracker decides
+/// are playing. Once every voice is taken, a new note takes the voice of
+/// the oldest note, which the tracker holds on to and hands back a voice
+/// when one comes free. Free voices are reused in the order they were
+/// released, so that each release rings out as long as it can.
+pub struct VoiceAllocator {
+    tracker: NoteTracker,
+    notes:   Vec<Option<u8>>,
+    free:    VecDeque<usize>,
+}
+
+impl VoiceAllocator {
+    pub fn new(num_voices: usize, extra_notes_count: usize) -> VoiceAllocator {
+        VoiceAllocator {
+            tracker: NoteTracker::new(num_voices, extra_notes_count),
+            notes:   vec![None; num_voices],
+            free:    (0..num_voices).collect(),
+        }
+    }
+
+    pub fn get_num_voices(&self) -> usize {
+        self.notes.len()
+    }
+
+    /// Returns the note a voice is playing, if it's playing one.
+    pub fn get_note(&self, voice: usize) -> Option<u8> {
+        self.notes.get(voice).cloned().unwrap_or(None)
+    }
+
+    /// Returns the voice playing a note, if it has one.
+    pub fn get_voice(&self, note: u8) -> Option<usize> {
+        self.notes.iter().position(|&playing| playing == Some(note))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracker.is_empty()
+    }
+
+    /// Starts a note, and returns the voice which should play it. A note
+    /// which is already playing keeps its voice. Returns `None` if the
+    /// tracker has no room left for the note.
+    pub fn note_on(&mut self, note: u8) -> Option<usize> {
+        if let Some(voice) = self.get_voice(note) {
+            return Some(voice);
+        }
+
+        // A note which was waiting for a voice starts again as the newest.
+        self.tracker.note_off(note);
+        self.tracker.note_on(note);
+
+        if !self.tracker.is_playing(note) {
+            return None;
+        }
+
+        // The tracker sets the oldest note aside to make room, so its voice
+        // is the one to take.
+        let tracker = &self.tracker;
+        let voice   = self.notes
+                          .iter()
+                          .position(|&playing| playing.is_some_and(|old| !tracker.is_playing(old)))
+                          .or_else(|| self.free.pop_front())?;
+
+        self.notes[voice] = Some(note);
+        Some(voice)
+    }
+
+    /// Ends a note, and returns what becomes of its voice. Notes which were
+    /// waiting for a voice don't have one to give up.
+    pub fn note_off(&mut self, note: u8) -> Option<Release> {
+        self.tracker.note_off(note);
+
+        let voice   = self.get_voice(note)?;
+        let resumed = self.tracker
+                          .iter_playing_notes()
+                          .find(|&playing| self.get_voice(playing).is_none());
+
+        self.notes[voice] = resumed;
+
+        match resumed {
+            Some(resumed) => Some(Release::Resume(voice, resumed)),
+            None          => {
+                self.free.push_back(voice);
+                Some(Release::Free(voice))
+            },
+        }
+    }
+
+    /// Forgets every note, and frees every voice.
+    pub fn clear(&mut self) {
+        self.tracker.clear();
+        self.free.clear();
+
+        for (voice, note) in self.notes.iter_mut().enumerate() {
+            *note = None;
+            self.free.push_back(voice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocate()
+    {
+        let mut allocator = VoiceAllocator::new(2, 2);
+
+        assert_eq!(allocator.note_on(60), Some(0));
+        assert_eq!(allocator.note_on(64), Some(1));
+        assert_eq!(allocator.note_on(60), Some(0));
+        assert_eq!(allocator.get_note(1), Some(64));
+
+        // The voice released first is the first to be reused.
+        assert_eq!(allocator.note_off(64), Some(Release::Free(1)));
+        assert_eq!(allocator.note_off(60), Some(Release::Free(0)));
+        assert!(allocator.is_empty());
+        assert_eq!(allocator.note_on(67), Some(1));
+        assert_eq!(allocator.note_on(72), Some(0));
+
+        allocator.clear();
+        assert!(allocator.is_empty());
+        assert_eq!(allocator.get_voice(67), None);
+        assert_eq!(allocator.note_on(60), Some(0));
+    }
+
+    #[test]
+    fn test_steal()
+    {
+        let mut allocator = VoiceAllocator::new(2, 1);
+
+        allocator.note_on(60);
+        allocator.note_on(64);
+
+        // The oldest note gives up its voice, and gets it back when the
+        // note which took it ends.
+        assert_eq!(allocator.note_on(67), Some(0));
+        assert_eq!(allocator.get_voice(60), None);
+        assert_eq!(allocator.note_off(67), Some(Release::Resume(0, 60)));
+        assert_eq!(allocator.get_voice(60), Some(0));
+
+        // Notes waiting for a voice have none to give up, and once there's
+        // no room left to wait, new notes are dropped.
+        allocator.note_on(67);
+        assert_eq!(allocator.note_off(60), None);
+        allocator.note_on(72);
+        assert_eq!(allocator.note_on(74), None);
+        assert_eq!(allocator.get_voice(64), None);
+        assert_eq!(allocator.get_voice(67), Some(0));
+        assert_eq!(allocator.get_voice(72), Some(1));
+    }
+}